@@ -35,6 +35,14 @@ namespace SimConnector
             }
         }
 
+        // the PMDG CDU screen: 24 columns of 14 cells of symbol, colour and flags, then the power flag
+        [StructLayout(LayoutKind.Sequential, Pack = 1)]
+        public struct ClientDataArea
+        {
+            [MarshalAs(UnmanagedType.ByValArray, SizeConst = 1009)]
+            public byte[] data;
+        }
+
         public struct ResponseString
         {
             [MarshalAs(UnmanagedType.ByValTStr, SizeConst = 1024)]
@@ -1,5 +1,8 @@
-﻿using System.Net.WebSockets;
+﻿using System.Globalization;
+using System.Net.WebSockets;
 using System.Text;
+using Newtonsoft.Json;
+using Newtonsoft.Json.Linq;
 
 
 namespace SimConnector
@@ -8,6 +11,7 @@ namespace SimConnector
     {
         WsClient client;
         const string _ws_addr = "ws://localhost:5273/ws";
+        public const int ProtocolVersion = 3;
         public SocketCom()
         {
             WasmConnect wasm = new WasmConnect();
@@ -23,7 +27,7 @@ namespace SimConnector
             Thread.Sleep(50);
             if (client.WSConnected)
             {
-                await client.SendMessageAsync("ConnectWSClient");
+                await client.SendJsonAsync(new JObject { ["type"] = "Hello", ["version"] = ProtocolVersion });
             }else
             {
                 SimLogger.Log("Can't connect to client...");
@@ -42,6 +46,15 @@ namespace SimConnector
         private CancellationTokenSource CTS;
         public WasmConnect wasm;
         public bool WSConnected = true;
+        // replies and pushed values share the send buffer
+        private readonly SemaphoreSlim sendLock = new SemaphoreSlim(1, 1);
+        // vars the rust app subscribed to and the values it got last
+        private readonly List<string> subscribedVars = new List<string>();
+        private readonly Dictionary<string, string> sentValues = new Dictionary<string, string>();
+        private bool pushing = false;
+        const int PushInterval = 100;
+        // names of client data areas (aircraft SDKs) start with this, the rest are simvars
+        const string ClientDataPrefix = "CD:";
 
         public WsClient(WasmConnect wasm)
         {
@@ -110,6 +123,19 @@ namespace SimConnector
         }
 
         public async Task SendMessageAsync(string message)
+        {
+            await sendLock.WaitAsync();
+            try
+            {
+                await SendSegmentsAsync(message);
+            }
+            finally
+            {
+                sendLock.Release();
+            }
+        }
+
+        private async Task SendSegmentsAsync(string message)
         {
             var messageLength = message.Length;
             var messageCount = (int)Math.Ceiling((double)messageLength / bufferSize);
@@ -126,10 +152,179 @@ namespace SimConnector
             }
         }
 
+        public Task SendJsonAsync(JObject message)
+        {
+            return SendMessageAsync(message.ToString(Formatting.None));
+        }
+
+        // replies carry the id of the request, so the rust app can hand it to the right caller
+        private Task ReplyAsync(JObject request, JObject reply)
+        {
+            if (request["id"] != null)
+            {
+                reply["id"] = request["id"];
+            }
+            return SendJsonAsync(reply);
+        }
+
         private void ResponseReceived(Stream inputStream)
         {
             StreamReader reader = new StreamReader(inputStream);
             string text = reader.ReadToEnd();
+            lock (wasm.SimLock)
+            {
+                if (text.StartsWith("{"))
+                {
+                    JsonReceived(text);
+                }
+                else
+                {
+                    LegacyReceived(text);
+                }
+            }
+        }
+
+        private void JsonReceived(string text)
+        {
+            // every frame is a json object with a "type" field, see bridge_protocol.rs in the rust app
+            JObject message;
+            try
+            {
+                message = JObject.Parse(text);
+            }
+            catch (JsonReaderException)
+            {
+                SimLogger.Log($"Invalid json message: {text}");
+                return;
+            }
+            string type = (string?)message["type"] ?? "";
+            switch (type)
+            {
+                case "Welcome":
+                    SimLogger.Log($"RUST APP CONNECTED, protocol version: {(int?)message["version"]}");
+                    wasm.RefreshLVarsList();
+                    this.Connected = true;
+                    break;
+                case "Close":
+                    wasm.Disconnect();
+                    SimLogger.Log("TERMINATING SIMCONNECTOR");
+                    Environment.Exit(0);
+                    break;
+                case "Status":
+                    this.ReplyAsync(message, new JObject { ["type"] = "Status", ["connected"] = wasm.WasmConnected });
+                    break;
+                case "Reconnect":
+                    string result = "CONNECTED";
+                    if (!wasm.WasmConnected)
+                    {
+                        wasm.Connect();
+                        result = "OK";
+                    }
+                    this.ReplyAsync(message, new JObject { ["type"] = "Reconnect", ["result"] = result });
+                    break;
+                case "ButtonPress":
+                    string lvar = (string?)message["lvar"] ?? "";
+                    SimLogger.Log($"LVAR BTN PRESS: {lvar}");
+                    wasm.ButtonPressL(lvar);
+                    break;
+                case "CustomWasm":
+                    string code = (string?)message["code"] ?? "";
+                    SimLogger.Log($"Sending custom WASM: {code}");
+                    wasm.CustomWasm(code);
+                    break;
+                case "GetAircraft":
+                    this.ReplyAsync(message, new JObject { ["type"] = "Aircraft", ["file"] = wasm.AircraftFile.ToUpper() });
+                    break;
+                case "GetVar":
+                    string var_name = (string?)message["name"] ?? "";
+                    SimLogger.Log("Getting variable:" + var_name);
+                    wasm.GetSimVar(var_name, out string stringVal, out double floatVal);
+                    SimLogger.Log("VAR stringval: " + stringVal);
+                    this.ReplyAsync(message, new JObject { ["type"] = "Var", ["value"] = stringVal });
+                    break;
+                case "VarList":
+                    this.ReplyAsync(message, new JObject { ["type"] = "Vars", ["list"] = wasm.GetSimVarsJson() });
+                    break;
+                case "SubscribeVars":
+                    List<string> names = message["names"]?.ToObject<List<string>>() ?? new List<string>();
+                    SimLogger.Log($"Subscribing to vars: {string.Join(", ", names)}");
+                    SubscribeVars(names);
+                    break;
+            }
+            SimLogger.Log($"GOT RESP:{text}");
+        }
+
+        // the list replaces the previous one, the values are pushed when they change
+        private void SubscribeVars(List<string> names)
+        {
+            bool start = false;
+            lock (subscribedVars)
+            {
+                subscribedVars.Clear();
+                subscribedVars.AddRange(names);
+                sentValues.Clear();
+                if (!pushing && names.Count > 0)
+                {
+                    pushing = true;
+                    start = true;
+                }
+            }
+            // the values only change as often as simconnect messages are read
+            wasm.MessageInterval = names.Count > 0 ? PushInterval / 2 : WasmConnect.IdleMessageInterval;
+            if (start)
+            {
+                Task.Run(PushLoop);
+            }
+        }
+
+        private async Task PushLoop()
+        {
+            while (WS != null && WS.State == WebSocketState.Open)
+            {
+                JObject values = new JObject();
+                // in the order of the receive loop, which subscribes with SimLock held
+                lock (wasm.SimLock)
+                lock (subscribedVars)
+                {
+                    if (subscribedVars.Count == 0)
+                    {
+                        pushing = false;
+                        return;
+                    }
+                    foreach (string name in subscribedVars)
+                    {
+                        string value = ReadVar(name);
+                        if (!sentValues.TryGetValue(name, out string? sent) || sent != value)
+                        {
+                            sentValues[name] = value;
+                            values[name] = value;
+                        }
+                    }
+                }
+                if (values.Count > 0)
+                {
+                    await SendJsonAsync(new JObject { ["type"] = "VarValues", ["values"] = values });
+                }
+                await Task.Delay(PushInterval);
+            }
+            lock (subscribedVars)
+            {
+                pushing = false;
+            }
+        }
+
+        private string ReadVar(string name)
+        {
+            if (name.StartsWith(ClientDataPrefix))
+            {
+                return wasm.GetClientDataHex(name.Substring(ClientDataPrefix.Length));
+            }
+            FSUIPCOffsetType type = wasm.GetSimVar(name, out string stringVal, out double floatVal);
+            return type == FSUIPCOffsetType.String ? stringVal : floatVal.ToString(CultureInfo.InvariantCulture);
+        }
+
+        private void LegacyReceived(string text)
+        {
             // protocol examples: 
             // CONNECTED => rust app sent back the comfirmation after first request
             // CMD_BTN:EXAMPLE_LVAR => pressing and releasing EXAMPLE_LVAR lvar
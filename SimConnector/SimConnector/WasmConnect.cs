@@ -56,8 +56,12 @@ namespace SimConnector
             int count = 0;
             while (true && ParentWatcher.ParentRunning())
             {
-                this.ReceiveSimConnectMessage();
-                Thread.Sleep(1000);
+                // the callbacks fill the var lists while ReceiveMessage runs
+                lock (SimLock)
+                {
+                    this.ReceiveSimConnectMessage();
+                }
+                Thread.Sleep(MessageInterval);
             }
         }
 
@@ -70,6 +74,10 @@ namespace SimConnector
 
         private uint MaxClientDataDefinition = 0;
 
+        // how often simconnect messages are read, faster while the rust app subscribes to vars
+        public const int IdleMessageInterval = 1000;
+        public int MessageInterval = IdleMessageInterval;
+
         private const string STANDARD_EVENT_GROUP = "STANDARD";
 
         private WasmModuleClientData WasmInitClientData;
@@ -89,6 +97,17 @@ namespace SimConnector
         // This must not exceed SIMCONNECT_CLIENTDATA_MAX_SIZE!
         private const int MOBIFLIGHT_STRINGVAR_DATAAREA_SIZE = MOBIFLIGHT_STRINGVAR_SIZE * MOBIFLIGHT_STRINGVAR_MAX_AMOUNT;
 
+        /// Client data areas of aircraft SDKs (PMDG CDU screens), requested by name
+        private const int CLIENT_DATA_AREA_ID_OFFSET = 20000;
+        // size of ClientDataArea
+        private const int CLIENT_DATA_AREA_SIZE = 1009;
+        private Dictionary<String, uint> ClientDataAreas = new Dictionary<String, uint>();
+        private Dictionary<uint, byte[]> ClientDataValues = new Dictionary<uint, byte[]>();
+
+        // SimConnect and the var lists aren't thread safe: the message loop, the websocket receive
+        // loop and the push loop of subscribed vars take turns with this
+        public readonly object SimLock = new object();
+
         /// User-defined win32 event
         public const int WM_USER_SIMCONNECT = 0x0402;
 
@@ -384,6 +403,12 @@ namespace SimConnector
                     }
 
 
+                }
+                // client data area of an aircraft SDK
+                else if (data.dwRequestID >= CLIENT_DATA_AREA_ID_OFFSET)
+                {
+                    var areaData = (ClientDataArea)(data.dwData[0]);
+                    ClientDataValues[data.dwRequestID] = areaData.data;
                 }
                 // SimVar value callback
                 else if (data.dwRequestID > MOBIFLIGHT_STRINGVAR_ID_OFFSET) // -> is string SimVar
@@ -440,6 +465,8 @@ namespace SimConnector
         public bool Disconnect()
         {
             ClearSimVars();
+            ClientDataAreas.Clear();
+            ClientDataValues.Clear();
             MaxClientDataDefinition = 0;
 
             if (m_oSimConnect != null)
@@ -602,6 +629,35 @@ namespace SimConnector
             return simVarType;
         }
 
+        // The area is requested the first time it's read, like simvars. Empty until the aircraft
+        // writes it (PMDG only does with EnableCDUBroadcast in its options)
+        public String GetClientDataHex(String areaName)
+        {
+            if (!IsSimConnectConnected())
+                return "";
+
+            if (!ClientDataAreas.TryGetValue(areaName, out uint id))
+            {
+                id = (uint)(CLIENT_DATA_AREA_ID_OFFSET + ClientDataAreas.Count);
+                ClientDataAreas[areaName] = id;
+                m_oSimConnect?.MapClientDataNameToID(areaName, (SIMCONNECT_CLIENT_DATA_ID)id);
+                m_oSimConnect?.AddToClientDataDefinition((SIMCONNECT_DEFINE_ID)id, 0, CLIENT_DATA_AREA_SIZE, 0, 0);
+                m_oSimConnect?.RegisterStruct<SIMCONNECT_RECV_CLIENT_DATA, ClientDataArea>((SIMCONNECT_DEFINE_ID)id);
+                m_oSimConnect?.RequestClientData(
+                    (SIMCONNECT_CLIENT_DATA_ID)id,
+                    (SIMCONNECT_REQUEST_ID)id,
+                    (SIMCONNECT_DEFINE_ID)id,
+                    SIMCONNECT_CLIENT_DATA_PERIOD.ON_SET,
+                    SIMCONNECT_CLIENT_DATA_REQUEST_FLAG.CHANGED,
+                    0,
+                    0,
+                    0
+                );
+            }
+
+            return ClientDataValues.TryGetValue(id, out byte[]? data) ? Convert.ToHexString(data) : "";
+        }
+
         private FSUIPCOffsetType RegisterSimVar(string SimVarName)
         {
             // Matches presets like "(A:TITLE,String)" in different variations. These will most likely be of type String an will therefore be treated as String.
@@ -80,14 +80,16 @@ export async function setFMS(hwnd) {
         `/set_hwnd?hwnd=${hwnd}`)).text()
 }
 
-export async function sendTouchEvent(xPos, yPos) {
+// x and y from 0 to 1 across the picture
+export async function sendTouchEvent(x, y) {
     return (await fetch(getServerAddr() +
-        `/touch_event?x_pos=${xPos}&y_pos=${yPos}`)).text()
+        `/touch_event?x=${x}&y=${y}`)).text()
 }
 
 export async function btnEvent(btn_name) {
+    let fo_toggle = document.getElementById("foTogBtn")
     return (await fetch(getServerAddr() +
-        `/mcdu_btn_press?btn=${btn_name}`)).text()
+        `/mcdu_btn_press?btn=${btn_name}` + (fo_toggle ? `&usefo=${fo_toggle.checked}` : ""))).text()
 }
 
 function getServerAddr() {
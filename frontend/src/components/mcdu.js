@@ -221,13 +221,11 @@ export function McduComponent(props) {
         }
 
         var rect = document.getElementById("streamImage").getBoundingClientRect();
-        var x = Math.round(e.clientX - rect.left); //x position within the element.
-        var y = Math.round(e.clientY - rect.top);  //y position within the element.
-        if (x >= 0 && y >= 0 && x <= rect.width && y <= rect.height) {
-
-            var current_scale = Number(document.getElementById("mcduSvg").style.scale);
-            sendTouchEvent(Math.round(x / current_scale),
-                Math.round(y / current_scale))
+        // position within the element, from 0 to 1: the scale of the page doesn't matter
+        var x = (e.clientX - rect.left) / rect.width;
+        var y = (e.clientY - rect.top) / rect.height;
+        if (x >= 0 && y >= 0 && x <= 1 && y <= 1) {
+            sendTouchEvent(x, y)
         }
     }
 
@@ -0,0 +1,67 @@
+// Encode time and size of a CDU-like frame in every format: cargo bench --bench frame_encoder
+
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use frame_encoder::{EncoderSettings, FrameFormat};
+
+const WIDTH: u32 = 700;
+const HEIGHT: u32 = 560;
+const ITERATIONS: u32 = 30;
+
+// black screen with lines of "text" blocks in the usual CDU colors
+fn cdu_frame() -> Vec<u8> {
+    let colors: [[u8; 4]; 4] = [[255, 255, 255, 255], [0, 255, 0, 255], [0, 255, 255, 255], [255, 160, 0, 255]];
+    let mut pixels = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+    let mut seed: u32 = 12345;
+    for line in 0..14 {
+        for column in 0..24 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            if seed % 3 == 0 {
+                continue;
+            }
+            let color = colors[(seed >> 8) as usize % colors.len()];
+            let left = 10 + column * 28;
+            let top = 10 + line * 39;
+            for y in top + 6..top + 30 {
+                for x in left + 4..left + 22 {
+                    // a rough glyph, not a filled box
+                    if (x + y + seed) % 5 < 2 {
+                        continue;
+                    }
+                    let i = ((y * WIDTH + x) * 4) as usize;
+                    pixels[i..i + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+    pixels
+}
+
+fn main() {
+    let pixels = cdu_frame();
+    let formats = [
+        EncoderSettings { format: FrameFormat::Png, quality: 0 },
+        EncoderSettings { format: FrameFormat::Qoi, quality: 0 },
+        EncoderSettings { format: FrameFormat::Webp, quality: 0 },
+        EncoderSettings { format: FrameFormat::Jpeg, quality: 90 },
+        EncoderSettings { format: FrameFormat::Jpeg, quality: 60 },
+        EncoderSettings { format: FrameFormat::Jpeg, quality: 30 },
+    ];
+    println!("{}x{} frame, {} iterations", WIDTH, HEIGHT, ITERATIONS);
+    for settings in formats {
+        let mut size = 0;
+        let mut total = Duration::ZERO;
+        for _ in 0..ITERATIONS {
+            let started = Instant::now();
+            let encoded = frame_encoder::encode(black_box(&pixels), WIDTH, HEIGHT, settings).unwrap();
+            total += started.elapsed();
+            size = encoded.len();
+        }
+        println!("{:<6} q{:<3} {:>8.2} ms {:>8} bytes", format!("{:?}", settings.format), settings.quality,
+                 total.as_secs_f64() * 1000.0 / ITERATIONS as f64, size);
+    }
+}
@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use crate::config_handler::{get_addon_config, get_file_in_exe_folder, get_static_folder};
 use crate::debug_logger;
 use crate::debug_logger::{show_fatal_error, show_warning_dialog};
+use crate::output_vars::{PageFormat, VarSource};
 
 #[derive(Serialize, Deserialize)]
 #[derive(Clone)]
@@ -20,8 +21,22 @@ pub struct AircraftAddon {
     title: String,
     display: String,
     button_actions: Vec<ButtonAction>,
+    // first officer's MCDU, empty if the aircraft has only one
+    #[serde(default)]
+    fo_button_actions: Vec<ButtonAction>,
+    // third MCDU (center pedestal) where the aircraft has one
+    #[serde(default)]
+    third_button_actions: Vec<ButtonAction>,
     svg_image: String,
     output_vars: Vec<String>,
+    // font template for reading the FMS pages as text (static/fonts/<fms_font>.json, see fms_ocr),
+    // empty if there is none for the aircraft
+    #[serde(default)]
+    fms_font: String,
+    // how output_vars make the CDU screen where the aircraft publishes it ("rows", "pmdg_cdu",
+    // see output_vars), empty if it doesn't
+    #[serde(default)]
+    fms_vars: String,
     // aspect: width/height
     fms_aspect: f64,
     display_width: u16,
@@ -31,6 +46,38 @@ pub struct AircraftAddon {
     touch_enabled: bool,
     last_updated: String,
 }
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum McduSide {
+    Captain,
+    FirstOfficer,
+    Third,
+}
+
+impl McduSide {
+    pub fn from_name(name: &str) -> Option<McduSide> {
+        return match name.to_lowercase().as_str() {
+            "fo" | "2" => Some(McduSide::FirstOfficer),
+            "third" | "3" => Some(McduSide::Third),
+            "capt" | "1" => Some(McduSide::Captain),
+            _ => None
+        };
+    }
+
+    // usefo=true comes from the side toggle of the web ui, side=fo/3 can select any of them
+    pub fn from_query(usefo: &str, side: &str) -> McduSide {
+        return match McduSide::from_name(side) {
+            Some(side) => side,
+            None => {
+                if usefo == "true" {
+                    McduSide::FirstOfficer
+                } else {
+                    McduSide::Captain
+                }
+            }
+        };
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[derive(Clone)]
 pub struct AddonConfig {
@@ -54,6 +101,7 @@ impl AddonConfig {
                         if res.version > stored.version && res.app_version <= get_app_version() {
                             Self::write_config(&res);
                             Self::download_svgs(&res).await;
+                            Self::download_fonts(&res).await;
                             res.log_str = log_str;
                             return res;
                         }
@@ -67,6 +115,7 @@ impl AddonConfig {
                         println!("error while opening: {}", err);
                         Self::write_config(&res);
                         Self::download_svgs(&res).await;
+                        Self::download_fonts(&res).await;
                         res
                     }
                 }
@@ -122,6 +171,45 @@ impl AddonConfig {
         }
     }
 
+    async fn download_fonts(&self) {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let fonts_folder = get_file_in_exe_folder(vec!["static", "fonts"]);
+        if !std::path::Path::new(&fonts_folder).exists() && fs::create_dir(&fonts_folder).is_err() {
+            debug_logger::log("Cant create fonts folder", &self.log_str);
+            return;
+        }
+        let mut downloaded_fonts: Vec<&str> = vec![];
+        for addon in &self.aircraft_addons {
+            if addon.fms_font.is_empty() || downloaded_fonts.contains(&&*addon.fms_font) {
+                continue;
+            }
+            downloaded_fonts.push(&*addon.fms_font);
+            let font_file = format!("{}.json", &addon.fms_font);
+            debug_logger::log(&*format!("Downloading FMS font: {}", &font_file), &self.log_str);
+            let resp = client.get(SERVER_BASE_ADDR.to_string() + "fonts/" + &font_file).send().await;
+            let txt = match resp {
+                Ok(resp) if resp.status().is_success() => resp.text().await,
+                Ok(resp) => {
+                    debug_logger::log(&*format!("error downloading FMS font: {}", resp.status()), &self.log_str);
+                    continue;
+                }
+                Err(er) => Err(er)
+            };
+            match txt {
+                Ok(txt) => {
+                    let filename = get_file_in_exe_folder(vec!["static", "fonts", &font_file]);
+                    if fs::write(&filename, txt).is_err() {
+                        debug_logger::log("Unable to write FMS font", &self.log_str);
+                    }
+                }
+                Err(er) => debug_logger::log(&*format!("error downloading FMS font: {}", &er), &self.log_str)
+            }
+        }
+    }
+
     fn get_stored() -> Result<AddonConfig, bool> {
         if !std::path::Path::new(&get_static_folder()).exists() {
             fs::create_dir(&get_static_folder()).expect("Cant create static.");
@@ -193,7 +281,7 @@ impl AddonConfig {
         Option::None
     }
 
-    pub fn get_var(&self, btn: String, aircraft_filename: &String) -> &str {
+    pub fn get_var(&self, btn: String, aircraft_filename: &String, side: McduSide) -> &str {
         return match self.get_aircraft_config(&aircraft_filename) {
             None => {
                 debug_logger::log("Cant find aircraft config for this aircraft!", &self.log_str);
@@ -201,7 +289,18 @@ impl AddonConfig {
                 ""
             }
             Some(aircraft_addon) => {
-                for button_action in &aircraft_addon.button_actions {
+                let button_actions = match side {
+                    McduSide::Captain => &aircraft_addon.button_actions,
+                    McduSide::FirstOfficer => &aircraft_addon.fo_button_actions,
+                    McduSide::Third => &aircraft_addon.third_button_actions,
+                };
+                // aircraft with a single MCDU only have the captain's map
+                let button_actions = if button_actions.is_empty() {
+                    &aircraft_addon.button_actions
+                } else {
+                    button_actions
+                };
+                for button_action in button_actions {
                     if &button_action.button == &btn {
                         return &button_action.lvar;
                     }
@@ -212,6 +311,28 @@ impl AddonConfig {
         };
     }
 
+    // path of the FMS font template of the aircraft, None if it has none
+    pub fn get_fms_font(&self, aircraft_filename: &String) -> Option<String> {
+        return match self.get_aircraft_config(aircraft_filename) {
+            Some(aircraft_addon) if !aircraft_addon.fms_font.is_empty() => {
+                Some(get_file_in_exe_folder(vec!["static", "fonts", &format!("{}.json", &aircraft_addon.fms_font)]))
+            }
+            _ => None
+        };
+    }
+
+    // the vars the bridge pushes for the aircraft, None if they don't make its CDU screen
+    pub fn get_output_vars(&self, aircraft_filename: &String) -> Option<VarSource> {
+        let aircraft_addon = self.get_aircraft_config(aircraft_filename)?;
+        return match PageFormat::parse(&aircraft_addon.fms_vars) {
+            Some(format) if !aircraft_addon.output_vars.is_empty() => Some(VarSource {
+                vars: aircraft_addon.output_vars.clone(),
+                format,
+            }),
+            _ => None
+        };
+    }
+
     pub fn popout_list(&self) -> Vec<String> {
         let mut popout_list: Vec<String> = Vec::new();
         for aircraft_addon in &self.aircraft_addons {
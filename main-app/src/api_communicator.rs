@@ -1,11 +1,16 @@
+#[cfg(windows)]
 use std::os::windows::process::CommandExt;
 use std::process::{Child, Command};
 use crate::config_handler::{get_simconnector_exe, get_simconnector_folder, SIMCONNECTOR_RELATIVE_DIR};
 use crate::debug_logger::show_fatal_error;
 
+#[cfg(windows)]
 const DETACHED_PROCESS: u32 = 0x00000008;
 
-pub fn start_bridge_process() -> Child {
+pub fn start_bridge_process(bridge_path: &str, bridge_args: &Vec<String>) -> Child {
+    if !bridge_path.is_empty() {
+        return start_custom_bridge(bridge_path, bridge_args);
+    }
     if !std::path::Path::new(&get_simconnector_exe()).exists() {
         show_fatal_error("Can't find SimConnector.exe. Did you fully extract the the archive? Try reinstalling the app.");
     }
@@ -15,8 +20,25 @@ pub fn start_bridge_process() -> Child {
         child = Command::new(get_simconnector_exe()).current_dir(get_simconnector_folder())
             .spawn().expect("failed to execute exe");
     }else {
-        child = Command::new(get_simconnector_exe()).current_dir(get_simconnector_folder())
-            .creation_flags(DETACHED_PROCESS).arg("hide").spawn().expect("failed to execute exe");
+        let mut command = Command::new(get_simconnector_exe());
+        command.current_dir(get_simconnector_folder()).arg("hide");
+        #[cfg(windows)]
+        command.creation_flags(DETACHED_PROCESS);
+        child = command.spawn().expect("failed to execute exe");
     }
     child
 }
+
+
+// starts a bridge from the configured path instead of the bundled SimConnector, used with mock_bridge
+fn start_custom_bridge(bridge_path: &str, bridge_args: &Vec<String>) -> Child {
+    let path = std::path::Path::new(bridge_path);
+    if !path.exists() {
+        show_fatal_error(&*format!("Can't find the configured bridge: {}", bridge_path));
+    }
+    let mut command = Command::new(path);
+    if let Some(folder) = path.parent() {
+        command.current_dir(folder);
+    }
+    command.args(bridge_args).spawn().expect("failed to execute bridge")
+}
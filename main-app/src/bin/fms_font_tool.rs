@@ -0,0 +1,85 @@
+// Makes and checks the FMS font templates of fms_ocr (static/fonts/<name>.json) from screenshots of
+// the cropped CDU pop-out, the /image_state of the selected pop-out is one.
+//
+// usage: fms_font_tool learn <font.json> <screenshot.png> <page.txt> [--small <row,row,..>] [--area <x,y,w,h>]
+//        fms_font_tool read <font.json> <screenshot.png>
+//
+// learn adds the characters of the screenshot to the font (it's created if missing), page.txt has
+// the text of the screenshot, one line per CDU row. --small marks the rows in the small font
+// (0 based), --area sets the part of the screenshot the grid covers for a new font.
+
+#[path = "../fms_ocr.rs"]
+mod fms_ocr;
+
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+use fms_ocr::FmsFont;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        usage();
+    }
+    let (pixels, width, height) = match image::open(&args[3]) {
+        Ok(img) => {
+            let rgba = img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            (rgba.into_raw(), width, height)
+        }
+        Err(e) => fail(&format!("Cant open {}: {}", &args[3], e))
+    };
+
+    match args[1].as_str() {
+        "learn" => {
+            if args.len() < 5 {
+                usage();
+            }
+            let mut font = match Path::new(&args[2]).exists() {
+                true => FmsFont::load(&args[2]).unwrap_or_else(|e| fail(&e)),
+                false => FmsFont::new()
+            };
+            let mut small_rows: Vec<u32> = vec![];
+            let mut i = 5;
+            while i + 1 < args.len() {
+                match args[i].as_str() {
+                    "--small" => small_rows = args[i + 1].split(',').map(|row| row.trim().parse().unwrap_or_else(|_| fail("Invalid row"))).collect(),
+                    "--area" => {
+                        let area: Vec<f32> = args[i + 1].split(',').map(|v| v.trim().parse().unwrap_or_else(|_| fail("Invalid area"))).collect();
+                        if area.len() != 4 {
+                            fail("Invalid area");
+                        }
+                        font.area = [[area[0], area[1]], [area[2], area[3]]];
+                    }
+                    other => println!("unknown argument: {}", other)
+                }
+                i += 2;
+            }
+            let text = fs::read_to_string(&args[4]).unwrap_or_else(|e| fail(&format!("Cant read {}: {}", &args[4], e)));
+            let lines: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+            let learned = font.learn(&pixels, width, height, &lines, &small_rows);
+            fs::write(&args[2], font.to_json()).unwrap_or_else(|e| fail(&format!("Cant write {}: {}", &args[2], e)));
+            println!("learned {} characters, {} glyphs in the font", learned, font.glyphs.len());
+        }
+        "read" => {
+            let font = FmsFont::load(&args[2]).unwrap_or_else(|e| fail(&e));
+            let page = fms_ocr::read_page(&font, &pixels, width, height);
+            for line in &page.lines {
+                println!("|{}|", line);
+            }
+            println!("unknown cells: {}", page.unknown);
+        }
+        _ => usage()
+    }
+}
+
+fn usage() -> ! {
+    println!("usage: fms_font_tool learn <font.json> <screenshot.png> <page.txt> [--small <row,row,..>] [--area <x,y,w,h>]");
+    println!("       fms_font_tool read <font.json> <screenshot.png>");
+    exit(2);
+}
+
+fn fail(message: &str) -> ! {
+    println!("{}", message);
+    exit(1);
+}
@@ -0,0 +1,198 @@
+// Stand-in for SimConnector.exe. Connects to the app's /ws like the real bridge does,
+// answers queries from a scenario file and records every button press it gets. Subscribed vars
+// it knows from the scenario are pushed once, when the app subscribes.
+//
+// usage: mock_bridge <scenario.json> [--record <file>] [--url <ws url>]
+//
+// scenario example:
+// {
+//   "connected": true,
+//   "aircraft": "FNX320",
+//   "vars": {"L:S_FCU_EFIS1_BARO_STD": "1"},
+//   "var_list": "[]",
+//   "legacy": false
+// }
+
+#[path = "../bridge_protocol.rs"]
+#[allow(dead_code)]
+mod bridge_protocol;
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use serde::{Deserialize, Serialize};
+use tungstenite::{connect, Message};
+use bridge_protocol::{BridgeCommand, BridgeReply, BridgeRequest, BridgeResponse, LEGACY_CONNECT, PROTOCOL_VERSION};
+
+const DEFAULT_URL: &str = "ws://localhost:5273/ws";
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct Scenario {
+    #[serde(default)]
+    connected: bool,
+    #[serde(default)]
+    aircraft: String,
+    #[serde(default)]
+    vars: HashMap<String, String>,
+    #[serde(default)]
+    var_list: String,
+    // speak the old text protocol instead of json
+    #[serde(default)]
+    legacy: bool,
+}
+
+#[derive(Serialize)]
+struct RecordedCommand<'a> {
+    time: String,
+    #[serde(flatten)]
+    command: &'a BridgeCommand,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut scenario_file: Option<String> = None;
+    let mut record_file: Option<String> = None;
+    let mut url: String = DEFAULT_URL.to_string();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--record" if i + 1 < args.len() => {
+                record_file = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "--url" if i + 1 < args.len() => {
+                url = args[i + 1].clone();
+                i += 1;
+            }
+            // the app passes "hide" to the real bridge
+            "hide" => {}
+            other => { scenario_file = Some(other.to_string()) }
+        }
+        i += 1;
+    }
+
+    let scenario: Scenario = match &scenario_file {
+        None => Scenario::default(),
+        Some(path) => {
+            let string_data = fs::read_to_string(path).expect("Unable to read scenario file");
+            serde_json::from_str(&string_data).expect("Invalid scenario file")
+        }
+    };
+
+    let (mut socket, _) = connect(url.as_str()).expect("Can't connect to the app");
+    println!("mock bridge connected to {}", &url);
+
+    if scenario.legacy {
+        socket.send(Message::Text(LEGACY_CONNECT.to_string())).unwrap();
+    } else {
+        let hello = BridgeResponse { id: None, reply: BridgeReply::Hello { version: PROTOCOL_VERSION } };
+        socket.send(Message::Text(serde_json::to_string(&hello).unwrap())).unwrap();
+    }
+
+    loop {
+        let text = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => continue,
+        };
+        let request = match parse_request(&text) {
+            Some(request) => request,
+            None => {
+                println!("unknown message: {}", &text);
+                continue;
+            }
+        };
+        println!("got: {:?}", &request);
+
+        let reply: Option<BridgeReply> = match &request.command {
+            BridgeCommand::Welcome { .. } => None,
+            BridgeCommand::Close => break,
+            BridgeCommand::Status => Some(BridgeReply::Status { connected: scenario.connected }),
+            BridgeCommand::GetAircraft => Some(BridgeReply::Aircraft { file: scenario.aircraft.to_uppercase() }),
+            BridgeCommand::Reconnect => Some(BridgeReply::Reconnect { result: "CONNECTED".to_string() }),
+            BridgeCommand::VarList => Some(BridgeReply::Vars { list: scenario.var_list.clone() }),
+            BridgeCommand::GetVar { name } => Some(BridgeReply::Var {
+                value: scenario.vars.get(name).cloned().unwrap_or("".to_string())
+            }),
+            BridgeCommand::ButtonPress { .. } | BridgeCommand::CustomWasm { .. } => {
+                record(&record_file, &request.command);
+                None
+            }
+            BridgeCommand::SubscribeVars { names } => {
+                record(&record_file, &request.command);
+                let values: HashMap<String, String> = names.iter()
+                    .filter_map(|name| scenario.vars.get(name).map(|value| (name.clone(), value.clone())))
+                    .collect();
+                if values.is_empty() { None } else { Some(BridgeReply::VarValues { values }) }
+            }
+        };
+
+        let out = match reply {
+            None => None,
+            Some(reply) if scenario.legacy => legacy_reply(&reply),
+            Some(reply) => Some(serde_json::to_string(&BridgeResponse { id: request.id, reply }).unwrap()),
+        };
+        if let Some(out) = out {
+            socket.send(Message::Text(out)).unwrap();
+        }
+    }
+    println!("mock bridge exiting");
+}
+
+fn parse_request(text: &str) -> Option<BridgeRequest> {
+    if text.starts_with('{') {
+        return serde_json::from_str(text).ok();
+    }
+    let command = match text {
+        "CONNECTED" => BridgeCommand::Welcome { version: 0 },
+        "CLOSE" => BridgeCommand::Close,
+        "STATUS" => BridgeCommand::Status,
+        "GET_AIRCRAFT" => BridgeCommand::GetAircraft,
+        "RECONNECT" => BridgeCommand::Reconnect,
+        "VAR_LIST" => BridgeCommand::VarList,
+        _ => {
+            if let Some(name) = text.strip_prefix("GET_VAR:") {
+                BridgeCommand::GetVar { name: name.to_string() }
+            } else if let Some(lvar) = text.strip_prefix("CMD_BTN:") {
+                BridgeCommand::ButtonPress { lvar: lvar.to_string() }
+            } else if let Some(code) = text.strip_prefix("CUSTOM_WASM:") {
+                BridgeCommand::CustomWasm { code: code.to_string() }
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(BridgeRequest { id: None, command })
+}
+
+// None if the old protocol has nothing to send for it
+fn legacy_reply(reply: &BridgeReply) -> Option<String> {
+    let text = match reply {
+        BridgeReply::Hello { .. } => LEGACY_CONNECT.to_string(),
+        BridgeReply::Status { connected } => format!("STATUS:{}", connected.to_string().to_uppercase()),
+        BridgeReply::Aircraft { file } => format!("AIRCRAFT:{}", file),
+        BridgeReply::Reconnect { result } => format!("RECONNECT:{}", result),
+        BridgeReply::Var { value } => format!("VAR:{}", value),
+        BridgeReply::Vars { list } => format!("VARS:{}", list),
+        // legacy bridges don't get subscriptions
+        BridgeReply::VarValues { .. } => return None,
+    };
+    Some(text)
+}
+
+// appends the command as a json line, so tests can check what the app sent
+fn record(record_file: &Option<String>, command: &BridgeCommand) {
+    let line = serde_json::to_string(&RecordedCommand {
+        time: chrono::Utc::now().to_string(),
+        command,
+    }).unwrap();
+    println!("recorded: {}", &line);
+    if let Some(path) = record_file {
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path).expect("Can't open record file");
+        writeln!(f, "{}", line).unwrap();
+    }
+}
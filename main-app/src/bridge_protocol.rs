@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+
+// Messages between the app and SimConnector on /ws. Every frame is a json object with a
+// "type" field. The old text protocol (CONNECTED, STATUS:TRUE, VAR:...) is still accepted
+// from bridges that connect with "ConnectWSClient" instead of a Hello frame.
+//
+// Handshake:
+//  bridge -> app: {"type":"Hello","version":2}
+//  app -> bridge: {"type":"Welcome","version":2}
+//
+// Since version 2 queries carry an "id" that the bridge copies into its reply, so the answer
+// gets back to the request that asked for it: {"type":"GetVar","name":"L:X","id":7}
+// -> {"type":"Var","value":"1","id":7}. Fire and forget commands have no id.
+//
+// Since version 3 the app can subscribe to vars, the bridge pushes the ones that changed (no id)
+// until the next subscription replaces the list, an empty list ends it:
+// {"type":"SubscribeVars","names":["L:X"]} -> {"type":"VarValues","values":{"L:X":"1"}}
+
+pub const PROTOCOL_VERSION: u32 = 3;
+pub const VAR_SUBSCRIPTION_VERSION: u32 = 3;
+pub const LEGACY_CONNECT: &str = "ConnectWSClient";
+pub const LEGACY_CONNECTED: &str = "CONNECTED";
+
+// app -> SimConnector
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum BridgeCommand {
+    Welcome { version: u32 },
+    Close,
+    Status,
+    GetAircraft,
+    Reconnect,
+    VarList,
+    GetVar { name: String },
+    ButtonPress { lvar: String },
+    CustomWasm { code: String },
+    SubscribeVars { names: Vec<String> },
+}
+
+// SimConnector -> app
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum BridgeReply {
+    Hello { version: u32 },
+    Status { connected: bool },
+    Aircraft { file: String },
+    Reconnect { result: String },
+    Var { value: String },
+    Vars { list: String },
+    VarValues { values: HashMap<String, String> },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BridgeRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub command: BridgeCommand,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BridgeResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(flatten)]
+    pub reply: BridgeReply,
+}
+
+impl BridgeRequest {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl BridgeResponse {
+    pub fn parse(text: &str) -> Option<BridgeResponse> {
+        if text.starts_with('{') {
+            return serde_json::from_str(text).ok();
+        }
+        BridgeReply::parse_legacy(text).map(|reply| BridgeResponse { id: None, reply })
+    }
+}
+
+impl BridgeCommand {
+    // lvars are pressed with CMD_BTN, anything that looks like RPN or an event goes as custom wasm
+    pub fn for_button(var: &str) -> BridgeCommand {
+        if var.contains(">") || var.contains("K:") || var.contains("H:") {
+            BridgeCommand::CustomWasm { code: var.to_string() }
+        } else {
+            BridgeCommand::ButtonPress { lvar: var.to_string() }
+        }
+    }
+
+    // the reply type a query waits for, None for fire and forget commands
+    pub fn reply_kind(&self) -> Option<&'static str> {
+        match self {
+            BridgeCommand::Status => Some("Status"),
+            BridgeCommand::GetAircraft => Some("Aircraft"),
+            BridgeCommand::Reconnect => Some("Reconnect"),
+            BridgeCommand::VarList => Some("Vars"),
+            BridgeCommand::GetVar { .. } => Some("Var"),
+            _ => None
+        }
+    }
+
+    pub fn to_legacy(&self) -> String {
+        match self {
+            BridgeCommand::Welcome { .. } => LEGACY_CONNECTED.to_string(),
+            BridgeCommand::Close => "CLOSE".to_string(),
+            BridgeCommand::Status => "STATUS".to_string(),
+            BridgeCommand::GetAircraft => "GET_AIRCRAFT".to_string(),
+            BridgeCommand::Reconnect => "RECONNECT".to_string(),
+            BridgeCommand::VarList => "VAR_LIST".to_string(),
+            BridgeCommand::GetVar { name } => format!("GET_VAR:{}", name),
+            BridgeCommand::ButtonPress { lvar } => format!("CMD_BTN:{}", lvar),
+            BridgeCommand::CustomWasm { code } => format!("CUSTOM_WASM:{}", code),
+            // legacy bridges can't push values, the app doesn't subscribe on them
+            BridgeCommand::SubscribeVars { names } => format!("SUBSCRIBE_VARS:{}", names.join(";")),
+        }
+    }
+}
+
+impl BridgeReply {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BridgeReply::Hello { .. } => "Hello",
+            BridgeReply::Status { .. } => "Status",
+            BridgeReply::Aircraft { .. } => "Aircraft",
+            BridgeReply::Reconnect { .. } => "Reconnect",
+            BridgeReply::Var { .. } => "Var",
+            BridgeReply::Vars { .. } => "Vars",
+            BridgeReply::VarValues { .. } => "VarValues",
+        }
+    }
+
+    // only the prefix decides the reply type, so a value containing "VAR:" stays a value
+    pub fn parse_legacy(text: &str) -> Option<BridgeReply> {
+        if let Some(status) = text.strip_prefix("STATUS:") {
+            return Some(BridgeReply::Status { connected: status == "TRUE" });
+        }
+        if let Some(file) = text.strip_prefix("AIRCRAFT:") {
+            return Some(BridgeReply::Aircraft { file: file.to_string() });
+        }
+        if let Some(result) = text.strip_prefix("RECONNECT:") {
+            return Some(BridgeReply::Reconnect { result: result.to_string() });
+        }
+        if let Some(list) = text.strip_prefix("VARS:") {
+            return Some(BridgeReply::Vars { list: list.to_string() });
+        }
+        if let Some(value) = text.strip_prefix("VAR:") {
+            return Some(BridgeReply::Var { value: value.to_string() });
+        }
+        None
+    }
+}
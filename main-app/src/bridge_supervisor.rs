@@ -0,0 +1,166 @@
+use std::cmp::min;
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use crate::{api_communicator, debug_logger};
+use crate::bridge_protocol::BridgeCommand;
+use crate::comm_sender::BridgeDispatcher;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// a bridge that ran this long resets the backoff
+const STABLE_RUN: Duration = Duration::from_secs(60);
+const STOP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct BridgeHealth {
+    pub crash_count: u32,
+    pub restart_count: u32,
+    pub last_exit_code: Option<i32>,
+}
+
+struct SupervisedProcess {
+    child: Option<Child>,
+    started_at: Instant,
+    bridge_path: String,
+    bridge_args: Vec<String>,
+    consecutive_crashes: u32,
+}
+
+// Owns the SimConnector process. A monitor thread polls the child, and while the bridge is
+// wanted it restarts it with exponential backoff whenever it exits.
+pub struct BridgeSupervisor {
+    process: Arc<Mutex<SupervisedProcess>>,
+    health: Arc<Mutex<BridgeHealth>>,
+    wanted: Arc<AtomicBool>,
+    log_str: Option<Arc<Mutex<String>>>,
+}
+
+impl BridgeSupervisor {
+    pub fn new(log_str: Option<Arc<Mutex<String>>>) -> Self {
+        let supervisor = BridgeSupervisor {
+            process: Arc::new(Mutex::new(SupervisedProcess {
+                child: None,
+                started_at: Instant::now(),
+                bridge_path: "".to_string(),
+                bridge_args: vec![],
+                consecutive_crashes: 0,
+            })),
+            health: Arc::new(Mutex::new(BridgeHealth::default())),
+            wanted: Arc::new(AtomicBool::new(false)),
+            log_str,
+        };
+        supervisor.spawn_monitor();
+        supervisor
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.wanted.load(Ordering::SeqCst)
+    }
+
+    pub fn health(&self) -> BridgeHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    // returns false if the bridge was already running
+    pub fn start(&self, bridge_path: String, bridge_args: Vec<String>) -> bool {
+        let mut process = self.process.lock().unwrap();
+        if self.wanted.load(Ordering::SeqCst) {
+            return false;
+        }
+        process.bridge_path = bridge_path;
+        process.bridge_args = bridge_args;
+        process.consecutive_crashes = 0;
+        process.child = Some(api_communicator::start_bridge_process(&process.bridge_path, &process.bridge_args));
+        process.started_at = Instant::now();
+        self.wanted.store(true, Ordering::SeqCst);
+        true
+    }
+
+    // asks the bridge to close, waits for the process to exit and reaps it, returns false if
+    // the bridge wasn't running
+    pub fn stop(&self, dispatcher: &BridgeDispatcher) -> bool {
+        let mut process = self.process.lock().unwrap();
+        if !self.wanted.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        dispatcher.send_with_timeout(BridgeCommand::Close, STOP_TIMEOUT);
+
+        if let Some(mut child) = process.child.take() {
+            let started = Instant::now();
+            let mut exit_status = None;
+            while started.elapsed() < STOP_TIMEOUT {
+                match child.try_wait() {
+                    Ok(Some(status)) => {
+                        exit_status = Some(status);
+                        break;
+                    }
+                    Ok(None) => thread::sleep(Duration::from_millis(50)),
+                    Err(_) => break
+                }
+            }
+            if exit_status.is_none() {
+                debug_logger::log("Bridge didn't exit in time, killing it", &self.log_str);
+                let _ = child.kill();
+                exit_status = child.wait().ok();
+            }
+            if let Some(status) = exit_status {
+                self.health.lock().unwrap().last_exit_code = status.code();
+            }
+        }
+        true
+    }
+
+    fn spawn_monitor(&self) {
+        let process = Arc::clone(&self.process);
+        let health = Arc::clone(&self.health);
+        let wanted = Arc::clone(&self.wanted);
+        let log_inner = debug_logger::clone_log(&self.log_str);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let mut locked = process.lock().unwrap();
+                if !wanted.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let exited = match locked.child.as_mut() {
+                    None => None,
+                    Some(child) => child.try_wait().ok().flatten()
+                };
+                let status = match exited {
+                    None => continue,
+                    Some(status) => status
+                };
+                locked.child = None;
+                if locked.started_at.elapsed() > STABLE_RUN {
+                    locked.consecutive_crashes = 0;
+                }
+                locked.consecutive_crashes += 1;
+                let mut current_health = health.lock().unwrap();
+                current_health.crash_count += 1;
+                current_health.last_exit_code = status.code();
+                drop(current_health);
+
+                let backoff = min(MIN_BACKOFF * 2u32.pow(min(locked.consecutive_crashes - 1, 5)), MAX_BACKOFF);
+                debug_logger::log(&*format!("Bridge exited unexpectedly ({}), restarting in {}s",
+                                            status, backoff.as_secs()), &log_inner);
+                drop(locked);
+
+                thread::sleep(backoff);
+                let mut locked = process.lock().unwrap();
+                // stop() may have been called while waiting
+                if !wanted.load(Ordering::SeqCst) || locked.child.is_some() {
+                    continue;
+                }
+                locked.child = Some(api_communicator::start_bridge_process(&locked.bridge_path, &locked.bridge_args));
+                locked.started_at = Instant::now();
+                health.lock().unwrap().restart_count += 1;
+                debug_logger::log("Bridge restarted", &log_inner);
+            }
+        });
+    }
+}
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+// The window listing / capturing / moving used by ImageProcess goes through these traits,
+// so the pop-out logic doesn't depend on Win32 directly. The Win32 backend is the default on
+// windows, the synthetic backend serves PNG fixtures from memory (used off windows and when
+// REACHFMS_CAPTURE_FIXTURES is set).
+
+pub const FIXTURES_ENV: &str = "REACHFMS_CAPTURE_FIXTURES";
+pub const SYNTHETIC_SIM_TITLE: &str = "Microsoft Flight Simulator - SYNTHETIC";
+
+// How hide_window keeps pop-outs out of sight:
+//  off_screen   below the virtual screen. The cursor input method can't click there, and MSFS
+//               can save the position if the app doesn't get to restore it
+//  transparent  stays where it is, fully transparent and behind the sim window. Clicks still land
+//               on it, so the cursor input method works too; it's sent back after each touch
+// DWM cloaking isn't one: Windows refuses to cloak a window of another process, the pop-outs are
+// MSFS's. Configs from when it was offered get off_screen, what it ended up doing.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HideStrategy {
+    #[default]
+    #[serde(alias = "cloaked")]
+    OffScreen,
+    Transparent,
+}
+
+impl HideStrategy {
+    pub fn from_name(name: &str) -> Option<HideStrategy> {
+        return match name.to_lowercase().as_str() {
+            "off_screen" => Some(HideStrategy::OffScreen),
+            "transparent" => Some(HideStrategy::Transparent),
+            _ => None
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WindowInfo {
+    pub hwnd: isize,
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct WindowRect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClientGeometry {
+    // the client area in the app's pixels, what gets captured
+    pub width: i32,
+    pub height: i32,
+    // pop-out pixels per app pixel. The app isn't dpi aware, Windows shows it the windows of a
+    // scaled monitor at 96 dpi, while a per monitor aware pop-out takes its input in real pixels
+    pub scale: f64,
+}
+
+pub struct RawCapture {
+    // rgba8 pixels
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub trait CaptureBackend: Send + Sync {
+    // every top level window with its title
+    fn window_list(&self) -> Result<Vec<WindowInfo>, u8>;
+    // hwnd of the owner window, 0 if there is none
+    fn window_owner(&self, hwnd: isize) -> isize;
+    // captures the client area, crop_xy/crop_wh work the same way as in capture_window_ex
+    fn capture_client(&self, hwnd: isize, crop_xy: Option<[i32; 2]>,
+                      crop_wh: Option<[i32; 2]>) -> Result<RawCapture, u8>;
+}
+
+pub trait WindowManager: Send + Sync {
+    fn window_rect(&self, hwnd: isize) -> Result<WindowRect, u8>;
+    fn move_window(&self, hwnd: isize, left: i32, top: i32, width: i32, height: i32) -> Result<(), u8>;
+    // gives the window the size and hides it the chosen way
+    fn hide_window(&self, hwnd: isize, strategy: HideStrategy, width: i32, height: i32) -> Result<(), u8>;
+    // undoes transparent, off_screen windows are shown by moving them
+    fn show_window(&self, hwnd: isize) -> Result<(), u8>;
+    // hidden by any of the strategies
+    fn is_hidden(&self, hwnd: isize) -> bool;
+    fn client_geometry(&self, hwnd: isize) -> Result<ClientGeometry, u8>;
+}
+
+pub trait Backend: CaptureBackend + WindowManager {}
+
+impl<T: CaptureBackend + WindowManager> Backend for T {}
+
+static BACKEND: OnceLock<Box<dyn Backend>> = OnceLock::new();
+
+pub fn backend() -> &'static dyn Backend {
+    BACKEND.get_or_init(default_backend).as_ref()
+}
+
+fn default_backend() -> Box<dyn Backend> {
+    if let Ok(fixture_dir) = std::env::var(FIXTURES_ENV) {
+        return Box::new(SyntheticBackend::from_folder(&fixture_dir));
+    }
+    #[cfg(windows)]
+    {
+        Box::new(win32::Win32Backend {})
+    }
+    #[cfg(not(windows))]
+    {
+        let fixture_dir = crate::config_handler::get_file_in_exe_folder(vec!["data", "fixtures"]);
+        Box::new(SyntheticBackend::from_folder(&fixture_dir))
+    }
+}
+
+#[cfg(windows)]
+pub mod win32 {
+    use std::mem;
+    use win_screenshot::prelude::*;
+    use windows::Win32::Foundation::{COLORREF, HWND, RECT};
+    use windows::Win32::UI::WindowsAndMessaging::{GetClientRect, GetLayeredWindowAttributes, GetSystemMetrics, GetWindow,
+                                                  GetWindowLongPtrW, GetWindowRect, GW_OWNER, GWL_EXSTYLE,
+                                                  HWND_BOTTOM, LAYERED_WINDOW_ATTRIBUTES_FLAGS, LWA_ALPHA,
+                                                  SetLayeredWindowAttributes, SetWindowLongPtrW, SetWindowPos,
+                                                  SM_CYVIRTUALSCREEN, SWP_NOACTIVATE, SWP_NOMOVE,
+                                                  SWP_NOREDRAW, SWP_NOZORDER, WS_EX_LAYERED};
+    use windows::Win32::UI::HiDpi::{DPI_AWARENESS_UNAWARE, GetAwarenessFromDpiAwarenessContext, GetDpiForWindow,
+                                    GetThreadDpiAwarenessContext};
+    use super::{CaptureBackend, ClientGeometry, HideStrategy, RawCapture, WindowInfo, WindowManager, WindowRect};
+
+    // not 0: a window without any alpha doesn't get clicks
+    const HIDDEN_ALPHA: u8 = 1;
+
+    const USING: Using = Using::PrintWindow;
+    const AREA: Area = Area::ClientOnly;
+
+    pub struct Win32Backend {}
+
+    impl CaptureBackend for Win32Backend {
+        fn window_list(&self) -> Result<Vec<WindowInfo>, u8> {
+            return match window_list() {
+                Ok(ls) => {
+                    Ok(ls.into_iter().map(|w| WindowInfo { hwnd: w.hwnd, title: w.window_name }).collect())
+                }
+                Err(_) => { Err(0) }
+            };
+        }
+
+        fn window_owner(&self, hwnd: isize) -> isize {
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                GetWindow(hwnd_in, GW_OWNER).0
+            }
+        }
+
+        fn capture_client(&self, hwnd: isize, crop_xy: Option<[i32; 2]>,
+                          crop_wh: Option<[i32; 2]>) -> Result<RawCapture, u8> {
+            return match capture_window_ex(hwnd, USING, AREA, crop_xy, crop_wh) {
+                Ok(buf) => Ok(RawCapture { pixels: buf.pixels, width: buf.width, height: buf.height }),
+                Err(..) => Err(0)
+            };
+        }
+    }
+
+    impl WindowManager for Win32Backend {
+        fn window_rect(&self, hwnd: isize) -> Result<WindowRect, u8> {
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                let mut rect: RECT = RECT::default();
+                if GetWindowRect(hwnd_in, &mut rect).is_err() {
+                    return Err(0);
+                }
+                Ok(WindowRect { left: rect.left, top: rect.top, right: rect.right, bottom: rect.bottom })
+            }
+        }
+
+        fn move_window(&self, hwnd: isize, left: i32, top: i32, width: i32, height: i32) -> Result<(), u8> {
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                match SetWindowPos(hwnd_in, hwnd_in, left, top, width, height,
+                                   SWP_NOREDRAW | SWP_NOZORDER | SWP_NOACTIVATE) {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(0)
+                }
+            }
+        }
+
+        fn hide_window(&self, hwnd: isize, strategy: HideStrategy, width: i32, height: i32) -> Result<(), u8> {
+            let screen_height = unsafe { GetSystemMetrics(SM_CYVIRTUALSCREEN) };
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                match strategy {
+                    HideStrategy::OffScreen => {}
+                    HideStrategy::Transparent => {
+                        let ex_style = GetWindowLongPtrW(hwnd_in, GWL_EXSTYLE);
+                        SetWindowLongPtrW(hwnd_in, GWL_EXSTYLE, ex_style | WS_EX_LAYERED.0 as isize);
+                        if SetLayeredWindowAttributes(hwnd_in, COLORREF(0), HIDDEN_ALPHA, LWA_ALPHA).is_ok() {
+                            return match SetWindowPos(hwnd_in, HWND_BOTTOM, 0, 0, width, height,
+                                                      SWP_NOMOVE | SWP_NOACTIVATE) {
+                                Ok(_) => Ok(()),
+                                Err(_) => Err(0)
+                            };
+                        }
+                    }
+                }
+            }
+            self.move_window(hwnd, 0, screen_height + 50, width, height)
+        }
+
+        fn show_window(&self, hwnd: isize) -> Result<(), u8> {
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                let ex_style = GetWindowLongPtrW(hwnd_in, GWL_EXSTYLE);
+                if ex_style & WS_EX_LAYERED.0 as isize != 0 {
+                    let _ = SetLayeredWindowAttributes(hwnd_in, COLORREF(0), 255, LWA_ALPHA);
+                    SetWindowLongPtrW(hwnd_in, GWL_EXSTYLE, ex_style & !(WS_EX_LAYERED.0 as isize));
+                }
+            }
+            Ok(())
+        }
+
+        fn is_hidden(&self, hwnd: isize) -> bool {
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                let mut rect: RECT = RECT::default();
+                if GetWindowRect(hwnd_in, &mut rect).is_ok() && rect.top >= GetSystemMetrics(SM_CYVIRTUALSCREEN) {
+                    return true;
+                }
+                let mut alpha: u8 = 255;
+                let mut flags = LAYERED_WINDOW_ATTRIBUTES_FLAGS(0);
+                if GetWindowLongPtrW(hwnd_in, GWL_EXSTYLE) & WS_EX_LAYERED.0 as isize != 0 &&
+                    GetLayeredWindowAttributes(hwnd_in, None, Some(&mut alpha as *mut u8),
+                                               Some(&mut flags as *mut LAYERED_WINDOW_ATTRIBUTES_FLAGS)).is_ok() &&
+                    flags.0 & LWA_ALPHA.0 != 0 && alpha < 255 {
+                    return true;
+                }
+                false
+            }
+        }
+
+        fn client_geometry(&self, hwnd: isize) -> Result<ClientGeometry, u8> {
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                let mut rect: RECT = RECT::default();
+                if GetClientRect(hwnd_in, &mut rect).is_err() {
+                    return Err(0);
+                }
+                Ok(ClientGeometry { width: rect.right - rect.left, height: rect.bottom - rect.top,
+                                    scale: dpi_scale(hwnd_in) })
+            }
+        }
+    }
+
+    // the dpi of the window (96 if it isn't dpi aware itself) over the 96 this process is shown
+    // everything at. 1 once the process is dpi aware, it sees the real pixels then too
+    pub unsafe fn dpi_scale(hwnd: HWND) -> f64 {
+        let dpi = GetDpiForWindow(hwnd);
+        if dpi == 0 || GetAwarenessFromDpiAwarenessContext(GetThreadDpiAwarenessContext()) != DPI_AWARENESS_UNAWARE {
+            return 1.0;
+        }
+        dpi as f64 / 96.0
+    }
+}
+
+struct SyntheticWindow {
+    title: String,
+    owner: isize,
+    frame: Option<RgbaImage>,
+    rect: WindowRect,
+    // transparent
+    hidden: bool,
+}
+
+// In-memory backend: one MSFS main window and a pop-out for every png in the fixture folder.
+// The file name (without extension) is used as the pop-out title.
+pub struct SyntheticBackend {
+    windows: Mutex<HashMap<isize, SyntheticWindow>>,
+    screen_height: i32,
+}
+
+impl SyntheticBackend {
+    const SIM_HWND: isize = 1;
+
+    pub fn new() -> Self {
+        let mut windows: HashMap<isize, SyntheticWindow> = HashMap::new();
+        windows.insert(Self::SIM_HWND, SyntheticWindow {
+            title: SYNTHETIC_SIM_TITLE.to_string(),
+            owner: 0,
+            frame: None,
+            rect: WindowRect { left: 0, top: 0, right: 1920, bottom: 1080 },
+            hidden: false,
+        });
+        SyntheticBackend {
+            windows: Mutex::new(windows),
+            screen_height: 1080,
+        }
+    }
+
+    pub fn from_folder(folder: &str) -> Self {
+        let backend = SyntheticBackend::new();
+        let mut paths: Vec<std::path::PathBuf> = match fs::read_dir(folder) {
+            Ok(dir) => dir.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+            Err(_) => vec![]
+        };
+        paths.sort();
+        for path in paths {
+            if path.extension().map(|ext| ext == "png").unwrap_or(false) {
+                let title = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                if let Ok(img) = image::open(&path) {
+                    backend.add_popout(&title, img.to_rgba8());
+                }
+            }
+        }
+        backend
+    }
+
+    // adds a pop-out owned by the synthetic sim window, returns its hwnd
+    pub fn add_popout(&self, title: &str, frame: RgbaImage) -> isize {
+        let mut windows = self.windows.lock().unwrap();
+        let hwnd = 100 + windows.len() as isize;
+        let rect = WindowRect { left: 0, top: 0, right: frame.width() as i32, bottom: frame.height() as i32 };
+        windows.insert(hwnd, SyntheticWindow {
+            title: title.to_string(),
+            owner: Self::SIM_HWND,
+            frame: Some(frame),
+            rect,
+            hidden: false,
+        });
+        hwnd
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    fn window_list(&self) -> Result<Vec<WindowInfo>, u8> {
+        let windows = self.windows.lock().unwrap();
+        let mut ls: Vec<WindowInfo> = windows.iter()
+            .map(|(hwnd, w)| WindowInfo { hwnd: *hwnd, title: w.title.clone() })
+            .collect();
+        ls.sort_by_key(|w| w.hwnd);
+        Ok(ls)
+    }
+
+    fn window_owner(&self, hwnd: isize) -> isize {
+        match self.windows.lock().unwrap().get(&hwnd) {
+            None => 0,
+            Some(window) => window.owner
+        }
+    }
+
+    fn capture_client(&self, hwnd: isize, crop_xy: Option<[i32; 2]>,
+                      crop_wh: Option<[i32; 2]>) -> Result<RawCapture, u8> {
+        let windows = self.windows.lock().unwrap();
+        let frame = match windows.get(&hwnd).and_then(|w| w.frame.as_ref()) {
+            None => return Err(0),
+            Some(frame) => frame
+        };
+        let [x, y] = crop_xy.unwrap_or([0, 0]);
+        let [w, h] = crop_wh.unwrap_or([frame.width() as i32 - x, frame.height() as i32 - y]);
+        if x < 0 || y < 0 || w <= 0 || h <= 0
+            || (x + w) as u32 > frame.width() || (y + h) as u32 > frame.height() {
+            return Err(1);
+        }
+        let cropped = image::imageops::crop_imm(frame, x as u32, y as u32, w as u32, h as u32).to_image();
+        Ok(RawCapture {
+            width: cropped.width(),
+            height: cropped.height(),
+            pixels: cropped.into_raw(),
+        })
+    }
+}
+
+impl WindowManager for SyntheticBackend {
+    fn window_rect(&self, hwnd: isize) -> Result<WindowRect, u8> {
+        match self.windows.lock().unwrap().get(&hwnd) {
+            None => Err(0),
+            Some(window) => Ok(window.rect)
+        }
+    }
+
+    fn move_window(&self, hwnd: isize, left: i32, top: i32, width: i32, height: i32) -> Result<(), u8> {
+        match self.windows.lock().unwrap().get_mut(&hwnd) {
+            None => Err(0),
+            Some(window) => {
+                window.rect = WindowRect { left, top, right: left + width, bottom: top + height };
+                Ok(())
+            }
+        }
+    }
+
+    fn hide_window(&self, hwnd: isize, strategy: HideStrategy, width: i32, height: i32) -> Result<(), u8> {
+        if strategy == HideStrategy::OffScreen {
+            return self.move_window(hwnd, 0, self.screen_height + 50, width, height);
+        }
+        let rect = self.window_rect(hwnd)?;
+        self.move_window(hwnd, rect.left, rect.top, width, height)?;
+        self.windows.lock().unwrap().get_mut(&hwnd).unwrap().hidden = true;
+        Ok(())
+    }
+
+    fn show_window(&self, hwnd: isize) -> Result<(), u8> {
+        match self.windows.lock().unwrap().get_mut(&hwnd) {
+            None => Err(0),
+            Some(window) => {
+                window.hidden = false;
+                Ok(())
+            }
+        }
+    }
+
+    fn is_hidden(&self, hwnd: isize) -> bool {
+        match self.windows.lock().unwrap().get(&hwnd) {
+            None => false,
+            Some(window) => window.hidden || window.rect.top >= self.screen_height
+        }
+    }
+
+    // the fixture is the client area, at 100%
+    fn client_geometry(&self, hwnd: isize) -> Result<ClientGeometry, u8> {
+        match self.windows.lock().unwrap().get(&hwnd).and_then(|w| w.frame.as_ref()) {
+            None => Err(0),
+            Some(frame) => Ok(ClientGeometry { width: frame.width() as i32, height: frame.height() as i32, scale: 1.0 })
+        }
+    }
+}
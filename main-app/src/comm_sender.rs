@@ -1,133 +1,169 @@
-use std::time::Instant;
-use crossbeam_channel::{select, after};
-use crate::http_streamer::BridgeStatus;
-
-pub fn get_aircraft(command_sender: &crossbeam_channel::Sender<String>,
-                    comm_receiver: &crossbeam_channel::Receiver<String>) -> String {
-    let timeout = std::time::Duration::from_millis(100);
-    command_sender.send("GetAircraft".to_string()).expect("Can't send.");
-    let mut aircraft: String = "".to_string();
-    let started: Instant = Instant::now();
-    let mut got_info = false;
-    while !got_info {
-        select! {
-            recv(comm_receiver) -> msg => {
-                let resp = msg.unwrap_or("".to_string());
-                if resp.contains("AIRCRAFT:"){
-                    aircraft = resp.replace("AIRCRAFT:", "");
-                    got_info = true;
-                }
-        },
-            recv(after(timeout)) -> _ => {
-                    if started.elapsed().as_millis() > 201 {
-                    
-                    break;
-                }
-                }
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crossbeam_channel::bounded;
+use serde::{Deserialize, Serialize};
+use crate::bridge_protocol::{BridgeCommand, BridgeReply, BridgeRequest, BridgeResponse};
+use crate::debug_logger;
+
+const SHORT_TIMEOUT: Duration = Duration::from_millis(200);
+const LONG_TIMEOUT: Duration = Duration::from_millis(5000);
+
+#[derive(Serialize, Deserialize)]
+#[derive(Clone)]
+pub struct BridgeStatus {
+    pub started: bool,
+    pub connected: bool,
+    pub comm: bool,
+    pub crash_count: u32,
+    pub restart_count: u32,
+    pub last_exit_code: Option<i32>,
+}
+
+struct PendingRequest {
+    id: u64,
+    kind: &'static str,
+    sender: crossbeam_channel::Sender<BridgeReply>,
+}
+
+// Every query gets its own id and its own reply channel, the ws actor of the bridge hands
+// replies to dispatch() which routes them back to the waiting caller. Legacy bridges don't
+// echo the id, their replies go to the oldest request waiting for that reply type.
+pub struct BridgeDispatcher {
+    next_id: AtomicU64,
+    // protocol version of the connected bridge, 0 for legacy bridges and without a bridge
+    bridge_version: AtomicU32,
+    pending: Mutex<Vec<PendingRequest>>,
+    command_sender: crossbeam_channel::Sender<BridgeRequest>,
+    log_str: Option<Arc<Mutex<String>>>,
+}
+
+impl BridgeDispatcher {
+    pub fn new(command_sender: crossbeam_channel::Sender<BridgeRequest>,
+               log_str: Option<Arc<Mutex<String>>>) -> Self {
+        BridgeDispatcher {
+            next_id: AtomicU64::new(1),
+            bridge_version: AtomicU32::new(0),
+            pending: Mutex::new(vec![]),
+            command_sender,
+            log_str,
         }
     }
-    return aircraft;
-}
 
-pub fn get_status(brid_status: &mut BridgeStatus, command_sender: crossbeam_channel::Sender<String>,
-                  comm_receiver: crossbeam_channel::Receiver<String>) {
-    let timeout = std::time::Duration::from_millis(100);
+    // sends the command and waits for its reply, None on timeout; the timeout is for both
+    pub fn request(&self, command: BridgeCommand, timeout: Duration) -> Option<BridgeReply> {
+        let kind = match command.reply_kind() {
+            None => {
+                self.send(command);
+                return None;
+            }
+            Some(kind) => kind
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = bounded::<BridgeReply>(1);
+        self.pending.lock().unwrap().push(PendingRequest { id, kind, sender });
+
+        let deadline = Instant::now() + timeout;
+        let reply = match self.command_sender.send_deadline(BridgeRequest { id: Some(id), command }, deadline) {
+            Ok(_) => receiver.recv_deadline(deadline).ok(),
+            Err(_) => None
+        };
+        self.pending.lock().unwrap().retain(|pending| pending.id != id);
+        reply
+    }
 
-    let started: Instant = Instant::now();
-    match command_sender.send_timeout("BridgeStatus".to_string(), timeout) {
-        Ok(_) => {}
-        Err(_) => {}
-    };
-    loop {
-        select! {
-            recv(comm_receiver) -> msg => {
-                let resp = msg.unwrap_or("".to_string());
-                brid_status.comm = true;
-                if resp == "STATUS:TRUE"{
-                    brid_status.connected = true;
-                }else if resp == "STATUS:FALSE"{
-                    brid_status.connected = false;
-                }
-                return;
-            },
-            recv(after(timeout)) -> _ => {
-                if started.elapsed().as_millis() > 201{
-                    brid_status.comm = false;
-                    return;
-                }
-             }
+    // fire and forget, returns false if no bridge took the command
+    pub fn send(&self, command: BridgeCommand) -> bool {
+        self.send_with_timeout(command, LONG_TIMEOUT)
+    }
+
+    pub fn send_with_timeout(&self, command: BridgeCommand, timeout: Duration) -> bool {
+        match self.command_sender.send_timeout(BridgeRequest { id: None, command }, timeout) {
+            Ok(_) => true,
+            Err(_) => {
+                debug_logger::log("Can't send command, bridge is not connected", &self.log_str);
+                false
             }
+        }
     }
-}
 
-pub fn reconnect(command_sender: crossbeam_channel::Sender<String>,
-                 comm_receiver: crossbeam_channel::Receiver<String>) -> String {
-    let timeout = std::time::Duration::from_millis(100);
+    // a command the connection of a bridge took but couldn't send, it went away: it goes to the
+    // next bridge, a query nobody takes fails right away instead of waiting out its timeout
+    pub fn requeue(&self, request: BridgeRequest) {
+        let request = match self.command_sender.send_timeout(request, SHORT_TIMEOUT) {
+            Ok(_) => return,
+            Err(e) => e.into_inner()
+        };
+        debug_logger::log(&*format!("Bridge is gone, dropping command: {:?}", &request), &self.log_str);
+        if let Some(id) = request.id {
+            // the waiting caller sees its reply channel closed
+            self.pending.lock().unwrap().retain(|pending| pending.id != id);
+        }
+    }
 
-    let started: Instant = Instant::now();
-    match command_sender.send_timeout("SM_SEND:RECONNECT".to_string(), timeout) {
-        Ok(_) => {}
-        Err(_) => {}
-    };
-    loop {
-        select! {
-            recv(comm_receiver) -> msg => {
-                let resp = msg.unwrap_or("".to_string());
-                return resp.replace("RECONNECT:", "");
-            },
-            recv(after(timeout)) -> _ => {
-                if started.elapsed().as_millis() > 201{
-                    return "ERROR".to_string();
-                }
-             }
+    pub fn set_bridge_version(&self, version: u32) {
+        self.bridge_version.store(version, Ordering::Relaxed);
+    }
+
+    pub fn bridge_version(&self) -> u32 {
+        self.bridge_version.load(Ordering::Relaxed)
+    }
+
+    pub fn dispatch(&self, response: BridgeResponse) {
+        let mut pending = self.pending.lock().unwrap();
+        let position = match response.id {
+            Some(id) => pending.iter().position(|p| p.id == id),
+            None => pending.iter().position(|p| p.kind == response.reply.kind())
+        };
+        match position {
+            Some(position) => {
+                let waiting = pending.remove(position);
+                let _ = waiting.sender.try_send(response.reply);
+            }
+            None => {
+                debug_logger::log(&*format!("Dropping bridge reply nobody waits for: {:?}", &response),
+                                  &self.log_str);
             }
+        }
     }
 }
 
-pub fn get_vars(command_sender: crossbeam_channel::Sender<String>,
-                 comm_receiver: crossbeam_channel::Receiver<String>) -> String {
-    let timeout = std::time::Duration::from_millis(100);
-
-    let started: Instant = Instant::now();
-    match command_sender.send_timeout("SM_SEND:VAR_LIST".to_string(), timeout) {
-        Ok(_) => {}
-        Err(_) => {}
+pub fn get_aircraft(dispatcher: &BridgeDispatcher) -> String {
+    return match dispatcher.request(BridgeCommand::GetAircraft, SHORT_TIMEOUT) {
+        Some(BridgeReply::Aircraft { file }) => file,
+        _ => "".to_string()
     };
-    loop {
-        select! {
-            recv(comm_receiver) -> msg => {
-                let resp = msg.unwrap_or("".to_string());
-                return resp.replace("VARS:", "");
-            },
-            recv(after(timeout)) -> _ => {
-                if started.elapsed().as_millis() > 5000{
-                    return "ERROR".to_string();
-                }
-             }
-            }
+}
+
+pub fn get_status(brid_status: &mut BridgeStatus, dispatcher: &BridgeDispatcher) {
+    match dispatcher.request(BridgeCommand::Status, SHORT_TIMEOUT) {
+        Some(BridgeReply::Status { connected }) => {
+            brid_status.comm = true;
+            brid_status.connected = connected;
+        }
+        _ => {
+            brid_status.comm = false;
+        }
     }
 }
 
-pub fn get_var(var_name: &str, command_sender: crossbeam_channel::Sender<String>,
-                comm_receiver: crossbeam_channel::Receiver<String>) -> String {
-    let timeout = std::time::Duration::from_millis(100);
+pub fn reconnect(dispatcher: &BridgeDispatcher) -> String {
+    return match dispatcher.request(BridgeCommand::Reconnect, SHORT_TIMEOUT) {
+        Some(BridgeReply::Reconnect { result }) => result,
+        _ => "ERROR".to_string()
+    };
+}
 
-    let started: Instant = Instant::now();
-    match command_sender.send_timeout(format!("SM_SEND:GET_VAR:{}", var_name), timeout) {
-        Ok(_) => {}
-        Err(_) => {}
+pub fn get_vars(dispatcher: &BridgeDispatcher) -> String {
+    return match dispatcher.request(BridgeCommand::VarList, LONG_TIMEOUT) {
+        Some(BridgeReply::Vars { list }) => list,
+        _ => "ERROR".to_string()
+    };
+}
+
+pub fn get_var(var_name: &str, dispatcher: &BridgeDispatcher) -> String {
+    return match dispatcher.request(BridgeCommand::GetVar { name: var_name.to_string() }, LONG_TIMEOUT) {
+        Some(BridgeReply::Var { value }) => value,
+        _ => "ERROR".to_string()
     };
-    loop {
-        select! {
-            recv(comm_receiver) -> msg => {
-                let resp = msg.unwrap_or("".to_string());
-                return resp.replace("VAR:", "");
-            },
-            recv(after(timeout)) -> _ => {
-                if started.elapsed().as_millis() > 5000{
-                    return "ERROR".to_string();
-                }
-             }
-            }
-    }
 }
@@ -3,7 +3,18 @@ use std::fs::File;
 use std::sync::{Arc, Mutex};
 use qrcode_generator::QrCodeEcc;
 use serde::{Deserialize, Serialize};
+use crate::capture_backend::HideStrategy;
 use crate::debug_logger;
+use crate::input_injector::InputMethod;
+use crate::pairing::PAIR_PATH;
+use crate::tls::TLS_PORT;
+use crate::frame_encoder::{DEFAULT_QUALITY, FrameFormat};
+
+const DEFAULT_MIN_FPS: u16 = 2;
+pub const HTTP_PORT: u16 = 5273;
+// another port for the http server, to run next to the app (the tests do); the bundled
+// SimConnector.exe always connects to HTTP_PORT, a custom bridge gets the url in bridge_args
+pub const PORT_ENV: &str = "REACHFMS_PORT";
 
 #[derive(Serialize, Deserialize)]
 #[derive(Clone)]
@@ -17,6 +28,36 @@ pub struct ConfigHandler {
     pub auto_start: bool,
     pub calibrated: bool,
     pub log_enabled: bool,
+    // empty: the bundled SimConnector.exe, otherwise the bridge executable to start (e.g. mock_bridge)
+    #[serde(default)]
+    pub bridge_path: String,
+    #[serde(default)]
+    pub bridge_args: Vec<String>,
+    // default encoding of the streamed frames, clients can ask for another one when subscribing
+    #[serde(default)]
+    pub image_format: FrameFormat,
+    #[serde(default = "default_image_quality")]
+    pub image_quality: u8,
+    // slow clients don't get fewer frames per second than this, refresh_rate is the fastest
+    #[serde(default = "default_min_fps")]
+    pub min_fps: u16,
+    // capture windows that stopped changing less often, until a button press or touch
+    #[serde(default)]
+    pub idle_capture: bool,
+    // how auto hide keeps the pop-outs out of sight
+    #[serde(default)]
+    pub hide_strategy: HideStrategy,
+    // how touches are sent to the pop-out
+    #[serde(default)]
+    pub input_method: InputMethod,
+    // https on TLS_PORT as well, see tls.rs; taken when the server starts
+    #[serde(default)]
+    pub tls_enabled: bool,
+    // PEM files of the certificate to use, empty: the self-signed one made in data/
+    #[serde(default)]
+    pub tls_cert_path: String,
+    #[serde(default)]
+    pub tls_key_path: String,
     #[serde(skip_serializing, skip_deserializing)]
     log_str: Option<Arc<Mutex<String>>>
 }
@@ -31,8 +72,7 @@ pub struct DebugSave {
 
 impl ConfigHandler {
     pub fn init(log_str: Option<Arc<Mutex<String>>>) -> Self {
-        let host = ConfigHandler::get_localhost();
-        let local_ip = host.clone();
+        let local_ip = ConfigHandler::get_localhost();
         let default_config = ConfigHandler {
             local_ip,
             auto_hide: true,
@@ -43,7 +83,18 @@ impl ConfigHandler {
             auto_start: false,
             calibrated: false,
             log_str,
-            log_enabled: false
+            log_enabled: false,
+            bridge_path: "".to_string(),
+            bridge_args: vec![],
+            image_format: FrameFormat::Png,
+            image_quality: DEFAULT_QUALITY,
+            min_fps: DEFAULT_MIN_FPS,
+            idle_capture: false,
+            hide_strategy: HideStrategy::OffScreen,
+            input_method: InputMethod::Message,
+            tls_enabled: false,
+            tls_cert_path: "".to_string(),
+            tls_key_path: "".to_string(),
         };
 
         if !ConfigHandler::is_data_created() {
@@ -59,10 +110,20 @@ impl ConfigHandler {
             let json_string = serde_json::to_string(&default_config).unwrap();
             fs::write(get_config_file(), json_string).expect("Unable to write file");
         }
-        qrcode_generator::to_png_to_file(host, QrCodeEcc::Low, 1024, get_qr_file()).unwrap();
         default_config
     }
 
+    // the QR code shown in the app pairs the device that scans it, see pairing.rs; over https with
+    // the fingerprint of the certificate if it's served
+    pub fn write_qr_code(pairing_token: &str, tls_fingerprint: Option<&str>) {
+        let url = match tls_fingerprint {
+            Some(fingerprint) => format!("{}{}?token={}&fp={}", ConfigHandler::get_secure_host(), PAIR_PATH,
+                                         pairing_token, fingerprint),
+            None => format!("{}{}?token={}", ConfigHandler::get_localhost(), PAIR_PATH, pairing_token)
+        };
+        qrcode_generator::to_png_to_file(url, QrCodeEcc::Low, 1024, get_qr_file()).unwrap();
+    }
+
     pub fn read_config(&mut self) {
         let string_data = fs::read_to_string(get_config_file()).expect("Unable to read file");
         let deserialized: ConfigHandler = serde_json::from_str(&string_data).unwrap();
@@ -73,7 +134,18 @@ impl ConfigHandler {
         self.multiple_displays = deserialized.multiple_displays;
         self.cpu_displays = deserialized.cpu_displays;
         self.calibrated = deserialized.calibrated;
-        self.log_enabled = deserialized.log_enabled
+        self.log_enabled = deserialized.log_enabled;
+        self.bridge_path = deserialized.bridge_path;
+        self.bridge_args = deserialized.bridge_args;
+        self.image_format = deserialized.image_format;
+        self.image_quality = deserialized.image_quality;
+        self.min_fps = deserialized.min_fps;
+        self.idle_capture = deserialized.idle_capture;
+        self.hide_strategy = deserialized.hide_strategy;
+        self.input_method = deserialized.input_method;
+        self.tls_enabled = deserialized.tls_enabled;
+        self.tls_cert_path = deserialized.tls_cert_path;
+        self.tls_key_path = deserialized.tls_key_path;
     }
 
     pub fn get_all_local_ip() -> Vec<String> {
@@ -82,7 +154,7 @@ impl ConfigHandler {
         for ip in raw_list {
             let cur_ip = ip.1.to_string();
             if cur_ip.contains(":") { continue; }
-            url_list.push(format!("http://{}:5273", cur_ip))
+            url_list.push(format!("http://{}:{}", cur_ip, get_http_port()))
         }
         url_list
     }
@@ -98,7 +170,11 @@ impl ConfigHandler {
     }
 
     pub fn get_localhost() -> String {
-        format!("http://{}:5273", local_ip_address::local_ip().unwrap().to_string())
+        format!("http://{}:{}", local_ip_address::local_ip().unwrap().to_string(), get_http_port())
+    }
+
+    pub fn get_secure_host() -> String {
+        format!("https://{}:{}", local_ip_address::local_ip().unwrap().to_string(), TLS_PORT)
     }
 
     pub fn get_string(&self) -> String {
@@ -113,6 +189,21 @@ impl ConfigHandler {
     }
 
 }
+fn default_image_quality() -> u8 {
+    DEFAULT_QUALITY
+}
+
+fn default_min_fps() -> u16 {
+    DEFAULT_MIN_FPS
+}
+
+pub fn get_http_port() -> u16 {
+    return match std::env::var(PORT_ENV).ok().and_then(|port| port.parse::<u16>().ok()) {
+        Some(port) => port,
+        None => HTTP_PORT
+    };
+}
+
 pub fn get_file_in_exe_folder(path_inside: Vec<&str>) -> String {
     return match std::env::current_exe() {
         Ok(mut res) => {
@@ -152,6 +243,24 @@ pub fn get_simconnector_folder() -> String {
 pub fn get_config_file() -> String {
     return get_file_in_exe_folder(vec!["data", "config.json"])
 }
+pub fn get_instrument_settings_file() -> String {
+    return get_file_in_exe_folder(vec!["data", "instrument_settings.json"])
+}
+pub fn get_window_layouts_file() -> String {
+    return get_file_in_exe_folder(vec!["data", "window_layouts.json"])
+}
+pub fn get_window_journal_file() -> String {
+    return get_file_in_exe_folder(vec!["data", "window_journal.json"])
+}
+pub fn get_pairings_file() -> String {
+    return get_file_in_exe_folder(vec!["data", "pairings.json"])
+}
+pub fn get_tls_cert_file() -> String {
+    return get_file_in_exe_folder(vec!["data", "tls_cert.pem"])
+}
+pub fn get_tls_key_file() -> String {
+    return get_file_in_exe_folder(vec!["data", "tls_key.pem"])
+}
 pub fn get_qr_file() -> String {
     return get_file_in_exe_folder(vec!["data", "qr.png"])
 }
@@ -1,11 +1,15 @@
+#[cfg(windows)]
 use std::ffi::CString;
 use std::fs::{OpenOptions};
 use std::sync::{Arc, Mutex};
 use chrono::Utc;
 use crate::config_handler::get_log_file;
 use std::io::prelude::*;
+#[cfg(windows)]
 use windows::core::PCSTR;
+#[cfg(windows)]
 use windows::Win32::Foundation::HWND;
+#[cfg(windows)]
 use windows::Win32::UI::WindowsAndMessaging::{MB_ICONERROR, MB_ICONWARNING, MB_OK, MessageBoxA};
 
 pub fn log(new_log: &str, log_str: &Option<Arc<Mutex<String>>>) {
@@ -67,6 +71,7 @@ pub fn clone_log(log_str: &Option<Arc<Mutex<String>>>) -> Option<Arc<Mutex<Strin
     }
 }
 
+#[cfg(windows)]
 pub fn show_error_dialog(message: &str) {
     unsafe {
         let lp_text = CString::new(message).unwrap();
@@ -78,6 +83,12 @@ pub fn show_error_dialog(message: &str) {
     }
 }
 
+#[cfg(not(windows))]
+pub fn show_error_dialog(message: &str) {
+    eprintln!("Error while running the app... {}", message);
+}
+
+#[cfg(windows)]
 pub fn show_warning_dialog(message: &str) {
     unsafe {
         let lp_text = CString::new(message).unwrap();
@@ -89,6 +100,11 @@ pub fn show_warning_dialog(message: &str) {
     }
 }
 
+#[cfg(not(windows))]
+pub fn show_warning_dialog(message: &str) {
+    eprintln!("Warning while running the app... {}", message);
+}
+
 pub fn show_fatal_error(message: &str) {
     show_error_dialog(message);
     std::process::exit(0);
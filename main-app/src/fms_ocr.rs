@@ -0,0 +1,339 @@
+use std::fs;
+use serde::{Deserialize, Serialize};
+
+// Reads the text of an FMS page from the cropped CDU capture. The screen is split into the
+// character grid, 24x14 on most CDUs, and every cell is compared to the glyphs of the aircraft's
+// font template. Templates are json files in static/fonts (fms_font in the addon config), made
+// from screenshots with fms_font_tool:
+//
+// {
+//   "area": [[0.0, 0.0], [1.0, 1.0]],    the part of the capture the grid covers, x y w h as
+//                                        fractions of the capture
+//   "columns": 24, "rows": 14,
+//   "glyph_width": 12, "glyph_height": 16,
+//   "glyphs": [{"char": "A", "small": false, "bounds": [0.1, 0.2, 0.8, 0.6], "rows": ["0039930000..", ...]}]
+// }
+//
+// A glyph is a cell sized window around the ink scaled down to glyph_width x glyph_height
+// samples, 0 (no ink) to 9 (as bright as the brightest pixel of the cell), and the bounds of the
+// ink in the cell, x y w h as fractions of the cell. The bounds tell "-" from "." and the small
+// font (the label lines of Airbus MCDUs) from the large one, small glyphs are separate glyphs.
+
+pub const COLUMNS: u32 = 24;
+pub const ROWS: u32 = 14;
+const GLYPH_WIDTH: u32 = 12;
+const GLYPH_HEIGHT: u32 = 16;
+// a pixel brighter than this is ink, the CDU background is black
+const INK_THRESHOLD: u8 = 80;
+const MAX_LEVEL: u8 = 9;
+// a cell matches a glyph if the samples differ by at most this much on average (0 - 1)
+const MAX_DISTANCE: f32 = 0.2;
+// and its ink is about at the same place, the sum of the x y w h differences
+const MAX_BOUNDS_DIFFERENCE: f32 = 0.3;
+// a pixel moves the bounds of small glyphs a lot, the shape decides between close glyphs
+const BOUNDS_WEIGHT: f32 = 0.25;
+pub const UNKNOWN_CHAR: char = '\u{fffd}';
+
+// the usual CDU colours, the ink of a cell is matched to the closest one
+const PALETTE: [(&str, [f32; 3]); 7] = [
+    ("white", [1.0, 1.0, 1.0]),
+    ("green", [0.0, 1.0, 0.0]),
+    ("cyan", [0.0, 1.0, 1.0]),
+    ("amber", [1.0, 0.65, 0.0]),
+    ("magenta", [1.0, 0.0, 1.0]),
+    ("yellow", [1.0, 1.0, 0.0]),
+    ("red", [1.0, 0.0, 0.0]),
+];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Glyph {
+    pub char: char,
+    #[serde(default)]
+    pub small: bool,
+    #[serde(default)]
+    pub bounds: [f32; 4],
+    pub rows: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FmsFont {
+    #[serde(default = "default_area")]
+    pub area: [[f32; 2]; 2],
+    #[serde(default = "default_columns")]
+    pub columns: u32,
+    #[serde(default = "default_rows")]
+    pub rows: u32,
+    #[serde(default = "default_glyph_width")]
+    pub glyph_width: u32,
+    #[serde(default = "default_glyph_height")]
+    pub glyph_height: u32,
+    pub glyphs: Vec<Glyph>,
+    // the rows of every glyph as levels
+    #[serde(skip)]
+    samples: Vec<Vec<u8>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FmsCell {
+    pub char: char,
+    // empty for spaces
+    pub color: String,
+    pub small: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FmsPage {
+    pub lines: Vec<String>,
+    pub cells: Vec<Vec<FmsCell>>,
+    // cells with ink that didn't match any glyph
+    pub unknown: u32,
+}
+
+struct CellInk {
+    samples: Vec<u8>,
+    bounds: [f32; 4],
+    // sum of the ink pixels
+    color: [f32; 3],
+}
+
+impl FmsFont {
+    pub fn new() -> Self {
+        FmsFont {
+            area: default_area(),
+            columns: COLUMNS,
+            rows: ROWS,
+            glyph_width: GLYPH_WIDTH,
+            glyph_height: GLYPH_HEIGHT,
+            glyphs: vec![],
+            samples: vec![],
+        }
+    }
+
+    pub fn load(path: &str) -> Result<FmsFont, String> {
+        let string_data = match fs::read_to_string(path) {
+            Ok(string_data) => string_data,
+            Err(e) => return Err(format!("Cant read font {}: {}", path, e))
+        };
+        FmsFont::from_json(&string_data)
+    }
+
+    pub fn from_json(json: &str) -> Result<FmsFont, String> {
+        let mut font: FmsFont = match serde_json::from_str(json) {
+            Ok(font) => font,
+            Err(e) => return Err(format!("Invalid font: {}", e))
+        };
+        if font.columns == 0 || font.rows == 0 || font.glyph_width == 0 || font.glyph_height == 0 {
+            return Err("Invalid font: empty grid".to_string());
+        }
+        let size = (font.glyph_width * font.glyph_height) as usize;
+        font.samples = font.glyphs.iter().map(|glyph| {
+            let mut samples: Vec<u8> = glyph.rows.iter()
+                .flat_map(|row| row.chars().map(|c| c.to_digit(10).unwrap_or(0) as u8))
+                .collect();
+            samples.resize(size, 0);
+            samples
+        }).collect();
+        Ok(font)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // adds the glyphs of a screenshot with known text (lines of the page, spaces are skipped), a
+    // glyph that's already in the font is replaced, returns the number of glyphs taken
+    pub fn learn(&mut self, pixels: &[u8], width: u32, height: u32, lines: &[String], small_rows: &[u32]) -> u32 {
+        let mut learned = 0;
+        for (row, line) in lines.iter().enumerate().take(self.rows as usize) {
+            let small = small_rows.contains(&(row as u32));
+            for (column, char) in line.chars().enumerate().take(self.columns as usize) {
+                if char == ' ' {
+                    continue;
+                }
+                let cell = match self.sample_cell(pixels, width, height, column as u32, row as u32) {
+                    None => continue,
+                    Some(cell) => cell
+                };
+                let rows: Vec<String> = cell.samples.chunks(self.glyph_width as usize)
+                    .map(|row| row.iter().map(|level| char::from(b'0' + level)).collect())
+                    .collect();
+                match self.glyphs.iter().position(|glyph| glyph.char == char && glyph.small == small) {
+                    Some(index) => {
+                        self.glyphs[index].rows = rows;
+                        self.glyphs[index].bounds = cell.bounds;
+                        self.samples[index] = cell.samples;
+                    }
+                    None => {
+                        self.glyphs.push(Glyph { char, small, bounds: cell.bounds, rows });
+                        self.samples.push(cell.samples);
+                    }
+                }
+                learned += 1;
+            }
+        }
+        learned
+    }
+
+    // the ink of the cell scaled down to the glyph size, None if the cell is empty
+    fn sample_cell(&self, pixels: &[u8], width: u32, height: u32, column: u32, row: u32) -> Option<CellInk> {
+        let cell_w = self.area[1][0] * width as f32 / self.columns as f32;
+        let cell_h = self.area[1][1] * height as f32 / self.rows as f32;
+        let left = self.area[0][0] * width as f32 + column as f32 * cell_w;
+        let top = self.area[0][1] * height as f32 + row as f32 * cell_h;
+        let x_end = ((left + cell_w) as u32).min(width);
+        let y_end = ((top + cell_h) as u32).min(height);
+        let brightness = |x: u32, y: u32| {
+            let i = ((y * width + x) * 4) as usize;
+            pixels[i].max(pixels[i + 1]).max(pixels[i + 2])
+        };
+
+        let mut color = [0.0f32; 3];
+        let mut peak = 0;
+        let mut weight = 0.0;
+        let (mut center_x, mut center_y) = (0.0, 0.0);
+        let (mut ink_left, mut ink_top, mut ink_right, mut ink_bottom) = (u32::MAX, u32::MAX, 0, 0);
+        for y in top as u32..y_end {
+            for x in left as u32..x_end {
+                let value = brightness(x, y);
+                if value <= INK_THRESHOLD {
+                    continue;
+                }
+                let i = ((y * width + x) * 4) as usize;
+                color[0] += pixels[i] as f32;
+                color[1] += pixels[i + 1] as f32;
+                color[2] += pixels[i + 2] as f32;
+                peak = peak.max(value);
+                weight += value as f32;
+                center_x += (x as f32 + 0.5) * value as f32;
+                center_y += (y as f32 + 0.5) * value as f32;
+                ink_left = ink_left.min(x);
+                ink_top = ink_top.min(y);
+                ink_right = ink_right.max(x + 1);
+                ink_bottom = ink_bottom.max(y + 1);
+            }
+        }
+        if ink_left == u32::MAX {
+            return None;
+        }
+
+        // a cell sized window around the middle of the ink, the glyphs don't have to be at the
+        // same place in the cell. Every sample is the mean of the pixels it covers, partly
+        // covered pixels count partly
+        let window_left = center_x / weight - cell_w / 2.0;
+        let window_top = center_y / weight - cell_h / 2.0;
+        let sample_w = cell_w / self.glyph_width as f32;
+        let sample_h = cell_h / self.glyph_height as f32;
+        let mut samples = Vec::with_capacity((self.glyph_width * self.glyph_height) as usize);
+        for sample_y in 0..self.glyph_height {
+            let y0 = (window_top + sample_y as f32 * sample_h).max(top);
+            let y1 = (window_top + (sample_y + 1) as f32 * sample_h).min(y_end as f32);
+            for sample_x in 0..self.glyph_width {
+                let x0 = (window_left + sample_x as f32 * sample_w).max(left);
+                let x1 = (window_left + (sample_x + 1) as f32 * sample_w).min(x_end as f32);
+                let mut sum = 0.0;
+                if x1 > x0 && y1 > y0 {
+                    for y in y0 as u32..y1.ceil() as u32 {
+                        let part_y = y1.min(y as f32 + 1.0) - y0.max(y as f32);
+                        for x in x0 as u32..x1.ceil() as u32 {
+                            let part_x = x1.min(x as f32 + 1.0) - x0.max(x as f32);
+                            sum += brightness(x, y) as f32 * part_x * part_y;
+                        }
+                    }
+                }
+                let mean = sum / (sample_w * sample_h);
+                samples.push((mean / peak as f32 * MAX_LEVEL as f32).round().min(MAX_LEVEL as f32) as u8);
+            }
+        }
+        let ink_w = (ink_right - ink_left) as f32;
+        let ink_h = (ink_bottom - ink_top) as f32;
+        let bounds = [
+            (ink_left as f32 - left) / cell_w,
+            (ink_top as f32 - top) / cell_h,
+            ink_w / cell_w,
+            ink_h / cell_h,
+        ];
+        Some(CellInk { samples, bounds, color })
+    }
+}
+
+pub fn read_page(font: &FmsFont, pixels: &[u8], width: u32, height: u32) -> FmsPage {
+    let mut page = FmsPage { lines: vec![], cells: vec![], unknown: 0 };
+    for row in 0..font.rows {
+        let mut line = String::new();
+        let mut cells = Vec::with_capacity(font.columns as usize);
+        for column in 0..font.columns {
+            let cell = match font.sample_cell(pixels, width, height, column, row) {
+                None => {
+                    line.push(' ');
+                    cells.push(FmsCell { char: ' ', color: "".to_string(), small: false });
+                    continue;
+                }
+                Some(cell) => cell
+            };
+            // the closest glyph by shape and place in the cell
+            let mut best: Option<(usize, f32)> = None;
+            for (index, glyph) in font.samples.iter().enumerate() {
+                let shape = distance(glyph, &cell.samples);
+                let bounds = font.glyphs[index].bounds.iter().zip(&cell.bounds).map(|(a, b)| (a - b).abs()).sum::<f32>();
+                if shape > MAX_DISTANCE || bounds > MAX_BOUNDS_DIFFERENCE {
+                    continue;
+                }
+                let score = shape + bounds * BOUNDS_WEIGHT;
+                if best.map(|(_, best_score)| score < best_score).unwrap_or(true) {
+                    best = Some((index, score));
+                }
+            }
+            let (char, small) = match best {
+                Some((index, _)) => (font.glyphs[index].char, font.glyphs[index].small),
+                None => {
+                    page.unknown += 1;
+                    (UNKNOWN_CHAR, false)
+                }
+            };
+            line.push(char);
+            cells.push(FmsCell { char, color: closest_color(cell.color).to_string(), small });
+        }
+        page.lines.push(line);
+        page.cells.push(cells);
+    }
+    page
+}
+
+// mean difference of the levels, 0 - 1
+fn distance(a: &[u8], b: &[u8]) -> f32 {
+    let sum: u32 = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b) as u32).sum();
+    sum as f32 / (a.len().max(1) as f32 * MAX_LEVEL as f32)
+}
+
+// the hue matters, not the brightness, anti-aliased edges are darker
+fn closest_color(sum: [f32; 3]) -> &'static str {
+    let max = sum[0].max(sum[1]).max(sum[2]).max(1.0);
+    let color = [sum[0] / max, sum[1] / max, sum[2] / max];
+    PALETTE.iter().min_by(|(_, a), (_, b)| {
+        color_distance(&color, a).partial_cmp(&color_distance(&color, b)).unwrap()
+    }).map(|(name, _)| *name).unwrap()
+}
+
+fn color_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn default_area() -> [[f32; 2]; 2] {
+    [[0.0, 0.0], [1.0, 1.0]]
+}
+
+fn default_columns() -> u32 {
+    COLUMNS
+}
+
+fn default_rows() -> u32 {
+    ROWS
+}
+
+fn default_glyph_width() -> u32 {
+    GLYPH_WIDTH
+}
+
+fn default_glyph_height() -> u32 {
+    GLYPH_HEIGHT
+}
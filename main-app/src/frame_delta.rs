@@ -0,0 +1,89 @@
+use crate::capture_backend::RawCapture;
+use crate::frame_encoder;
+use crate::frame_encoder::EncoderSettings;
+use crate::image_process::ImageProcess;
+
+// Binary frames for subscribers that asked for deltas ("delta": true in IMAGESUBSCRIBE).
+// Every message starts with the same header, numbers are little endian:
+//
+//  magic      4 bytes  "RFT1"
+//  kind       u8       0 = keyframe, 1 = delta
+//  format     u8       0 = png, 1 = jpeg, 2 = webp, 3 = qoi
+//  tile_size  u16
+//  width      u16      size of the whole frame
+//  height     u16
+//  seq        u32      counts the frames sent to this subscriber
+//  tiles      u16      number of tiles that follow, 0 for keyframes
+//
+// A keyframe is followed by the whole frame as an image. A delta is followed by its tiles, every
+// tile is x u16, y u16, len u32 and len bytes of image that has to be drawn at x, y over the
+// previous frame. Tiles at the right and bottom edge can be smaller than tile_size.
+
+pub const MAGIC: &[u8; 4] = b"RFT1";
+pub const TILE_SIZE: u32 = 32;
+pub const HEADER_LEN: usize = 18;
+const KIND_KEYFRAME: u8 = 0;
+const KIND_DELTA: u8 = 1;
+
+fn write_header(out: &mut Vec<u8>, kind: u8, format: u8, width: u32, height: u32, seq: u32, tiles: u16) {
+    out.extend_from_slice(MAGIC);
+    out.push(kind);
+    out.push(format);
+    out.extend_from_slice(&(TILE_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(width as u16).to_le_bytes());
+    out.extend_from_slice(&(height as u16).to_le_bytes());
+    out.extend_from_slice(&seq.to_le_bytes());
+    out.extend_from_slice(&tiles.to_le_bytes());
+}
+
+pub fn encode_keyframe(frame: &RawCapture, encoded: &[u8], settings: EncoderSettings, seq: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + encoded.len());
+    write_header(&mut out, KIND_KEYFRAME, settings.format.id(), frame.width, frame.height, seq, 0);
+    out.extend_from_slice(encoded);
+    out
+}
+
+// None if the frames can't be diffed (size changed), Some(empty) if nothing changed
+pub fn changed_tiles(previous: &RawCapture, current: &RawCapture) -> Option<Vec<[u32; 4]>> {
+    if previous.width != current.width || previous.height != current.height {
+        return None;
+    }
+    let stride = current.width as usize * 4;
+    let mut tiles: Vec<[u32; 4]> = vec![];
+    let mut y = 0;
+    while y < current.height {
+        let h = TILE_SIZE.min(current.height - y);
+        let mut x = 0;
+        while x < current.width {
+            let w = TILE_SIZE.min(current.width - x);
+            let changed = (y..y + h).any(|row| {
+                let start = row as usize * stride + x as usize * 4;
+                let end = start + w as usize * 4;
+                previous.pixels[start..end] != current.pixels[start..end]
+            });
+            if changed {
+                tiles.push([x, y, w, h]);
+            }
+            x += TILE_SIZE;
+        }
+        y += TILE_SIZE;
+    }
+    Some(tiles)
+}
+
+// None if a tile can't be encoded
+pub fn encode_delta(current: &RawCapture, tiles: &Vec<[u32; 4]>, settings: EncoderSettings,
+                    seq: u32) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    write_header(&mut out, KIND_DELTA, settings.format.id(), current.width, current.height, seq,
+                 tiles.len() as u16);
+    for [x, y, w, h] in tiles {
+        let tile = ImageProcess::crop_capture(current, [[*x as i32, *y as i32], [*w as i32, *h as i32]]).ok()?;
+        let encoded = frame_encoder::encode(&tile.pixels, tile.width, tile.height, settings).ok()?;
+        out.extend_from_slice(&(*x as u16).to_le_bytes());
+        out.extend_from_slice(&(*y as u16).to_le_bytes());
+        out.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        out.extend_from_slice(&encoded);
+    }
+    Some(out)
+}
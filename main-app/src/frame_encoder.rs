@@ -0,0 +1,114 @@
+use image::{ExtendedColorType, ImageEncoder};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::qoi::QoiEncoder;
+use image::codecs::webp::WebPEncoder;
+use serde::{Deserialize, Serialize};
+
+// Encoders for the streamed frames. All of them take rgba8 pixels. PNG and QOI are lossless and
+// the cheapest to decode, lossless WebP is the smallest but the slowest to encode, JPEG trades
+// fidelity for size with the quality setting (the alpha channel is dropped).
+
+pub const DEFAULT_QUALITY: u8 = 80;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+    Qoi,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct EncoderSettings {
+    pub format: FrameFormat,
+    // 1-100, only used by jpeg
+    pub quality: u8,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings { format: FrameFormat::Png, quality: DEFAULT_QUALITY }
+    }
+}
+
+impl FrameFormat {
+    pub fn from_name(name: &str) -> Option<FrameFormat> {
+        return match name.to_lowercase().as_str() {
+            "png" => Some(FrameFormat::Png),
+            "jpeg" | "jpg" => Some(FrameFormat::Jpeg),
+            "webp" => Some(FrameFormat::Webp),
+            "qoi" => Some(FrameFormat::Qoi),
+            _ => None
+        };
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            FrameFormat::Png => "image/png",
+            FrameFormat::Jpeg => "image/jpeg",
+            FrameFormat::Webp => "image/webp",
+            FrameFormat::Qoi => "image/qoi",
+        }
+    }
+
+    // the id used in binary frame headers
+    pub fn id(&self) -> u8 {
+        match self {
+            FrameFormat::Png => 0,
+            FrameFormat::Jpeg => 1,
+            FrameFormat::Webp => 2,
+            FrameFormat::Qoi => 3,
+        }
+    }
+}
+
+pub fn encode(pixels: &[u8], width: u32, height: u32, settings: EncoderSettings) -> Result<Vec<u8>, u8> {
+    return match settings.format {
+        FrameFormat::Png => encode_png(pixels, width, height),
+        FrameFormat::Jpeg => {
+            let rgb: Vec<u8> = pixels.chunks_exact(4).flat_map(|px| [px[0], px[1], px[2]]).collect();
+            let mut out = Vec::new();
+            let quality = settings.quality.clamp(1, 100);
+            match JpegEncoder::new_with_quality(&mut out, quality)
+                .write_image(&rgb, width, height, ExtendedColorType::Rgb8) {
+                Ok(_) => Ok(out),
+                Err(_) => Err(1)
+            }
+        }
+        FrameFormat::Webp => {
+            let mut out = Vec::new();
+            match WebPEncoder::new_lossless(&mut out)
+                .write_image(pixels, width, height, ExtendedColorType::Rgba8) {
+                Ok(_) => Ok(out),
+                Err(_) => Err(1)
+            }
+        }
+        FrameFormat::Qoi => {
+            let mut out = Vec::new();
+            match QoiEncoder::new(&mut out)
+                .write_image(pixels, width, height, ExtendedColorType::Rgba8) {
+                Ok(_) => Ok(out),
+                Err(_) => Err(1)
+            }
+        }
+    };
+}
+
+fn encode_png(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, u8> {
+    let mut outputbuf = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut outputbuf, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = match encoder.write_header() {
+            Ok(writer) => writer,
+            Err(_) => return Err(1)
+        };
+        if writer.write_image_data(pixels).is_err() {
+            return Err(1);
+        }
+    }
+    Ok(outputbuf)
+}
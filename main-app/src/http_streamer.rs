@@ -1,73 +1,117 @@
-use std::{mem, thread};
-use std::process::Child;
+use std::collections::HashMap;
+use std::thread;
 use std::sync::{Arc, Mutex};
 use actix_cors::Cors;
 use actix_web::{get, web, App, HttpResponse, HttpServer, Responder, HttpRequest, Error};
+use actix_web::cookie::{time, Cookie, SameSite};
+use actix_web::dev::{Service, ServiceRequest};
 use actix_web_actors::ws;
-use actix::{Actor, Addr, AsyncContext, Handler, Message, StreamHandler};
-use crate::{api_communicator, comm_sender, debug_logger, ImageProcess};
+use actix::{Actor, ActorContext, AsyncContext, Handler, Message, StreamHandler};
+use crate::{comm_sender, debug_logger, ImageProcess};
 use qstring::QString;
 use actix_files::Files;
-use crossbeam_channel::{bounded};
-use crate::config_handler::{ConfigHandler, get_static_folder};
-use crate::image_process::{InstrumentRgb, POPOUT_HEIGHT, POPOUT_WIDTH};
+use crossbeam_channel::{bounded, select};
+use crate::config_handler::{ConfigHandler, get_http_port, get_pairings_file, get_static_folder, get_tls_cert_file, get_tls_key_file};
+use crate::image_process::{InstrumentRgb, PopOutWindow, POPOUT_HEIGHT, POPOUT_WIDTH};
 use serde::{Deserialize, Serialize};
-use windows::Win32::Foundation::{HWND, POINT, RECT};
-use windows::Win32::Graphics::Gdi::ClientToScreen;
-use windows::Win32::UI::WindowsAndMessaging::{GetCursorPos, GetForegroundWindow, GetSystemMetrics, GetWindowRect, SetCursorPos, SetForegroundWindow, SM_CXSCREEN, SM_CYSCREEN};
-use windows::Win32::UI::Input::KeyboardAndMouse::{mouse_event, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, SetFocus};
-use crate::addon_config::{AddonConfig};
+use crate::addon_config::{AddonConfig, McduSide};
+use crate::bridge_protocol::{BridgeCommand, BridgeReply, BridgeRequest, BridgeResponse, LEGACY_CONNECT, LEGACY_CONNECTED, PROTOCOL_VERSION, VAR_SUBSCRIPTION_VERSION};
+use crate::bridge_supervisor::BridgeSupervisor;
+use crate::capture_backend::{backend, HideStrategy};
+use crate::comm_sender::{BridgeDispatcher, BridgeStatus};
+use crate::fms_ocr;
+use crate::fms_ocr::FmsFont;
+use crate::frame_encoder;
+use crate::frame_encoder::{EncoderSettings, FrameFormat};
+use crate::image_streamer;
+use crate::image_streamer::{ClientStatus, FrameMessage, FrameSink, ImageStreamer, SubscribeRequest};
+use crate::mjpeg_stream;
+use crate::mjpeg_stream::{FrameQueue, MjpegBody};
+use crate::input_injector;
+use crate::input_injector::{InputInjector, InputMethod, RecordingInjector};
+use crate::touch_gestures::{GestureEvent, GestureInput};
+use crate::output_vars::OutputVars;
+use crate::pairing;
+use crate::pairing::{PairingStore, SESSION_COOKIE};
+use crate::tls;
+use crate::tls::{TlsIdentity, TLS_PORT};
+use crate::instrument_settings::InstrumentSettingsStore;
+use crate::window_layouts::window_layouts;
+#[cfg(feature = "webrtc")]
+use crate::webrtc_transport;
+#[cfg(feature = "webrtc")]
+use crate::webrtc_transport::{Negotiated, OfferRequest, WebRtcTransport};
 #[derive(Serialize, Deserialize)]
 struct StatusResponse {
     bridge_status: BridgeStatus,
     settings: ConfigHandler,
-}
-#[derive(Serialize, Deserialize)]
-#[derive(Clone)]
-pub struct BridgeStatus {
-    pub started: bool,
-    pub connected: bool,
-    pub comm: bool,
+    image_clients: Vec<ClientStatus>,
 }
 
 
 pub struct ImageSubscriptionStatus {
-    pub thread_started: Arc<Mutex<bool>>,
+    pub streamer: Arc<ImageStreamer>,
     pub selected_hwnd: Arc<Mutex<isize>>,
-    pub img_sub_list: Arc<Mutex<Vec<Addr<MyWs>>>>,
     pub display_crop: Arc<Mutex<[[i32; 2]; 2]>>,
     pub instrument_search: Mutex<String>,
+    // None if the transport couldn't start
+    #[cfg(feature = "webrtc")]
+    pub webrtc: Option<Arc<WebRtcTransport>>,
 }
 
 
 struct AppState {
-    last_bytes: Mutex<Vec<u8>>,
     main_html_string: &'static str,
     icon_png: &'static [u8],
     instrument_list: Mutex<Vec<InstrumentRgb>>,
     config: Arc<Mutex<ConfigHandler>>,
-    child_process: Mutex<Option<Child>>,
+    bridge_supervisor: Arc<BridgeSupervisor>,
     //selected_hwnd: Mutex<isize>,
-    command_sender: crossbeam_channel::Sender<String>,
-    command_receiver: crossbeam_channel::Receiver<String>,
-    comm_sender: crossbeam_channel::Sender<String>,
-    comm_receiver: crossbeam_channel::Receiver<String>,
+    bridge: Arc<BridgeDispatcher>,
+    command_receiver: crossbeam_channel::Receiver<BridgeRequest>,
     bridge_status: Mutex<BridgeStatus>,
     img_sub_status: ImageSubscriptionStatus,
     current_aircraft: Mutex<String>,
+    // the pop-out showing the MCDU of a side (set_hwnd_settings?side=), its button presses wake it
+    mcdu_popouts: Mutex<HashMap<McduSide, isize>>,
+    output_vars: Arc<OutputVars>,
+    // swapped when the input method changes
+    injector: Arc<Mutex<Arc<dyn InputInjector>>>,
+    instrument_settings: InstrumentSettingsStore,
+    pairing: Arc<PairingStore>,
+    // the certificate served on TLS_PORT, None without https
+    tls_cert: Option<Vec<u8>>,
     addon_config: AddonConfig,
     log_str: Option<Arc<Mutex<String>>>,
 }
 
 
+// Bridge queries block until the reply arrives, so they run on the blocking pool: the bridge's
+// ws actor can live on the same worker and has to stay free to deliver the reply.
+async fn query_aircraft(data: &web::Data<AppState>) -> String {
+    let bridge = Arc::clone(&data.bridge);
+    web::block(move || comm_sender::get_aircraft(&bridge)).await.unwrap()
+}
+
+async fn query_status(data: &web::Data<AppState>) -> BridgeStatus {
+    let mut brid_status = data.bridge_status.lock().unwrap().clone();
+    let health = data.bridge_supervisor.health();
+    brid_status.started = data.bridge_supervisor.is_started();
+    brid_status.crash_count = health.crash_count;
+    brid_status.restart_count = health.restart_count;
+    brid_status.last_exit_code = health.last_exit_code;
+    let bridge = Arc::clone(&data.bridge);
+    web::block(move || {
+        comm_sender::get_status(&mut brid_status, &bridge);
+        brid_status
+    }).await.unwrap()
+}
+
 #[get("/")]
 async fn index(data: web::Data<AppState>) -> impl Responder {
-    let child_proc = data.child_process.lock().unwrap();
-    if child_proc.is_none() {
-        drop(child_proc);
+    if !data.bridge_supervisor.is_started() {
         return HttpResponse::Ok().body("Bridge not running");
     } else {
-        drop(child_proc);
         HttpResponse::Ok().body(data.main_html_string)
     }
 }
@@ -77,25 +121,30 @@ async fn icon_png(data: web::Data<AppState>) -> impl Responder {
     HttpResponse::Ok().body(data.icon_png)
 }
 
+// the certificate of the https server, a device installs it to trust the self-signed one
+#[get("/reachfms.crt")]
+async fn tls_cert(data: web::Data<AppState>) -> HttpResponse {
+    return match &data.tls_cert {
+        Some(cert) => HttpResponse::Ok().content_type("application/x-x509-ca-cert").body(cert.clone()),
+        None => HttpResponse::NotFound().body("https is off")
+    };
+}
+
 
 #[get("/start_server")]
 async fn start_server(data: web::Data<AppState>) -> impl Responder {
     debug_logger::log("Starting SimConnector", &data.log_str);
-    let mut child_proc = data.child_process.lock().unwrap();
-    if child_proc.is_none() {
-        *child_proc = std::thread::spawn(move || {
-            Option::from(api_communicator::start_bridge_process())
-        }).join().unwrap();
-        debug_logger::log("SimConnector started", &data.log_str);
-    } else {
-        drop(child_proc);
+    let conf = data.config.lock().unwrap();
+    let bridge_path = conf.bridge_path.clone();
+    let bridge_args = conf.bridge_args.clone();
+    drop(conf);
+    let supervisor = Arc::clone(&data.bridge_supervisor);
+    let started = web::block(move || supervisor.start(bridge_path, bridge_args)).await.unwrap();
+    if !started {
         debug_logger::log("SimConnector was already running...", &data.log_str);
         return HttpResponse::Ok().body("Already running");
     }
-    drop(child_proc);
-    let mut brstat = data.bridge_status.lock().unwrap();
-    brstat.started = true;
-    drop(brstat);
+    debug_logger::log("SimConnector started", &data.log_str);
 
     HttpResponse::Ok().body("started")
 }
@@ -103,8 +152,7 @@ async fn start_server(data: web::Data<AppState>) -> impl Responder {
 #[get("/bridge_status")]
 async fn bridge_status(data: web::Data<AppState>) -> impl Responder {
     debug_logger::log("Getting bridge status", &data.log_str);
-    let mut brid_status = data.bridge_status.lock().unwrap().clone();
-    comm_sender::get_status(&mut brid_status, data.command_sender.clone(), data.comm_receiver.clone());
+    let brid_status = query_status(&data).await;
 
     debug_logger::log(&*format!("Getting comm_sender status connected: {}, conn: {}",
                                 &brid_status.connected, &brid_status.comm), &data.log_str);
@@ -116,26 +164,23 @@ async fn bridge_status(data: web::Data<AppState>) -> impl Responder {
 async fn stop_server(data: web::Data<AppState>) -> impl Responder {
     debug_logger::log("Stopping SimConnector...", &data.log_str);
 
-    let mut child_proc = data.child_process.lock().unwrap();
-    if !child_proc.is_none() {
-        data.command_sender.send("CloseBridge".to_string()).expect("cannot send CloseBridge");
-        *child_proc = Option::None;
-    } else {
+    let supervisor = Arc::clone(&data.bridge_supervisor);
+    let bridge = Arc::clone(&data.bridge);
+    // waits until the process exited, so this runs on the blocking pool as well
+    let stopped = web::block(move || supervisor.stop(&bridge)).await.unwrap();
+    if !stopped {
         debug_logger::log("Simconnector wasn't running...", &data.log_str);
-        drop(child_proc);
         return HttpResponse::Ok().body("Not running");
     }
-    drop(child_proc);
-    let mut brid_status = data.bridge_status.lock().unwrap().clone();
-    brid_status.started = false;
-    drop(brid_status);
+    debug_logger::log("SimConnector stopped", &data.log_str);
 
     HttpResponse::Ok().body("stopped")
 }
 
 #[get("/reconnect")]
 async fn bridge_reconnect(data: web::Data<AppState>) -> impl Responder {
-    let resp: String = comm_sender::reconnect(data.command_sender.clone(), data.comm_receiver.clone());
+    let bridge = Arc::clone(&data.bridge);
+    let resp: String = web::block(move || comm_sender::reconnect(&bridge)).await.unwrap();
     debug_logger::log("reconnecting server...", &data.log_str);
     HttpResponse::Ok().body(resp)
 }
@@ -165,22 +210,69 @@ async fn set_hwnd_settings(req: HttpRequest, data: web::Data<AppState>) -> impl
         "false" => Option::from(false),
         _ => { None }
     };
+    // side=capt/fo/3 shows that side's MCDU, side=none shows none
+    let side = match qs.clone().get("side") {
+        None => None,
+        Some("none") => Some(None),
+        Some(text) => match McduSide::from_name(text) {
+            None => return HttpResponse::Ok().body("invalid side"),
+            Some(side) => Some(Some(side))
+        }
+    };
+    // crop=x,y,w,h in pop-out pixels, crop=none goes back to the crop of the aircraft
+    let crop = match qs.clone().get("crop") {
+        None => None,
+        Some("none") => Some(None),
+        Some(text) => match parse_crop(text) {
+            None => return HttpResponse::Ok().body("invalid crop"),
+            Some(crop) => Some(Some(crop))
+        }
+    };
+    let aircraft = data.current_aircraft.lock().unwrap().clone();
     let mut state_instruments = data.instrument_list.lock().unwrap();
-    let mut count: usize = 0;
-    for instr in state_instruments.iter() {
-        if instr.hwnd == for_hwnd {
-            if auto_hide.is_some() {
-                state_instruments[count].auto_hide = auto_hide.unwrap();
-            }
-            if excluded.is_some() {
-                state_instruments[count].excluded = excluded.unwrap();
-            }
-            return HttpResponse::Ok().body("ok");
+    let instr = match state_instruments.iter_mut().find(|instr| instr.hwnd == for_hwnd) {
+        None => return HttpResponse::Ok().body("can't find hwnd"),
+        Some(instr) => instr
+    };
+    if let Some(auto_hide) = auto_hide {
+        instr.auto_hide = auto_hide;
+    }
+    if let Some(excluded) = excluded {
+        instr.excluded = excluded;
+    }
+    if let Some(side) = side {
+        let mut mcdu_popouts = data.mcdu_popouts.lock().unwrap();
+        mcdu_popouts.retain(|_, hwnd| *hwnd != for_hwnd);
+        if let Some(side) = side {
+            mcdu_popouts.insert(side, for_hwnd);
+        }
+    }
+    // kept for the next time this pop-out of the aircraft is open
+    data.instrument_settings.update(&aircraft, &instr.instrument, |stored| {
+        if auto_hide.is_some() {
+            stored.auto_hide = auto_hide;
+        }
+        if excluded.is_some() {
+            stored.excluded = excluded;
         }
-        count += 1;
+        if let Some(crop) = crop {
+            stored.crop = crop;
+        }
+    });
+    if crop.is_some() && *data.img_sub_status.selected_hwnd.lock().unwrap() == for_hwnd {
+        *data.img_sub_status.display_crop.lock().unwrap() = instrument_crop(&data, &instr.instrument, &aircraft);
     }
     drop(state_instruments);
-    HttpResponse::Ok().body("can't find hwnd")
+    HttpResponse::Ok().body("ok")
+}
+
+fn parse_crop(text: &str) -> Option<[[i32; 2]; 2]> {
+    let values: Vec<i32> = text.split(',').map(|value| value.trim().parse::<i32>().ok())
+        .collect::<Option<Vec<i32>>>()?;
+    return match values[..] {
+        [x, y, w, h] if x >= 0 && y >= 0 && w > 0 && h > 0 => Some([[x, y], [w, h]]),
+        _ => None
+    };
 }
 
 #[get("/set_settings")]
@@ -220,11 +312,43 @@ async fn set_settings(req: HttpRequest, data: web::Data<AppState>) -> impl Respo
         _ => { false }
     };
 
+    // only changed when given, the settings page doesn't send them
+    let image_format = FrameFormat::from_name(qs.clone().get("format").unwrap_or(""));
+    let image_quality = qs.clone().get("quality").and_then(|q| q.parse::<u8>().ok());
+    let min_fps = qs.clone().get("minfps").and_then(|fps| fps.parse::<u16>().ok());
+    let idle_capture = qs.clone().get("idlecapture").map(|idle| idle == "true");
+    let hide_strategy = HideStrategy::from_name(qs.clone().get("hidestrategy").unwrap_or(""));
+    let input_method = InputMethod::from_name(qs.clone().get("inputmethod").unwrap_or(""));
+    // the server is bound at start, it's served after a restart
+    let tls_enabled = qs.clone().get("https").map(|https| https == "true");
+
     if refresh < 50 {
         refresh = 50;
     }
 
     let mut conf = data.config.lock().unwrap();
+    if let Some(image_format) = image_format {
+        conf.image_format = image_format;
+    }
+    if let Some(image_quality) = image_quality {
+        conf.image_quality = image_quality.clamp(1, 100);
+    }
+    if let Some(min_fps) = min_fps {
+        conf.min_fps = min_fps.max(1);
+    }
+    if let Some(idle_capture) = idle_capture {
+        conf.idle_capture = idle_capture;
+    }
+    if let Some(hide_strategy) = hide_strategy {
+        conf.hide_strategy = hide_strategy;
+    }
+    if let Some(input_method) = input_method {
+        conf.input_method = input_method;
+        *data.injector.lock().unwrap() = select_injector(input_method, &data.log_str);
+    }
+    if let Some(tls_enabled) = tls_enabled {
+        conf.tls_enabled = tls_enabled;
+    }
     conf.refresh_rate = refresh;
     conf.auto_hide = auto_hide;
     conf.max_fps = max_fps;
@@ -248,14 +372,14 @@ async fn save_debug(data: web::Data<AppState>) -> impl Responder {
 
 #[get("/status")]
 async fn status(data: web::Data<AppState>) -> impl Responder {
-    let mut brid_status = data.bridge_status.lock().unwrap().clone();
-    comm_sender::get_status(&mut brid_status, data.command_sender.clone(), data.comm_receiver.clone());
+    let brid_status = query_status(&data).await;
 
 
     let sting = data.config.lock().unwrap();
     let resp: StatusResponse = StatusResponse {
         settings: sting.clone(),
         bridge_status: brid_status.clone(),
+        image_clients: data.img_sub_status.streamer.client_status(),
     };
     drop(sting);
     drop(brid_status);
@@ -269,11 +393,15 @@ async fn mcdu_btn(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
     let query_str = req.query_string();
     let qs = QString::from(query_str);
     let btn_id = qs.clone().get("btn").unwrap_or("").to_string();
-    let instr = data.img_sub_status.instrument_search.lock().unwrap().clone();
+    let side = McduSide::from_query(qs.clone().get("usefo").unwrap_or("false"),
+                                    qs.clone().get("side").unwrap_or(""));
+    let mut instr = data.img_sub_status.instrument_search.lock().unwrap().clone();
+    // no pop-out selected, the screen may come from output vars
+    if instr.is_empty() {
+        instr = data.current_aircraft.lock().unwrap().clone();
+    }
 
-    if instr.is_empty() {}
-    
-    let aircraft_var = data.addon_config.get_var(btn_id.replace("BTN:", ""), &instr);
+    let aircraft_var = data.addon_config.get_var(btn_id.replace("BTN:", ""), &instr, side);
 
     if aircraft_var == "" {
         debug_logger::log(&*format!("PLANE INSTRUMENT/FILE:: {}", &instr), &data.log_str);
@@ -281,10 +409,10 @@ async fn mcdu_btn(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
         return HttpResponse::Ok().body("Cant find lvar");
     }
 
-    if aircraft_var.contains(">") || aircraft_var.contains("K:") || aircraft_var.contains("H:") {
-        data.command_sender.send(format!("SM_SEND:CUSTOM_WASM:{}", aircraft_var)).expect("ERROR SENDING MESSAGE");
-    } else {
-        data.command_sender.send(format!("SM_SEND:CMD_BTN:{}", aircraft_var)).expect("ERROR SENDING MESSAGE");
+    data.bridge.send(BridgeCommand::for_button(aircraft_var));
+    // the screen of the pressed side is about to change
+    for hwnd in mcdu_popouts(&data, side) {
+        data.img_sub_status.streamer.wake(hwnd);
     }
 
 
@@ -292,12 +420,32 @@ async fn mcdu_btn(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
 }
 
 
+// the pop-out set for the side, the selected one for the captain's MCDU; without one all the
+// pop-outs found, one of them shows it
+fn mcdu_popouts(data: &web::Data<AppState>, side: McduSide) -> Vec<isize> {
+    if let Some(hwnd) = data.mcdu_popouts.lock().unwrap().get(&side) {
+        return vec![*hwnd];
+    }
+    let selected = *data.img_sub_status.selected_hwnd.lock().unwrap();
+    if side == McduSide::Captain && selected != 0 {
+        return vec![selected];
+    }
+    data.instrument_list.lock().unwrap().iter()
+        .filter(|instr| !instr.excluded)
+        .map(|instr| instr.hwnd)
+        .collect()
+}
+
+
 #[get("/touch_event")]
 async fn touch_event(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
     let query_str = req.query_string();
     let qs = QString::from(query_str);
     // let hwnd = qs.clone().get("hwnd").unwrap_or("0")
     //     .parse::<isize>().unwrap_or(0);
+    // x and y go from 0 to 1 across the picture, x_pos and y_pos are its pixels (older clients)
+    let x = qs.clone().get("x").and_then(|x| x.parse::<f64>().ok());
+    let y = qs.clone().get("y").and_then(|y| y.parse::<f64>().ok());
     let x_pos = qs.clone().get("x_pos").unwrap_or("0").parse::<u16>().unwrap_or(0);
     let y_pos = qs.clone().get("y_pos").unwrap_or("0").parse::<u16>().unwrap_or(0);
     let sleep_ms: u64 = qs.clone().get("sleep_ms").unwrap_or("100").
@@ -305,69 +453,38 @@ async fn touch_event(req: HttpRequest, data: web::Data<AppState>) -> HttpRespons
 
     let sleep_time: core::time::Duration = core::time::Duration::from_millis(sleep_ms);
 
-    let hwnd = data.img_sub_status.selected_hwnd.lock().unwrap().clone();
+    // the picture is the selected pop-out with the shared crop
+    let (hwnd, area) = match data.img_sub_status.streamer.touch_target(None) {
+        None => return HttpResponse::Ok().body("not executed"),
+        Some(target) => target
+    };
+    let (x, y) = match (x, y) {
+        (Some(x), Some(y)) => (x, y),
+        _ => area.normalize(x_pos as i32, y_pos as i32)
+    };
+    let (x_pos, y_pos) = area.to_popout(x, y);
 
 
     //thread::sleep(std::time::Duration::from_millis(4000));
     debug_logger::log(&*format!("Touch event: x:{} y:{} hwnd:{}, sleep_ms:{}",
                                 &x_pos, &y_pos, &hwnd, sleep_ms), &data.log_str);
 
-    if hwnd != 0 {
-        unsafe {
-            let hwnda_to_use: HWND = mem::transmute(hwnd);
-            let mut original_pos = POINT { x: 0, y: 0 };
-            if let Ok(_) = GetCursorPos(&mut original_pos as *mut POINT) {
-                debug_logger::log(&*format!("Original cursor position: x:{} y:{}", &original_pos.x, &original_pos.y), &data.log_str);
-
-                // Convert client coordinates (x, y) to screen coordinates
-                let mut click_point = POINT { x: x_pos as i32, y: y_pos as i32 };
-                let _ = ClientToScreen(hwnda_to_use, &mut click_point);
-                debug_logger::log(&*format!("Target ursor position: x:{} y:{}", &click_point.x, &click_point.y), &data.log_str);
-
-                let focused_window = GetForegroundWindow();
-                if focused_window != hwnda_to_use {
-                    let _ = SetForegroundWindow(hwnda_to_use);
-                    SetFocus(hwnda_to_use);
-                }
-
-                let mut window_rect: RECT = RECT {
-                    left: 0,
-                    top: 0,
-                    right: 0,
-                    bottom: 0,
-                };
-                GetWindowRect(hwnda_to_use, &mut window_rect).expect("Can't get window rect");
-
-                let sx = GetSystemMetrics(SM_CXSCREEN);
-                let sy = GetSystemMetrics(SM_CYSCREEN);
-                let move_x = window_rect.left + x_pos as i32;
-                let move_y = window_rect.top + y_pos as i32;
-
-                if click_point.x > sx || click_point.y > sy {
-                    return HttpResponse::Ok().body("error: auto_hide enabled!");
-                }
-
-                let absolute_x = click_point.x * 65536 / sx;
-                let absolute_y = click_point.y * 65536 / sy;
-                debug_logger::log(&*format!("WNDOW POS: x:{} y:{}", &window_rect.left, &window_rect.top), &data.log_str);
-                debug_logger::log(&*format!("MOVING TO: x:{} y:{}", &move_x, &move_y), &data.log_str);
-
-                SetCursorPos(move_x, move_y).expect("Can't set cursor pos");
-                thread::sleep(sleep_time);
-                mouse_event(MOUSEEVENTF_LEFTDOWN
-                            , absolute_x, absolute_y, 0, 0);
-                thread::sleep(sleep_time);
-                mouse_event(MOUSEEVENTF_LEFTUP
-                            , absolute_x, absolute_y, 0, 0);
-
-
-                thread::sleep(core::time::Duration::from_millis(50));
-                SetCursorPos(original_pos.x, original_pos.y).expect("Can't set cursor pos");
-            }
+    data.img_sub_status.streamer.wake(hwnd);
+    let injector = Arc::clone(&data.injector.lock().unwrap());
+    // a backend that brings the pop-out to the front, a hidden one goes back behind the sim
+    let rehide = injector.takes_focus() && backend().is_hidden(hwnd);
+    let strategy = data.config.lock().unwrap().hide_strategy;
+    let result = web::block(move || {
+        let result = input_injector::click(injector.as_ref(), hwnd, x_pos, y_pos, sleep_time);
+        if rehide {
+            ImageProcess::hide_window(hwnd, strategy);
         }
-        return HttpResponse::Ok().body("ok");
-    }
-    HttpResponse::Ok().body("not executed")
+        result
+    }).await.unwrap_or_else(|e| Err(e.to_string()));
+    return match result {
+        Ok(_) => HttpResponse::Ok().body("ok"),
+        Err(e) => HttpResponse::Ok().body(e)
+    };
 }
 
 #[get("/var_test")]
@@ -377,11 +494,7 @@ async fn var_test(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
     let btn_id = qs.clone().get("btn").unwrap_or("").to_string();
 
 
-    if btn_id.contains(">") || btn_id.contains("K:") || btn_id.contains("H:") {
-        data.command_sender.send(format!("SM_SEND:CUSTOM_WASM:{}", btn_id)).expect("ERROR SENDING MESSAGE");
-    } else {
-        data.command_sender.send(format!("SM_SEND:CMD_BTN:{}", btn_id)).expect("ERROR SENDING MESSAGE");
-    }
+    data.bridge.send(BridgeCommand::for_button(&btn_id));
 
 
     HttpResponse::Ok().body("ok")
@@ -394,10 +507,159 @@ async fn restore_windows() -> HttpResponse {
     HttpResponse::Ok().body("ok")
 }
 
+// layouts of the current aircraft's pop-outs, saved under a name and put back with apply_layout
+#[get("/save_layout")]
+async fn save_layout(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    return layout_request(req, data, |name, popouts| window_layouts().save_layout(name, popouts)).await;
+}
+
+#[get("/apply_layout")]
+async fn apply_layout(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    return layout_request(req, data, |name, popouts| window_layouts().apply_layout(name, popouts)).await;
+}
+
+#[get("/delete_layout")]
+async fn delete_layout(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    return layout_request(req, data, |name, _| match window_layouts().delete_layout(name) {
+        true => Ok(0),
+        false => Err(format!("No layout named {}", name))
+    }).await;
+}
+
+#[get("/layouts")]
+async fn get_layouts(data: web::Data<AppState>) -> HttpResponse {
+    let aircraft: String = query_aircraft(&data).await;
+    set_current_aircraft(&data, &aircraft);
+    return HttpResponse::Ok().body(serde_json::to_string(&window_layouts().layout_names()).unwrap());
+}
+
+async fn layout_request<F>(req: HttpRequest, data: web::Data<AppState>, action: F) -> HttpResponse
+    where F: FnOnce(&str, &[PopOutWindow]) -> Result<usize, String> {
+    let qs = QString::from(req.query_string());
+    let name = match qs.get("name") {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => return HttpResponse::BadRequest().body("missing name")
+    };
+    let aircraft: String = query_aircraft(&data).await;
+    set_current_aircraft(&data, &aircraft);
+    let popouts = match ImageProcess::find_popup_windows() {
+        Ok(popouts) => popouts,
+        Err(_) => return HttpResponse::Ok().body("Can't find MSFS window")
+    };
+    return match action(&name, &popouts) {
+        Ok(..) => HttpResponse::Ok().body("ok"),
+        Err(e) => {
+            debug_logger::log(&e, &data.log_str);
+            HttpResponse::Ok().body(e)
+        }
+    };
+}
+
+// after pairing over https, to compare the certificate the browser shows with the one in the app
+const PAIRED_PAGE: &str = "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+    <title>ReachFMS</title></head><body style=\"font-family: sans-serif\"><p>Paired.</p>\
+    <p>SHA-256 fingerprint of the certificate:</p><p><code style=\"word-break: break-all\">{fingerprint}</code></p>\
+    <p>It has to be the one shown next to the QR code in the ReachFMS app.</p><p><a href=\"/\">Continue</a></p>\
+    </body></html>";
+
+#[get("/pair")]
+async fn pair(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+    let name = match qs.get("name") {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => req.headers().get("User-Agent").and_then(|agent| agent.to_str().ok())
+            .unwrap_or("unknown device").to_string()
+    };
+    let fingerprint = data.tls_cert.as_ref().map(|cert| tls::fingerprint(cert));
+    // a QR code made for another certificate, or for https that isn't served any more
+    if let Some(qr_fingerprint) = qs.get("fp") {
+        if !fingerprint.as_ref().map(|fingerprint| tls::same_fingerprint(fingerprint, qr_fingerprint)).unwrap_or(false) {
+            debug_logger::log(&*format!("Pairing refused, the QR code is for the certificate {}", qr_fingerprint),
+                              &data.log_str);
+            return HttpResponse::Conflict().body(format!(
+                "The QR code is for another certificate ({}), this server's is {}. Scan the QR code shown in the app again.",
+                tls::display_fingerprint(qr_fingerprint),
+                fingerprint.as_ref().map(|fingerprint| tls::display_fingerprint(fingerprint))
+                    .unwrap_or("none, https is off".to_string())));
+        }
+    }
+    let paired_at = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
+    return match data.pairing.pair(qs.get("token").unwrap_or(""), &name, paired_at) {
+        Ok(session) => {
+            debug_logger::log(&*format!("Device paired: {}", name), &data.log_str);
+            // the next device needs a new QR code
+            ConfigHandler::write_qr_code(&data.pairing.token(), fingerprint.as_deref());
+            let cookie = Cookie::build(SESSION_COOKIE, session)
+                .path("/")
+                .http_only(true)
+                .secure(req.connection_info().scheme() == "https")
+                .same_site(SameSite::Strict)
+                .max_age(time::Duration::days(400))
+                .finish();
+            match fingerprint {
+                Some(fingerprint) => HttpResponse::Ok().cookie(cookie).content_type("text/html; charset=utf-8")
+                    .body(PAIRED_PAGE.replace("{fingerprint}", &tls::display_fingerprint(&fingerprint))),
+                None => HttpResponse::Found().cookie(cookie).insert_header(("Location", "/")).finish()
+            }
+        }
+        Err(e) => {
+            debug_logger::log(&*format!("Pairing refused: {}", e), &data.log_str);
+            HttpResponse::Unauthorized().body(e)
+        }
+    };
+}
+
+#[get("/pairings")]
+async fn get_pairings(data: web::Data<AppState>) -> HttpResponse {
+    return HttpResponse::Ok().body(serde_json::to_string(&data.pairing.devices()).unwrap());
+}
+
+// id=<device id> or id=all, the devices have to scan the QR code again
+#[get("/pairings/revoke")]
+async fn revoke_pairing(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+    let result = match qs.get("id") {
+        Some("all") => data.pairing.revoke_all().map(|_| true),
+        Some(id) if !id.is_empty() => data.pairing.revoke(id),
+        _ => return HttpResponse::BadRequest().body("missing id")
+    };
+    return match result {
+        Ok(true) => {
+            debug_logger::log(&*format!("Pairing revoked: {}", qs.get("id").unwrap()), &data.log_str);
+            HttpResponse::Ok().body("ok")
+        }
+        Ok(false) => HttpResponse::Ok().body("No such device"),
+        Err(e) => {
+            debug_logger::log(&e, &data.log_str);
+            HttpResponse::Ok().body(e)
+        }
+    };
+}
+
+// the app on this pc, or a paired device; see pairing.rs
+fn is_authorized(req: &ServiceRequest, pairing: &PairingStore) -> bool {
+    if pairing::is_public_path(req.path()) {
+        return true;
+    }
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+    // the cookie isn't sent cross-site, the loopback address is the only thing such a request has
+    if pairing::is_cross_site(header("Sec-Fetch-Site"), header("Origin"), header("Host")) {
+        return false;
+    }
+    if req.peer_addr().map(|addr| addr.ip().is_loopback()).unwrap_or(false) && pairing::is_local_host(header("Host")) {
+        return true;
+    }
+    return match req.cookie(SESSION_COOKIE) {
+        Some(cookie) => pairing.device(cookie.value()).is_some(),
+        None => false
+    };
+}
+
 #[get("/hide_windows")]
 async fn hide_popout_windows(data: web::Data<AppState>) -> HttpResponse {
     drop(data.img_sub_status.selected_hwnd.lock().expect("Can't hide windows!"));
-    ImageProcess::hide_all();
+    let strategy = data.config.lock().unwrap().hide_strategy;
+    ImageProcess::hide_all(strategy);
 
     HttpResponse::Ok().body("ok")
 }
@@ -405,37 +667,28 @@ async fn hide_popout_windows(data: web::Data<AppState>) -> HttpResponse {
 
 #[get("/get_windows")]
 async fn get_windows(data: web::Data<AppState>) -> HttpResponse {
+    let aircraft: String = query_aircraft(&data).await;
     let mut state_instruments = data.instrument_list.lock().unwrap();
     let conf = data.config.lock().unwrap();
 
     let popout_lst = data.addon_config.popout_list();
-    let aircraft: String = comm_sender::get_aircraft(&data.command_sender, &data.comm_receiver);
 
 
-    let mut saved = data.current_aircraft.lock().unwrap();
-    *saved = aircraft.clone();
-    drop(saved);
+    set_current_aircraft(&data, &aircraft);
     let sub_hwnd = data.img_sub_status.selected_hwnd.lock().unwrap().clone();
-    let wndows = ImageProcess::start(Option::from(conf.auto_hide),
-                                     Option::from(sub_hwnd), &data.log_str);
+    let mut wndows = ImageProcess::start(None, None, &data.log_str);
+    data.instrument_settings.apply(&aircraft, &mut wndows);
+    let place_hwnd = if wndows.len() == 1 { wndows[0].hwnd } else { sub_hwnd };
+    let hide = hide_selected(&data, &conf, &wndows, place_hwnd);
+    ImageProcess::place_selected(&mut wndows, place_hwnd, hide, &data.log_str);
     let resp = ImageProcess::window_to_string(&wndows);
-    for img in &wndows {
-        if img.instrument == "MCDU" || popout_lst.contains(&img.instrument) || wndows.len() == 1 {
-            let mut instr_search = data.img_sub_status.instrument_search.lock().unwrap();
-            if img.instrument != crate::image_process::UNKNOWN_TITLE &&
-                img.instrument != crate::image_process::MCDU_TITLE &&
-                data.addon_config.get_aircraft_config(&img.instrument).is_some() {
-                *instr_search = img.instrument.clone();
-            } else {
-                *instr_search = aircraft.clone();
+    match data.instrument_settings.last_selected_in(&aircraft, &wndows) {
+        Some(img) => select_instrument(&data, img, &aircraft),
+        None => for img in &wndows {
+            if !img.excluded && (img.instrument == "MCDU" || popout_lst.contains(&img.instrument) ||
+                wndows.len() == 1) {
+                select_instrument(&data, img, &aircraft);
             }
-            drop(instr_search);
-            let mut sub_hwnd = data.img_sub_status.selected_hwnd.lock().unwrap();
-            *sub_hwnd = img.hwnd;
-            let find_crop = data.addon_config.calculate_crop(&aircraft,
-                                                             POPOUT_WIDTH, POPOUT_HEIGHT);
-            let mut crop = data.img_sub_status.display_crop.lock().unwrap();
-            *crop = find_crop;
         }
     }
 
@@ -445,14 +698,119 @@ async fn get_windows(data: web::Data<AppState>) -> HttpResponse {
     HttpResponse::Ok().body(resp)
 }
 
+// Same scan as /get_windows, but nothing gets hidden or moved: windows the user already placed
+// stay where they are and the flags set from the UI are kept.
+#[get("/force_rescan")]
+async fn force_rescan(data: web::Data<AppState>) -> HttpResponse {
+    debug_logger::log("Rescanning pop-outs...", &data.log_str);
+    let aircraft: String = query_aircraft(&data).await;
+    let mut state_instruments = data.instrument_list.lock().unwrap();
+
+    let popout_lst = data.addon_config.popout_list();
+
+    set_current_aircraft(&data, &aircraft);
+    let sub_hwnd = data.img_sub_status.selected_hwnd.lock().unwrap().clone();
+    let mut wndows = ImageProcess::start(None, None, &data.log_str);
+    data.instrument_settings.apply(&aircraft, &mut wndows);
+    ImageProcess::keep_previous_state(&mut wndows, &state_instruments, sub_hwnd);
+
+    let selected = match wndows.iter().find(|img| img.selected) {
+        Some(img) => Some(img),
+        None => data.instrument_settings.last_selected_in(&aircraft, &wndows)
+            .or_else(|| wndows.iter().find(|img| !img.excluded && (img.instrument == "MCDU" ||
+                popout_lst.contains(&img.instrument) || wndows.len() == 1)))
+    };
+    if let Some(img) = selected {
+        select_instrument(&data, img, &aircraft);
+    }
+    let resp = ImageProcess::window_to_string(&wndows);
+
+    *state_instruments = wndows;
+    drop(state_instruments);
+
+    HttpResponse::Ok().body(resp)
+}
+
+fn select_instrument(data: &web::Data<AppState>, img: &InstrumentRgb, aircraft: &String) {
+    let mut instr_search = data.img_sub_status.instrument_search.lock().unwrap();
+    if img.instrument != crate::image_process::UNKNOWN_TITLE &&
+        img.instrument != crate::image_process::MCDU_TITLE &&
+        data.addon_config.get_aircraft_config(&img.instrument).is_some() {
+        *instr_search = img.instrument.clone();
+    } else {
+        *instr_search = aircraft.clone();
+    }
+    drop(instr_search);
+    let mut sub_hwnd = data.img_sub_status.selected_hwnd.lock().unwrap();
+    *sub_hwnd = img.hwnd;
+    let find_crop = instrument_crop(data, &img.instrument, aircraft);
+    let mut crop = data.img_sub_status.display_crop.lock().unwrap();
+    *crop = find_crop;
+    drop(crop);
+    select_fms_font(data, aircraft);
+}
+
+// the crop the user set for the pop-out, or the one from the aircraft's fms_aspect
+fn instrument_crop(data: &web::Data<AppState>, instrument: &str, aircraft: &String) -> [[i32; 2]; 2] {
+    return match data.instrument_settings.get(aircraft, instrument).crop {
+        Some(crop) => crop,
+        None => data.addon_config.calculate_crop(aircraft, POPOUT_WIDTH, POPOUT_HEIGHT)
+    };
+}
+
+// nothing to capture if the aircraft publishes its screen, the pop-out can stay where it is. Same
+// if the user turned auto hide off for this pop-out
+fn hide_selected(data: &web::Data<AppState>, conf: &ConfigHandler, wndows: &[InstrumentRgb],
+                 hwnd: isize) -> Option<HideStrategy> {
+    let hide = conf.auto_hide && !data.output_vars.available() &&
+        wndows.iter().find(|img| img.hwnd == hwnd).map(|img| img.auto_hide).unwrap_or(true);
+    if hide { Some(conf.hide_strategy) } else { None }
+}
+
+// touches are only recorded if the backend can't be used here
+fn select_injector(method: InputMethod, log_str: &Option<Arc<Mutex<String>>>) -> Arc<dyn InputInjector> {
+    let injector: Arc<dyn InputInjector> = match input_injector::create_injector(method) {
+        Ok(injector) => Arc::from(injector),
+        Err(e) => {
+            debug_logger::log(&e, log_str);
+            Arc::new(RecordingInjector::new())
+        }
+    };
+    debug_logger::log(&*format!("Input method: {}", injector.name()), log_str);
+    injector
+}
+
+// the font text subscribers and /fms_text read the selected pop-out with
+fn select_fms_font(data: &web::Data<AppState>, aircraft: &String) {
+    let font = match data.addon_config.get_fms_font(aircraft) {
+        None => None,
+        Some(path) => match FmsFont::load(&path) {
+            Ok(font) => Some(Arc::new(font)),
+            Err(e) => {
+                debug_logger::log(&e, &data.log_str);
+                None
+            }
+        }
+    };
+    data.img_sub_status.streamer.set_fms_font(font);
+}
+
 #[get("/get_aircraft")]
 async fn get_aircraft(data: web::Data<AppState>) -> HttpResponse {
-    let aircraft: String = comm_sender::get_aircraft(&data.command_sender, &data.comm_receiver);
-    let mut saved = data.current_aircraft.lock().unwrap();
-    *saved = aircraft.clone();
+    let aircraft: String = query_aircraft(&data).await;
+    set_current_aircraft(&data, &aircraft);
     return HttpResponse::Ok().body(aircraft);
 }
 
+// the output vars follow the aircraft, an empty name is a bridge that didn't answer
+fn set_current_aircraft(data: &web::Data<AppState>, aircraft: &String) {
+    *data.current_aircraft.lock().unwrap() = aircraft.clone();
+    if !aircraft.is_empty() {
+        window_layouts().set_aircraft(aircraft);
+        data.output_vars.select(data.addon_config.get_output_vars(aircraft));
+    }
+}
+
 #[get("/set_min_capture_ms")]
 async fn set_min_capture_ms(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
     let query_str = req.query_string(); // "name=ferret"
@@ -472,19 +830,111 @@ async fn set_min_capture_ms(req: HttpRequest, data: web::Data<AppState>) -> Http
         .body("ok")
 }
 
+// the latest frame of a pop-out (the selected one without hwnd) in the image_format setting
 #[get("/image_state")]
-async fn image_state(data: web::Data<AppState>) -> HttpResponse {
-    let last_bytes = data.last_bytes.lock().unwrap();
-    let bytes = last_bytes.clone();
-    drop(last_bytes);
-    return HttpResponse::Ok()
-        .body(bytes);
+async fn image_state(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+    let mut hwnd = qs.get("hwnd").unwrap_or("0").parse::<isize>().unwrap_or(0);
+    if hwnd != 0 && !ImageProcess::is_popup_window(hwnd) {
+        return HttpResponse::NotFound().body("Can't find pop-out");
+    }
+    if hwnd == 0 {
+        hwnd = *data.img_sub_status.selected_hwnd.lock().unwrap();
+    }
+    if hwnd == 0 {
+        return HttpResponse::NotFound().body("No pop-out selected");
+    }
+    let conf = data.config.lock().unwrap();
+    let encoder = EncoderSettings { format: conf.image_format, quality: conf.image_quality };
+    drop(conf);
+
+    let latest = data.img_sub_status.streamer.latest_frame(hwnd);
+    let encoded = web::block(move || {
+        let frame = match latest {
+            Some(frame) => frame,
+            // nobody watches it, capture it now
+            None => Arc::new(ImageProcess::capture_raw(hwnd)?)
+        };
+        frame_encoder::encode(&frame.pixels, frame.width, frame.height, encoder)
+    }).await.unwrap();
+    return match encoded {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type(encoder.format.mime_type())
+            .body(bytes),
+        Err(_) => HttpResponse::NotFound().body("Cant capture instrument")
+    };
+}
+
+// the text of the selected pop-out's FMS page as json (see fms_ocr), the same as FMSTEXT messages,
+// or the page the aircraft publishes in its output vars
+#[get("/fms_text")]
+async fn fms_text(data: web::Data<AppState>) -> HttpResponse {
+    if let Some(page) = data.output_vars.page_json() {
+        return HttpResponse::Ok().content_type("application/json").body(page);
+    }
+    let hwnd = *data.img_sub_status.selected_hwnd.lock().unwrap();
+    if hwnd == 0 {
+        return HttpResponse::NotFound().body("No pop-out selected");
+    }
+    let font = match data.img_sub_status.streamer.fms_font() {
+        None => return HttpResponse::NotFound().body("No FMS font for this aircraft"),
+        Some(font) => font
+    };
+    let crop = *data.img_sub_status.display_crop.lock().unwrap();
+
+    let latest = data.img_sub_status.streamer.latest_frame(hwnd);
+    let page = web::block(move || {
+        let frame = match latest {
+            Some(frame) => frame,
+            None => Arc::new(ImageProcess::capture_raw(hwnd)?)
+        };
+        let cropped = match crop {
+            [[0, 0], [0, 0]] => frame,
+            _ => Arc::new(ImageProcess::crop_capture(&frame, crop)?)
+        };
+        Ok::<_, u8>(fms_ocr::read_page(&font, &cropped.pixels, cropped.width, cropped.height))
+    }).await.unwrap();
+    return match page {
+        Ok(page) => HttpResponse::Ok().json(page),
+        Err(_) => HttpResponse::NotFound().body("Cant capture instrument")
+    };
+}
+
+// a jpeg stream for clients without websockets (<img> tags, OBS, e-ink displays), the instrument
+// is a hwnd, a pop-out title or "selected". Optional refresh_ms, quality and crop=x,y,w,h.
+#[get("/stream/{instrument}.mjpg")]
+async fn mjpeg(req: HttpRequest, path: web::Path<String>, data: web::Data<AppState>) -> HttpResponse {
+    let qs = QString::from(req.query_string());
+    let instrument = path.into_inner();
+    let mut request = match instrument.as_str() {
+        "selected" => SubscribeRequest::default(),
+        _ => SubscribeRequest::for_target(&instrument)
+    };
+    request.format = Some(FrameFormat::Jpeg);
+    request.refresh_ms = qs.get("refresh_ms").and_then(|ms| ms.parse::<u16>().ok());
+    request.quality = qs.get("quality").and_then(|q| q.parse::<u8>().ok());
+    let crop: Vec<i32> = qs.get("crop").unwrap_or("").split(',')
+        .filter_map(|value| value.trim().parse::<i32>().ok()).collect();
+    if crop.len() == 4 {
+        request.crop = Some([[crop[0], crop[1]], [crop[2], crop[3]]]);
+    }
+    debug_logger::log(&*format!("New mjpeg stream: {:?}", &request), &data.log_str);
+
+    let queue = FrameQueue::new();
+    return match data.img_sub_status.streamer.subscribe(request, FrameSink::Http(Arc::clone(&queue))) {
+        Ok(_) => HttpResponse::Ok()
+            .content_type(format!("multipart/x-mixed-replace; boundary={}", mjpeg_stream::BOUNDARY))
+            .insert_header(("Cache-Control", "no-cache"))
+            .body(MjpegBody::new(queue)),
+        Err(e) => HttpResponse::NotFound().body(e)
+    };
 }
 
 
 #[get("/get_simvars")]
 async fn get_simvars(data: web::Data<AppState>) -> HttpResponse {
-    let resp: String = comm_sender::get_vars(data.command_sender.clone(), data.comm_receiver.clone());
+    let bridge = Arc::clone(&data.bridge);
+    let resp: String = web::block(move || comm_sender::get_vars(&bridge)).await.unwrap();
     return HttpResponse::Ok()
         .body(resp);
 }
@@ -493,30 +943,31 @@ async fn get_simvars(data: web::Data<AppState>) -> HttpResponse {
 async fn get_simvar(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
     let query_str = req.query_string(); // "name=ferret"
     let qs = QString::from(query_str);
-    let simvar = qs.get("var").unwrap_or("");
+    let simvar = qs.get("var").unwrap_or("").to_string();
 
-    let resp: String = comm_sender::get_var(simvar, data.command_sender.clone(), data.comm_receiver.clone());
+    let bridge = Arc::clone(&data.bridge);
+    let resp: String = web::block(move || comm_sender::get_var(&simvar, &bridge)).await.unwrap();
     return HttpResponse::Ok()
         .body(resp);
 }
 
 #[get("/set_hwnd")]
 async fn set_hwnd(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
+    let aircraft: String = query_aircraft(&data).await;
     let conf = data.config.lock().unwrap();
 
-    let aircraft: String = comm_sender::get_aircraft(&data.command_sender, &data.comm_receiver);
-    let mut saved = data.current_aircraft.lock().unwrap();
-    *saved = aircraft.clone();
-    drop(saved);
+    set_current_aircraft(&data, &aircraft);
 
     let query_str = req.query_string(); // "name=ferret"
     let qs = QString::from(query_str);
     let hw_id = qs.get("hwnd").unwrap_or("0")
         .parse::<isize>().unwrap_or(0);
 
-    let wndows = ImageProcess::start(Option::from(conf.auto_hide), Option::from(hw_id),
-                                     &data.log_str);
-
+    let mut wndows = ImageProcess::start(None, None, &data.log_str);
+    data.instrument_settings.apply(&aircraft, &mut wndows);
+    let place_hwnd = if wndows.len() == 1 { wndows[0].hwnd } else { hw_id };
+    let hide = hide_selected(&data, &conf, &wndows, place_hwnd);
+    ImageProcess::place_selected(&mut wndows, place_hwnd, hide, &data.log_str);
 
     for img in &wndows {
         if img.hwnd == hw_id {
@@ -531,10 +982,12 @@ async fn set_hwnd(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
 
             let mut sub_hwnd = data.img_sub_status.selected_hwnd.lock().unwrap();
             *sub_hwnd = img.hwnd;
-            let find_crop = data.addon_config.calculate_crop(&aircraft,
-                                                             POPOUT_WIDTH, POPOUT_HEIGHT);
+            let find_crop = instrument_crop(&data, &img.instrument, &aircraft);
+            data.instrument_settings.set_last_selected(&aircraft, &img.instrument);
             let mut crop = data.img_sub_status.display_crop.lock().unwrap();
             *crop = find_crop;
+            drop(crop);
+            select_fms_font(&data, &aircraft);
             let mut state_instruments = data.instrument_list.lock().unwrap();
             *state_instruments = wndows;
 
@@ -548,19 +1001,65 @@ async fn set_hwnd(req: HttpRequest, data: web::Data<AppState>) -> HttpResponse {
         .body("error")
 }
 
+#[derive(PartialEq, Clone, Copy)]
+pub enum BridgeMode {
+    // browser clients and connections that didn't finish the handshake yet
+    None,
+    // SimConnector using the old text protocol
+    Legacy,
+    Json,
+}
+
 pub struct MyWs {
-    pub command_receiver: crossbeam_channel::Receiver<String>,
-    pub comm_sender: crossbeam_channel::Sender<String>,
-    pub img_subscribers: Arc<Mutex<Vec<Addr<MyWs>>>>,
-    pub sub_started: Arc<Mutex<bool>>,
-    pub sub_hwnd: Arc<Mutex<isize>>,
-    pub img_crop: Arc<Mutex<[[i32; 2]; 2]>>,
+    pub command_receiver: crossbeam_channel::Receiver<BridgeRequest>,
+    pub bridge: Arc<BridgeDispatcher>,
+    pub bridge_mode: BridgeMode,
+    // dropped when the connection stops, the thread sending it the commands stops with it
+    pub bridge_stop: Option<crossbeam_channel::Sender<()>>,
+    pub streamer: Arc<ImageStreamer>,
+    pub output_vars: Arc<OutputVars>,
+    pub subscription: Option<u64>,
+    pub injector: Arc<Mutex<Arc<dyn InputInjector>>>,
     pub config: Arc<Mutex<ConfigHandler>>,
+    // started with the first touch of the connection
+    pub gestures: Option<GestureInput>,
+    // the paired device of the connection, None for the app itself
+    pub device: Option<String>,
+    // from a loopback address by a loopback name: only the bridge the app starts on this pc can
+    // take the bridge role, not a paired device
+    pub bridge_allowed: bool,
+    pub pairing: Arc<PairingStore>,
+    #[cfg(feature = "webrtc")]
+    pub webrtc: Option<Arc<WebRtcTransport>>,
     pub log_str: Option<Arc<Mutex<String>>>,
 }
 
 impl Actor for MyWs {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(id) = self.device.clone() {
+            // a revoked device loses its connection too, not only its next requests
+            ctx.run_interval(pairing::CHECK_INTERVAL, move |act, ctx| {
+                if !act.pairing.has_device(&id) {
+                    debug_logger::log(&*format!("Closing the connection of revoked device {}", id), &act.log_str);
+                    ctx.close(Some(ws::CloseReason::from((ws::CloseCode::Policy, pairing::NOT_PAIRED))));
+                    ctx.stop();
+                }
+            });
+        }
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(id) = self.subscription.take() {
+            self.streamer.unsubscribe(id);
+        }
+        self.bridge_stop.take();
+        if self.bridge_mode != BridgeMode::None {
+            self.bridge.set_bridge_version(0);
+            self.output_vars.bridge_lost();
+        }
+    }
 }
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -575,16 +1074,106 @@ impl Handler<StringConnectMessage> for MyWs {
 }
 
 
-// Define a custom message type for sending binary data
 #[derive(Message)]
 #[rtype(result = "()")]
-struct BinaryMessage(Vec<u8>);
+struct BridgeRequestMessage(BridgeRequest);
+
+impl Handler<BridgeRequestMessage> for MyWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: BridgeRequestMessage, ctx: &mut Self::Context) {
+        match self.bridge_mode {
+            BridgeMode::Json => ctx.text(msg.0.to_json()),
+            BridgeMode::Legacy => ctx.text(msg.0.command.to_legacy()),
+            BridgeMode::None => {}
+        }
+    }
+}
+
+impl MyWs {
+    fn start_bridge(&mut self, mode: BridgeMode, ctx: &mut ws::WebsocketContext<Self>) {
+        self.bridge_mode = mode;
+        let rx = self.command_receiver.clone();
+        let addr = ctx.address().clone();
+        let bridge = Arc::clone(&self.bridge);
+        // a thread of an earlier handshake on this connection stops as well
+        let (stop_sender, stop) = bounded::<()>(0);
+        self.bridge_stop = Some(stop_sender);
+        let log_inner = debug_logger::clone_log(&self.log_str);
+        thread::spawn(move || {
+            debug_logger::log("Spawning recv thread...", &log_inner);
+            loop {
+                // the receiver is shared, a thread of a closed connection mustn't take the commands
+                // of the next bridge
+                let value = select! {
+                    recv(rx) -> value => value.expect("Unable to receive from channel"),
+                    recv(stop) -> _ => break,
+                };
+                if !addr.connected() {
+                    bridge.requeue(value);
+                    break;
+                }
+                debug_logger::log(&*format!("Sending bridge command: {:?}", &value), &log_inner);
+                let close = value.command == BridgeCommand::Close;
+                addr.do_send(BridgeRequestMessage(value));
+                if close {
+                    break;
+                }
+            }
+            debug_logger::log("Recv thread exiting...", &log_inner);
+        });
+    }
+}
+
+impl MyWs {
+    fn refuse_bridge(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        debug_logger::log("Refused a bridge that isn't on this pc", &self.log_str);
+        ctx.text("Bridges can only connect from this pc");
+    }
+
+    // true if the text was a touch event
+    fn handle_touch(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        let event = match GestureEvent::parse(text) {
+            None => return false,
+            Some(Ok(event)) => event,
+            Some(Err(e)) => {
+                ctx.text(e);
+                return true;
+            }
+        };
+        let (hwnd, area) = match self.streamer.touch_target(self.subscription) {
+            None => {
+                ctx.text("No pop-out to touch");
+                return true;
+            }
+            Some(target) => target
+        };
+        let gestures = self.gestures.get_or_insert_with(|| {
+            let config = Arc::clone(&self.config);
+            let log_inner = debug_logger::clone_log(&self.log_str);
+            GestureInput::start(Arc::clone(&self.injector), move |injector, hwnd| {
+                // a backend that brings the pop-out to the front, a hidden one goes back behind the sim
+                if injector.takes_focus() && backend().is_hidden(hwnd) {
+                    ImageProcess::hide_window(hwnd, config.lock().unwrap().hide_strategy);
+                }
+            }, move |e| debug_logger::log(&*format!("Touch failed: {}", e), &log_inner))
+        });
+        if let Some(hwnd) = gestures.handle(&event, hwnd, area) {
+            // the screen is about to change
+            self.streamer.wake(hwnd);
+        }
+        true
+    }
+}
 
-impl Handler<BinaryMessage> for MyWs {
+impl Handler<FrameMessage> for MyWs {
     type Result = ();
 
-    fn handle(&mut self, msg: BinaryMessage, ctx: &mut Self::Context) {
-        ctx.binary(msg.0);
+    fn handle(&mut self, msg: FrameMessage, ctx: &mut Self::Context) {
+        match msg {
+            FrameMessage::Image(image) => ctx.binary(image),
+            FrameMessage::Text(text) => ctx.text(text)
+        }
     }
 }
 
@@ -593,115 +1182,78 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                if text == "ConnectWSClient" {
-                    let rx = self.command_receiver.clone();
-                    //let sn = self.comm_sender.clone();
-                    let addr = ctx.address().clone();
-                    //let ctx_clone = ctx.deref().clone();
-                    let log_inner = debug_logger::clone_log(&self.log_str);
-                    thread::spawn(move || {
-                        debug_logger::log("Spawning recv thread...", &log_inner);
-                        loop {
-                            let value = rx.recv().expect("Unable to receive from channel");
-
-                            // COMMUNICATION ON THE CHANNELS:
-                            // CloseBridge  => send close cmd
-                            // SM_SEND:CMD_BTN:EXAMPLE_LVAR send msg to Simconnector to press the EXAMLE_LVAR btn
-                            if value == "CloseBridge" {
-                                debug_logger::log("Sending bridge CLOSE command", &log_inner);
-                                addr.do_send(StringConnectMessage("CLOSE".to_string()));
-                                break;
+                #[cfg(feature = "webrtc")]
+                if self.handle_webrtc(&text, ctx) {
+                    return;
+                }
+                if self.handle_touch(&text, ctx) {
+                    return;
+                }
+                if text == LEGACY_CONNECT && !self.bridge_allowed {
+                    self.refuse_bridge(ctx);
+                } else if text == LEGACY_CONNECT {
+                    debug_logger::log("Legacy bridge connected", &self.log_str);
+                    self.bridge.set_bridge_version(0);
+                    self.start_bridge(BridgeMode::Legacy, ctx);
+                    ctx.text(LEGACY_CONNECTED);
+                } else if text == image_streamer::UNSUBSCRIBE {
+                    if let Some(id) = self.subscription.take() {
+                        self.streamer.unsubscribe(id);
+                    }
+                } else if let Some(rest) = text.strip_prefix(image_streamer::ACK) {
+                    if let Some(id) = self.subscription {
+                        self.streamer.ack(id, rest.strip_prefix(':').and_then(|seq| seq.parse::<u32>().ok()));
+                    }
+                } else if text == image_streamer::KEYFRAME {
+                    if let Some(id) = self.subscription {
+                        self.streamer.request_keyframe(id);
+                    }
+                } else if let Some(request) = SubscribeRequest::parse(&text) {
+                    debug_logger::log(&*format!("New IMAGE_SUBSCRIBE: {:?}", &request), &self.log_str);
+                    // a new subscription replaces the previous one of this connection
+                    if let Some(id) = self.subscription.take() {
+                        self.streamer.unsubscribe(id);
+                    }
+                    match self.streamer.subscribe(request, FrameSink::Ws(ctx.address().recipient())) {
+                        Ok(id) => self.subscription = Some(id),
+                        Err(e) => {
+                            debug_logger::log(&e, &self.log_str);
+                            ctx.text(e);
+                        }
+                    }
+                } else {
+                    match BridgeResponse::parse(&text) {
+                        Some(BridgeResponse { reply: BridgeReply::Hello { .. }, .. }) if !self.bridge_allowed => {
+                            self.refuse_bridge(ctx);
+                        }
+                        Some(BridgeResponse { reply: BridgeReply::Hello { version }, .. }) => {
+                            debug_logger::log(&*format!("Bridge connected, protocol version: {}", version),
+                                              &self.log_str);
+                            self.bridge.set_bridge_version(version);
+                            self.start_bridge(BridgeMode::Json, ctx);
+                            let welcome = BridgeRequest { id: None, command: BridgeCommand::Welcome { version: PROTOCOL_VERSION } };
+                            ctx.text(welcome.to_json());
+                            // a restarted bridge gets the vars of the current aircraft again
+                            if let Some(command) = self.output_vars.subscription() {
+                                if version >= VAR_SUBSCRIPTION_VERSION {
+                                    ctx.text(BridgeRequest { id: None, command }.to_json());
+                                }
                             }
-                            if value == "BridgeStatus" {
-                                debug_logger::log("Sending bridge STATUS command", &log_inner);
-
-                                addr.do_send(StringConnectMessage("STATUS".to_string()));
-                            } else if value == "GetAircraft" {
-                                debug_logger::log("Sending bridge GET_AIRCRAFT command", &log_inner);
-                                addr.do_send(StringConnectMessage("GET_AIRCRAFT".to_string()));
-                            } else if value.contains("SM_SEND:") {
-                                //let cmnd: &str = value.split(":").collect::<Vec<&str>>()[1];
-                                debug_logger::log(&*format!("Sending bridge SM_SEND:{}", &value), &log_inner);
-
-                                addr.do_send(StringConnectMessage(value.replace("SM_SEND:", "")))
+                        }
+                        Some(BridgeResponse { reply: BridgeReply::VarValues { values }, .. }) => {
+                            if self.bridge_mode != BridgeMode::None {
+                                self.output_vars.update(values);
                             }
                         }
-                        debug_logger::log("Recv thread exiting...", &log_inner);
-                    });
-                    ctx.text("CONNECTED");
-                } else if text == "IMAGESUBSCRIBE" {
-                    debug_logger::log("New IMAGE_SUBSCRIBE", &self.log_str);
-
-                    let mut started = self.sub_started.lock().unwrap();
-                    self.img_subscribers.lock().unwrap().push(ctx.address());
-
-                    if !*started {
-                        debug_logger::log("Adding subscription thread...", &self.log_str);
-
-                        *started = true;
-                        drop(started);
-                        let sbs = Arc::clone(&self.img_subscribers);
-                        let cnfig_inner = Arc::clone(&self.config);
-                        let hwnd = Arc::clone(&self.sub_hwnd);
-                        let crop = Arc::clone(&self.img_crop);
-                        let inner_log = debug_logger::clone_log(&self.log_str);
-                        thread::spawn(move || {
-                            let mut refresh = std::time::Duration::from_millis(cnfig_inner.lock().unwrap().refresh_rate.clone() as u64);
-                            let mut inner_subs = Arc::clone(&sbs).lock().unwrap().clone();
-                            let mut inner_hwnd = Arc::clone(&hwnd).lock().unwrap().clone();
-                            let mut inner_crop = Arc::clone(&crop).lock().unwrap().clone();
-
-                            let mut counter = 500;
-                            loop {
-                                if counter % 10 == 0 {
-                                    refresh = std::time::Duration::from_millis(cnfig_inner.lock().unwrap().refresh_rate.clone() as u64);
-                                    let current_subs = Arc::clone(&sbs);
-                                    let mut subs_locked = current_subs.lock().unwrap();
-                                    let mut i = 0;
-                                    while i < subs_locked.len() {
-                                        if !subs_locked[i].connected() {
-                                            debug_logger::log("Removing a subscriber from img thread",
-                                                              &inner_log);
-                                            subs_locked.remove(i);
-                                        } else {
-                                            i += 1;
-                                        }
-                                    }
-                                    inner_subs = subs_locked.clone();
-                                    inner_hwnd = Arc::clone(&hwnd).lock().unwrap().clone();
-                                    inner_crop = Arc::clone(&crop).lock().unwrap().clone();
-                                }
-                                if inner_subs.len() > 0 {
-                                    let img =
-                                        match ImageProcess::capture_instrument(
-                                            inner_hwnd, inner_crop) {
-                                            Ok(res) => { res.buf }
-                                            Err(e) => {
-                                                println!("Cant capture instrument!:{}", e);
-                                                vec![]
-                                            }
-                                        };
-
-                                    if inner_hwnd != 0 && img.len() > 0 {
-                                        for i in 0..inner_subs.len() {
-                                            match inner_subs[i].try_send(BinaryMessage(img.clone())) {
-                                                Ok(_) => {}
-                                                Err(e) => {
-                                                    debug_logger::log(
-                                                        format!("Cant send img from sub thread: {}", e).as_str(),
-                                                        &inner_log);
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                thread::sleep(refresh);
-                                counter += 1;
+                        Some(response) => {
+                            if self.bridge_mode != BridgeMode::None {
+                                self.bridge.dispatch(response);
                             }
-                        });
+                        }
+                        None => {
+                            debug_logger::log(&*format!("Unknown ws message: {}", &text), &self.log_str);
+                        }
                     }
-                } else {
-                    self.comm_sender.send(text.to_string()).unwrap()
                 }
             }
             Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
@@ -710,18 +1262,80 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
     }
 }
 
+#[cfg(feature = "webrtc")]
+impl MyWs {
+    // true if the text was WebRTC signalling
+    fn handle_webrtc(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) -> bool {
+        if text == webrtc_transport::CLOSE {
+            if let Some(id) = self.subscription.take() {
+                self.streamer.unsubscribe(id);
+            }
+            return true;
+        }
+        let request = match OfferRequest::parse(text) {
+            None => return false,
+            Some(Ok(request)) => request,
+            Some(Err(e)) => {
+                ctx.text(e);
+                return true;
+            }
+        };
+        debug_logger::log(&*format!("New WebRTC offer: {:?}", &request.subscription), &self.log_str);
+        match &self.webrtc {
+            None => ctx.text("WebRTC isn't available"),
+            Some(transport) => transport.negotiate(request, ctx.address().recipient())
+        }
+        true
+    }
+}
+
+#[cfg(feature = "webrtc")]
+impl Handler<Negotiated> for MyWs {
+    type Result = ();
+
+    fn handle(&mut self, msg: Negotiated, ctx: &mut Self::Context) {
+        let (answer, video) = match msg.result {
+            Ok(session) => session,
+            Err(e) => {
+                ctx.text(e);
+                return;
+            }
+        };
+        // the session replaces the image subscription of the connection
+        if let Some(id) = self.subscription.take() {
+            self.streamer.unsubscribe(id);
+        }
+        match self.streamer.subscribe(msg.subscription, FrameSink::Video(video)) {
+            Ok(id) => {
+                self.subscription = Some(id);
+                ctx.text(format!("{}:{}", webrtc_transport::ANSWER, answer));
+            }
+            Err(e) => ctx.text(e)
+        }
+    }
+}
+
 async fn ws_index(req: HttpRequest, stream: web::Payload, data: web::Data<AppState>) -> Result<HttpResponse, Error> {
     debug_logger::log("ws_index, starting communications...", &data.log_str);
     let rec = data.command_receiver.clone();
-    let sndr = data.comm_sender.clone();
+    let dispatcher = Arc::clone(&data.bridge);
     let resp = ws::start(MyWs {
         command_receiver: rec,
-        comm_sender: sndr,
-        img_subscribers: Arc::clone(&data.img_sub_status.img_sub_list),
-        sub_started: Arc::clone(&data.img_sub_status.thread_started),
-        config: data.config.clone(),
-        sub_hwnd: Arc::clone(&data.img_sub_status.selected_hwnd),
-        img_crop: Arc::clone(&data.img_sub_status.display_crop),
+        bridge: dispatcher,
+        bridge_mode: BridgeMode::None,
+        bridge_stop: None,
+        streamer: Arc::clone(&data.img_sub_status.streamer),
+        output_vars: Arc::clone(&data.output_vars),
+        subscription: None,
+        injector: Arc::clone(&data.injector),
+        config: Arc::clone(&data.config),
+        gestures: None,
+        device: req.cookie(SESSION_COOKIE).and_then(|cookie| data.pairing.device(cookie.value())),
+        bridge_allowed: req.peer_addr().map(|addr| addr.ip().is_loopback()).unwrap_or(false)
+            && pairing::is_local_host(req.headers().get("Host").and_then(|value| value.to_str().ok())),
+        pairing: Arc::clone(&data.pairing),
+        #[cfg(feature = "webrtc")]
+        webrtc: data.img_sub_status.webrtc.clone(),
         log_str: debug_logger::clone_log(&data.log_str),
     }, &req, stream);
 
@@ -734,33 +1348,59 @@ pub async fn main(log_str: Option<Arc<Mutex<String>>>) -> std::io::Result<()> {
 
     let mut config = ConfigHandler::init(debug_logger::clone_log(&log_str));
     config.read_config();
-    let (s, r) = bounded::<String>(0);
-    let (sc, rc) = bounded::<String>(0);
+    let (s, r) = bounded::<BridgeRequest>(0);
     let addon_config = AddonConfig::load(debug_logger::clone_log(&log_str)).await;
+    let injector = select_injector(config.input_method, &log_str);
+    let tls_identity = tls_identity(&config, &log_str);
+    let pairing = Arc::new(PairingStore::open(get_pairings_file()));
+    ConfigHandler::write_qr_code(&pairing.token(), tls_identity.as_ref().map(|identity| identity.fingerprint.as_str()));
+    let config = Arc::new(Mutex::from(config));
+    let selected_hwnd = Arc::new(Mutex::new(0));
+    let display_crop = Arc::new(Mutex::new([[0, 0], [0, 0]]));
+    let bridge = Arc::new(BridgeDispatcher::new(s, debug_logger::clone_log(&log_str)));
+    let output_vars = Arc::new(OutputVars::new(Arc::clone(&bridge), debug_logger::clone_log(&log_str)));
+    let streamer = Arc::new(ImageStreamer::new(Arc::clone(&selected_hwnd), Arc::clone(&display_crop),
+                                               Arc::clone(&config), Arc::clone(&output_vars),
+                                               debug_logger::clone_log(&log_str)));
+    #[cfg(feature = "webrtc")]
+    let webrtc = match WebRtcTransport::new(debug_logger::clone_log(&log_str)) {
+        Ok(transport) => Some(Arc::new(transport)),
+        Err(e) => {
+            debug_logger::log(&e, &log_str);
+            None
+        }
+    };
     let state = web::Data::new(AppState {
-        last_bytes: Mutex::from(Vec::new()),
         main_html_string: include_str!("../../frontend/build/index.html"),
         icon_png: include_bytes!("../../svg/reachfms_white.png"),
         instrument_list: Mutex::from(vec![]),
-        config: Arc::new(Mutex::from(config)),
-        child_process: Mutex::from(Option::None),
+        config,
+        bridge_supervisor: Arc::new(BridgeSupervisor::new(debug_logger::clone_log(&log_str))),
         //selected_hwnd: Mutex::from(0),
-        command_sender: s,
+        bridge,
         command_receiver: r,
-        comm_sender: sc,
-        comm_receiver: rc,
         current_aircraft: Mutex::new("".to_string()),
+        mcdu_popouts: Mutex::new(HashMap::new()),
+        output_vars,
+        injector: Arc::new(Mutex::new(injector)),
+        instrument_settings: InstrumentSettingsStore::load(debug_logger::clone_log(&log_str)),
+        pairing: Arc::clone(&pairing),
+        tls_cert: tls_identity.as_ref().map(|identity| identity.cert.clone()),
         bridge_status: Mutex::from(BridgeStatus {
             connected: false,
             started: false,
             comm: false,
+            crash_count: 0,
+            restart_count: 0,
+            last_exit_code: None,
         }),
         img_sub_status: ImageSubscriptionStatus {
-            thread_started: Arc::new(Mutex::new(false)),
-            img_sub_list: Arc::new(Mutex::new(vec![])),
-            selected_hwnd: Arc::new(Mutex::new(0)),
-            display_crop: Arc::new(Mutex::new([[0, 0], [0, 0]])),
+            streamer,
+            selected_hwnd,
+            display_crop,
             instrument_search: Mutex::from("".to_string()),
+            #[cfg(feature = "webrtc")]
+            webrtc,
         },
         addon_config,
         log_str,
@@ -768,20 +1408,51 @@ pub async fn main(log_str: Option<Arc<Mutex<String>>>) -> std::io::Result<()> {
     let static_path: String = get_static_folder();
 
 
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    let server = HttpServer::new(move || {
+        // only the pages of the server itself read its answers, without credentials
+        let cors = Cors::default()
+            .allowed_origin_fn(|origin, head| {
+                let host = head.headers().get("Host").and_then(|value| value.to_str().ok());
+                !pairing::is_cross_site(None, origin.to_str().ok(), host)
+            })
+            .allow_any_method()
+            .allow_any_header()
+            .max_age(3600);
+        let pairing = Arc::clone(&pairing);
         App::new()
+            // inside cors, so the 401 gets its headers as well
+            .wrap_fn(move |req, srv| {
+                let call = match is_authorized(&req, &pairing) {
+                    true => Ok(srv.call(req)),
+                    false => Err(req)
+                };
+                async move {
+                    return match call {
+                        Ok(response) => Ok(response.await?.map_into_left_body()),
+                        Err(req) => Ok(req.into_response(HttpResponse::Unauthorized().body(pairing::NOT_PAIRED))
+                            .map_into_right_body())
+                    };
+                }
+            })
             .wrap(cors)
             .app_data(state.clone())
             .service(index)
             .service(icon_png)
+            .service(tls_cert)
             .service(mcdu_btn)
             .service(save_debug)
             .service(set_hwnd_settings)
             .service(get_windows)
+            .service(force_rescan)
             .service(image_state)
+            .service(mjpeg)
+            .service(fms_text)
             .service(set_min_capture_ms)
             .service(restore_windows)
+            .service(save_layout)
+            .service(apply_layout)
+            .service(delete_layout)
+            .service(get_layouts)
             .service(hide_popout_windows)
             .service(settings)
             .service(status)
@@ -796,11 +1467,57 @@ pub async fn main(log_str: Option<Arc<Mutex<String>>>) -> std::io::Result<()> {
             .service(get_simvars)
             .service(get_simvar)
             .service(touch_event)
+            .service(pair)
+            .service(get_pairings)
+            .service(revoke_pairing)
             .service(Files::new("/static", static_path.clone()))
             .route("/ws", web::get().to(ws_index))
         //.service(jpeg_test)
     })
-        .bind(("0.0.0.0", 5273))?
-        .run()
-        .await
+        .bind(("0.0.0.0", get_http_port()))?;
+    let server = match tls_identity {
+        Some(identity) => server.bind_rustls_0_23(("0.0.0.0", TLS_PORT), identity.server_config)?,
+        None => server
+    };
+    server.run().await
+}
+
+// the configured certificate or the self-signed one, None if https is off or the certificate
+// can't be used (the server still starts, without https)
+fn tls_identity(config: &ConfigHandler, log_str: &Option<Arc<Mutex<String>>>) -> Option<TlsIdentity> {
+    if !config.tls_enabled {
+        return None;
+    }
+    let identity = if config.tls_cert_path.is_empty() {
+        let (cert_file, key_file) = (get_tls_cert_file(), get_tls_key_file());
+        match tls::ensure_self_signed(&cert_file, &key_file, &tls_hosts()) {
+            Ok(true) => debug_logger::log("Made a self-signed certificate for https", log_str),
+            Ok(false) => {}
+            Err(e) => debug_logger::log(&e, log_str)
+        }
+        tls::load(&cert_file, &key_file)
+    } else {
+        tls::load(&config.tls_cert_path, &config.tls_key_path)
+    };
+    return match identity {
+        Ok(identity) => {
+            debug_logger::log(&*format!("Serving https on {}, certificate fingerprint {}", TLS_PORT, identity.fingerprint), log_str);
+            Some(identity)
+        }
+        Err(e) => {
+            debug_logger::log(&*format!("Https is off: {}", e), log_str);
+            None
+        }
+    };
+}
+
+// what the self-signed certificate is made for: this pc and its addresses on the network
+fn tls_hosts() -> Vec<String> {
+    let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    for (_, ip) in local_ip_address::list_afinet_netifas().unwrap_or_default() {
+        if ip.is_ipv4() && !ip.is_loopback() && !hosts.contains(&ip.to_string()) {
+            hosts.push(ip.to_string());
+        }
+    }
+    hosts
 }
\ No newline at end of file
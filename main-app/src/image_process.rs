@@ -1,12 +1,10 @@
-use std::{mem};
 use std::sync::{Arc, Mutex};
-use fltk::draw::width;
-use image::{RgbaImage};
-use win_screenshot::prelude::*;
 use serde::{Deserialize, Serialize};
-use windows::Win32::Foundation::{HWND, RECT};
-use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, GetWindow, GetWindowRect, GW_OWNER, SetWindowPos, SM_CYVIRTUALSCREEN, SWP_NOACTIVATE, SWP_NOREDRAW, SWP_NOZORDER};
-use crate::debug_logger;
+use crate::capture_backend::{backend, HideStrategy, RawCapture, WindowInfo, WindowRect};
+use crate::{debug_logger, frame_encoder};
+use crate::frame_encoder::EncoderSettings;
+use crate::window_journal::window_journal;
+use crate::window_layouts::window_layouts;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct InstrumentRgb {
@@ -40,8 +38,6 @@ pub struct PopOutWindow {
 }
 
 
-const USING: Using = Using::PrintWindow;
-const AREA: Area = Area::ClientOnly;
 const MSFS_TITLE: &str = "Microsoft Flight Simulator - ";
 const DEFAULT_TITLE: &str = "WASMINSTRUMENT";
 pub(crate) const UNKNOWN_TITLE: &str = "UNKNOWN";
@@ -74,14 +70,15 @@ impl ImageProcess {
             Ok(res) => {
                 debug_logger::log(format!("Found pop-outs: {}", 
                                           serde_json::to_string(&res).unwrap()).as_str(), &log_str);
+                window_layouts().snapshot(&res);
                 res
             }
             Err(_) => {
                 debug_logger::log("Can't find MSFS window! Found window handles:", &log_str);
-                let ls = window_list().unwrap();
+                let ls = backend().window_list().unwrap_or_default();
                 for ls_c in ls {
                     debug_logger::log(format!("HWND: {}, TITLE: '{}'",
-                                              &ls_c.hwnd, &ls_c.window_name).as_str(), &log_str);
+                                              &ls_c.hwnd, &ls_c.title).as_str(), &log_str);
                 }
                 //show_warning_dialog("Cant find MSFS window!");
                 vec![]
@@ -94,8 +91,14 @@ impl ImageProcess {
 
 
         for hw in av_hw.iter() {
-            let capture = ImageProcess::capture_instrument(
-                hw.hwnd.clone(), [[0, 0], [0, 0]]).unwrap();
+            let capture = match ImageProcess::capture_instrument(
+                hw.hwnd.clone(), [[0, 0], [0, 0]]) {
+                Ok(res) => res,
+                Err(_) => {
+                    debug_logger::log(format!("Can't capture pop-out: {}", &hw.hwnd).as_str(), &log_str);
+                    continue;
+                }
+            };
 
 
             // let buf = capture_window_ex(hw.hwnd.clone(), Using::PrintWindow,
@@ -118,29 +121,15 @@ impl ImageProcess {
         if av_hw.len() == 1 || selected_hwnd.is_some() {
             match auto_hide {
                 None => {}
-                Some(hide_res) => unsafe {
+                Some(hide_res) => {
                     let hw: isize;
                     if av_hw.len() == 1 {
                         hw = av_hw[0].hwnd;
                     } else {
                         hw = selected_hwnd.unwrap_or(0);
                     }
-                    for rgb in &mut rgb_list {
-                        if rgb.hwnd == hw {
-                            debug_logger::log(format!("Setting selected hwnd: {}",
-                                                      &rgb.hwnd).as_str(), &log_str);
-                            if rgb.instrument == UNKNOWN_TITLE {
-                                rgb.instrument = MCDU_TITLE.to_string();
-                            }
-                            rgb.selected = true;
-                            if hide_res {
-                                ImageProcess::hide_window(hw)
-                            } else {
-                                let wsize = ImageProcess::get_window_pos(hw);
-                                ImageProcess::move_window(hw, wsize.top, wsize.left, POPOUT_WIDTH, POPOUT_HEIGHT);
-                            }
-                        }
-                    }
+                    let strategy = if hide_res { Some(HideStrategy::default()) } else { None };
+                    ImageProcess::place_selected(&mut rgb_list, hw, strategy, log_str);
                 }
             }
         }
@@ -148,7 +137,44 @@ impl ImageProcess {
         rgb_list
     }
 
-    fn find_crop_for_instruments(width: u32, height: u32) -> [[i32; 2]; 2] {
+    // marks the pop-out selected and hides it, or gives it the pop-out size where it is
+    pub fn place_selected(rgb_list: &mut [InstrumentRgb], hw: isize, hide: Option<HideStrategy>,
+                          log_str: &Option<Arc<Mutex<String>>>) {
+        for rgb in rgb_list.iter_mut() {
+            if rgb.hwnd == hw {
+                debug_logger::log(format!("Setting selected hwnd: {}",
+                                          &rgb.hwnd).as_str(), log_str);
+                if rgb.instrument == UNKNOWN_TITLE {
+                    rgb.instrument = MCDU_TITLE.to_string();
+                }
+                rgb.selected = true;
+                match hide {
+                    Some(strategy) => ImageProcess::hide_window(hw, strategy),
+                    None => {
+                        let wsize = ImageProcess::get_window_pos(hw);
+                        ImageProcess::move_window(hw, wsize.top, wsize.left, POPOUT_WIDTH, POPOUT_HEIGHT);
+                    }
+                }
+            }
+        }
+    }
+
+    // carries the user's flags over to a fresh scan, pop-outs are matched by hwnd
+    pub fn keep_previous_state(rgb_list: &mut Vec<InstrumentRgb>, previous: &Vec<InstrumentRgb>,
+                               selected_hwnd: isize) {
+        for rgb in rgb_list.iter_mut() {
+            if let Some(prev) = previous.iter().find(|prev| prev.hwnd == rgb.hwnd) {
+                rgb.auto_hide = prev.auto_hide;
+                rgb.excluded = prev.excluded;
+                if rgb.instrument == UNKNOWN_TITLE {
+                    rgb.instrument = prev.instrument.clone();
+                }
+            }
+            rgb.selected = rgb.hwnd == selected_hwnd;
+        }
+    }
+
+    pub fn find_crop_for_instruments(width: u32, height: u32) -> [[i32; 2]; 2] {
         let crop: [[i32; 2]; 2] = [[0, 0], [width as i32, height as i32]];
 
 
@@ -163,46 +189,34 @@ impl ImageProcess {
         };
     }
 
-    pub unsafe fn hide_window(hwnd_in: isize) {
+    pub fn hide_window(hwnd_in: isize, strategy: HideStrategy) {
         if hwnd_in != 0 {
-            let hwnda_to_move: HWND = mem::transmute(hwnd_in);
-            let height = GetSystemMetrics(SM_CYVIRTUALSCREEN);
-
-            SetWindowPos(hwnda_to_move, hwnda_to_move,
-                         0, height + 50, POPOUT_WIDTH, POPOUT_HEIGHT, SWP_NOREDRAW | SWP_NOZORDER | SWP_NOACTIVATE).expect("Cant set window pos");
+            window_journal().record(hwnd_in, "hide");
+            backend().hide_window(hwnd_in, strategy, POPOUT_WIDTH, POPOUT_HEIGHT).expect("Cant set window pos");
         }
     }
 
 
-    pub fn get_window_parent(hwnd: isize) -> HWND {
-        unsafe {
-            let hwnda_to_move: HWND = mem::transmute(hwnd);
-
-            let owner = GetWindow(hwnda_to_move, GW_OWNER);
-
-            //let prnt = GetAncestor(hwnda_to_move, GA_PARENT);
-            return owner;
-        }
+    pub fn get_window_parent(hwnd: isize) -> isize {
+        backend().window_owner(hwnd)
     }
 
-    pub unsafe fn get_window_pos(hwnd_in: isize) -> RECT {
-        let hwnda_to_move: HWND = mem::transmute(hwnd_in);
-        let mut rrval: RECT = RECT::default();
-        GetWindowRect(hwnda_to_move, &mut rrval).expect("Cant get window rect");
-        rrval
+    pub fn get_window_pos(hwnd_in: isize) -> WindowRect {
+        backend().window_rect(hwnd_in).expect("Cant get window rect")
     }
 
-    pub unsafe fn move_window(hwnd_in: isize, move_top: i32, move_left: i32, move_width: i32, move_height: i32) {
-        let hwnda_to_move: HWND = mem::transmute(hwnd_in);
-        SetWindowPos(hwnda_to_move, hwnda_to_move,
-                     move_left, move_top, move_width, move_height,
-                     SWP_NOREDRAW | SWP_NOZORDER | SWP_NOACTIVATE).expect("Cant move window!");
+    pub fn move_window(hwnd_in: isize, move_top: i32, move_left: i32, move_width: i32, move_height: i32) {
+        window_journal().record(hwnd_in, "move");
+        // a window is moved to be seen there, whichever way it was hidden
+        let _ = backend().show_window(hwnd_in);
+        backend().move_window(hwnd_in, move_left, move_top, move_width, move_height)
+            .expect("Cant move window!");
     }
 
-    pub fn get_sim_hwnd(window_ls: &Vec<HwndName>) -> isize {
+    pub fn get_sim_hwnd(window_ls: &Vec<WindowInfo>) -> isize {
         //let mut current_hwnd: HWND;
         for i in window_ls {
-            if i.window_name.contains(MSFS_TITLE) {
+            if i.title.contains(MSFS_TITLE) {
                 return i.hwnd;
             }
         }
@@ -212,7 +226,10 @@ impl ImageProcess {
 
     pub fn find_popup_windows() -> Result<Vec<PopOutWindow>, bool> {
         let mut process_ls = Vec::<PopOutWindow>::new();
-        let ls = window_list().unwrap();
+        let ls = match backend().window_list() {
+            Ok(res) => res,
+            Err(_) => return Err(false)
+        };
         let fs_hwnd = Self::get_sim_hwnd(&ls);
         if fs_hwnd == 0 {
             return Err(false);
@@ -221,19 +238,27 @@ impl ImageProcess {
         for i in ls {
             let prnt_id = Self::get_window_parent(i.hwnd);
 
-            if i.window_name == DEFAULT_TITLE || prnt_id.0 == fs_hwnd {
+            if i.title == DEFAULT_TITLE || prnt_id == fs_hwnd {
                 let hwnd = i.hwnd;
                 process_ls.push(PopOutWindow {
                     hwnd,
-                    title: match i.window_name.as_str() {
+                    title: match i.title.as_str() {
                         DEFAULT_TITLE => { UNKNOWN_TITLE.to_string() }
-                        _ => { i.window_name }
+                        _ => { i.title }
                     },
                 })
             }
         }
         return Ok(process_ls);
     }
+
+    // true if the hwnd is a pop-out of the sim now, clients can't ask for any window of the pc
+    pub fn is_popup_window(hwnd: isize) -> bool {
+        return match ImageProcess::find_popup_windows() {
+            Ok(popouts) => popouts.iter().any(|popout| popout.hwnd == hwnd),
+            Err(_) => false
+        };
+    }
     pub fn window_to_string(input: &Vec<InstrumentRgb>) -> String {
         let mut string_instruments: Vec<InstrumentResponse> = Vec::new();
 
@@ -265,29 +290,47 @@ impl ImageProcess {
         };
 
 
-        let buf = match capture_window_ex(hw_id, USING, AREA,
-                                          cropxy, cropwh) {
+        let buf = match backend().capture_client(hw_id, cropxy, cropwh) {
             Ok(tempbuf) => tempbuf,
             Err(..) => {
                 return Err(0);
             }
         };
-        // let ref mut w = BufWriter::new(Cursor::new(Vec::new()));
-        let mut outputbuf = Vec::new();
-        {
-            let mut encoder = png::Encoder::new(&mut outputbuf, buf.width, buf.height);
-            encoder.set_color(png::ColorType::Rgba);
-            encoder.set_depth(png::BitDepth::Eight);
-            let mut writer = encoder.write_header().unwrap();
-
-            writer.write_image_data(&buf.pixels).unwrap(); // Save
+        Ok(ImageProcess::encode_png(&buf))
+    }
+
+    // the whole client area, unencoded, so it can be cropped for several subscribers
+    pub fn capture_raw(hw_id: isize) -> Result<RawCapture, u8> {
+        backend().capture_client(hw_id, None, None)
+    }
+
+    // crops a raw capture the same way capture_instrument crops on the backend
+    pub fn crop_capture(raw: &RawCapture, crop: [[i32; 2]; 2]) -> Result<RawCapture, u8> {
+        let [x, y] = crop[0];
+        let [w, h] = match crop[1] {
+            [0, 0] => [raw.width as i32 - x, raw.height as i32 - y],
+            _ => crop[1]
+        };
+        if x < 0 || y < 0 || w <= 0 || h <= 0
+            || (x + w) as u32 > raw.width || (y + h) as u32 > raw.height {
+            return Err(1);
+        }
+        let stride = raw.width as usize * 4;
+        let mut pixels: Vec<u8> = Vec::with_capacity(w as usize * h as usize * 4);
+        for row in y as usize..(y + h) as usize {
+            let start = row * stride + x as usize * 4;
+            pixels.extend_from_slice(&raw.pixels[start..start + w as usize * 4]);
         }
-        let capture = WindowCapture{
-            buf: outputbuf,
+        Ok(RawCapture { pixels, width: w as u32, height: h as u32 })
+    }
+
+    pub fn encode_png(buf: &RawCapture) -> WindowCapture {
+        WindowCapture {
+            buf: frame_encoder::encode(&buf.pixels, buf.width, buf.height, EncoderSettings::default())
+                .unwrap_or_default(),
             width: buf.width as u16,
             height: buf.height as u16
-        };
-        Ok(capture)
+        }
     }
 
     pub fn restore_all() -> bool {
@@ -297,21 +340,21 @@ impl ImageProcess {
                 return false
             }
         };
-        for popout in poputs {
-            unsafe { ImageProcess::move_window(popout.hwnd, 0, 0, POPOUT_WIDTH, POPOUT_HEIGHT) }
-        }
+        window_layouts().restore(&poputs);
+        window_journal().forget(&poputs.iter().map(|popout| popout.hwnd).collect::<Vec<isize>>());
         return true;
     }
 
-    pub fn hide_all() -> bool {
+    pub fn hide_all(strategy: HideStrategy) -> bool {
         let poputs = match ImageProcess::find_popup_windows() {
             Ok(res) => { res }
             Err(_) => {
                 return false
             }
         };
+        window_layouts().snapshot(&poputs);
         for popout in poputs {
-            unsafe { Self::hide_window(popout.hwnd) }
+            Self::hide_window(popout.hwnd, strategy)
         }
         return true;
     }
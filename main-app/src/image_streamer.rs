@@ -0,0 +1,840 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use actix::{Message, Recipient};
+use serde::{Deserialize, Serialize};
+use crate::capture_backend::{backend, RawCapture};
+use crate::config_handler::ConfigHandler;
+use crate::frame_encoder::{EncoderSettings, FrameFormat};
+use crate::{debug_logger, fms_ocr, frame_delta, frame_encoder};
+use crate::fms_ocr::FmsFont;
+use crate::image_process::ImageProcess;
+use crate::mjpeg_stream::FrameQueue;
+use crate::output_vars::OutputVars;
+use crate::touch_gestures::TouchArea;
+#[cfg(feature = "webrtc")]
+use crate::webrtc_transport::VideoSink;
+
+// Image subscriptions of the ws clients. A subscription names the pop-out it wants to see, the
+// capture thread grabs every watched window once per frame and sends each subscriber its own
+// crop at its own rate. One subscription per ws connection, a client that wants two instruments
+// opens two connections. The /stream/{instrument}.mjpg responses are subscribers too, they always
+// get whole jpeg frames, WebRTC sessions get the raw frames for their video encoder.
+//
+//  IMAGESUBSCRIBE                  the selected pop-out (/set_hwnd) with the shared crop
+//  IMAGESUBSCRIBE:<hwnd>           a pop-out by hwnd
+//  IMAGESUBSCRIBE:<instrument>     a pop-out by its title
+//  IMAGESUBSCRIBE:{"hwnd":0,"instrument":"","crop":[[x,y],[w,h]],"refresh_ms":100,"delta":false,
+//                  "format":"jpeg","quality":60,"ack":true,"text":false}
+//  IMAGEUNSUBSCRIBE
+//  IMAGEKEYFRAME                   the next frame is sent whole
+//  IMAGEACK[:<seq>]                the client got the frame (the oldest one without a seq)
+//
+// The frame rate of every subscriber adapts between its refresh rate and the min_fps setting.
+// Clients that ack their frames get at most MAX_IN_FLIGHT frames ahead, a slow one gets frames
+// less often instead of a growing queue. For the others a full mailbox slows their stream down.
+//
+// Frames that didn't change since the last one a subscriber got aren't sent again. Without
+// "delta" every frame is a plain image (png unless the subscriber or the image_format setting
+// asks for another format), with it the frames are the tiles that changed, see frame_delta.
+//
+// With "text" the subscriber gets the page read by fms_ocr instead of images, whenever the text
+// changes. That needs the FMS font of the aircraft (fms_font in the addon config, set by /set_hwnd):
+//
+//  FMSTEXT:{"lines":["    INIT    ",...],"cells":[[{"char":"I","color":"white","small":false},...]],"unknown":0}
+//
+// Aircraft that publish their CDU screen in output vars (see output_vars) don't need either, text
+// subscribers get the page made from the vars and no window is captured for them.
+//
+// With the idle_capture setting a window whose pixels didn't change for IDLE_FRAMES captures is
+// captured less and less often, down to once per IDLE_MAX_INTERVAL. A button press or touch on
+// it (wake) brings it back to the full rate for a while, the screen is about to change then.
+
+pub const SUBSCRIBE: &str = "IMAGESUBSCRIBE";
+pub const UNSUBSCRIBE: &str = "IMAGEUNSUBSCRIBE";
+pub const KEYFRAME: &str = "IMAGEKEYFRAME";
+pub const ACK: &str = "IMAGEACK";
+pub const FMS_TEXT: &str = "FMSTEXT";
+const IDLE_SLEEP: Duration = Duration::from_millis(100);
+const MIN_REFRESH_MS: u16 = 10;
+// delta subscribers get a whole frame at least this often, in case a message got lost
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_IN_FLIGHT: usize = 2;
+// unacked frames older than this are considered lost
+const ACK_TIMEOUT: Duration = Duration::from_secs(3);
+const FPS_WINDOW: Duration = Duration::from_secs(5);
+// the interval grows this much when a client falls behind and shrinks when it keeps up
+const SLOW_DOWN: f64 = 1.5;
+const SPEED_UP: f64 = 0.9;
+const IDLE_FRAMES: u32 = 5;
+const IDLE_MAX_INTERVAL: Duration = Duration::from_secs(1);
+// full rate after a wake, menus and page changes take a moment to draw
+const BURST: Duration = Duration::from_secs(2);
+// http streams get the frame again this often even if it didn't change, a client that went
+// away is only noticed when something is written
+const STREAM_REPEAT: Duration = Duration::from_secs(2);
+// /image_state captures the window itself if the thread didn't recently
+const LATEST_MAX_AGE: Duration = Duration::from_secs(2);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub enum FrameMessage {
+    Image(Vec<u8>),
+    Text(String),
+}
+
+// where a subscriber's frames go
+#[derive(Clone)]
+pub enum FrameSink {
+    Ws(Recipient<FrameMessage>),
+    Http(Arc<FrameQueue>),
+    #[cfg(feature = "webrtc")]
+    Video(VideoSink),
+}
+
+impl FrameSink {
+    fn connected(&self) -> bool {
+        return match self {
+            FrameSink::Ws(recipient) => recipient.connected(),
+            FrameSink::Http(queue) => queue.is_open(),
+            #[cfg(feature = "webrtc")]
+            FrameSink::Video(video) => video.is_open()
+        };
+    }
+
+    fn is_http(&self) -> bool {
+        matches!(self, FrameSink::Http(_))
+    }
+
+    fn try_send(&self, frame: Vec<u8>) -> Result<(), String> {
+        return match self {
+            FrameSink::Ws(recipient) => recipient.try_send(FrameMessage::Image(frame)).map_err(|e| e.to_string()),
+            FrameSink::Http(queue) => queue.push(&frame).map_err(|e| e.to_string()),
+            // see send_frames
+            #[cfg(feature = "webrtc")]
+            FrameSink::Video(_) => Err("video sessions take raw frames".to_string())
+        };
+    }
+
+    fn try_send_text(&self, text: String) -> Result<(), String> {
+        return match self {
+            FrameSink::Ws(recipient) => recipient.try_send(FrameMessage::Text(text)).map_err(|e| e.to_string()),
+            _ => Err("only ws clients take text".to_string())
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct SubscribeRequest {
+    // 0 follows the selected pop-out
+    #[serde(default)]
+    pub hwnd: isize,
+    #[serde(default)]
+    pub instrument: String,
+    // [[crop_x, crop_y], [crop_w, crop_h]], the shared crop is used for the selected pop-out
+    // and the whole window for the others if it's missing
+    #[serde(default)]
+    pub crop: Option<[[i32; 2]; 2]>,
+    // the refresh_rate setting if it's missing
+    #[serde(default)]
+    pub refresh_ms: Option<u16>,
+    #[serde(default)]
+    pub delta: bool,
+    // the image_format/image_quality settings if they're missing
+    #[serde(default)]
+    pub format: Option<FrameFormat>,
+    #[serde(default)]
+    pub quality: Option<u8>,
+    // the client sends IMAGEACK for every frame
+    #[serde(default)]
+    pub ack: bool,
+    // FMSTEXT messages instead of images
+    #[serde(default)]
+    pub text: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ClientStatus {
+    pub id: u64,
+    pub hwnd: isize,
+    pub delta: bool,
+    pub format: Option<FrameFormat>,
+    pub ack: bool,
+    pub text: bool,
+    // what the adaptive rate allows now
+    pub target_fps: f64,
+    // frames actually sent in the last seconds, unchanged frames aren't sent
+    pub fps: f64,
+    pub rtt_ms: Option<f64>,
+    pub in_flight: usize,
+    pub dropped: u64,
+    // its window is captured at the idle rate
+    pub idle: bool,
+}
+
+impl SubscribeRequest {
+    // None if the text isn't a subscription
+    pub fn parse(text: &str) -> Option<SubscribeRequest> {
+        let rest = text.strip_prefix(SUBSCRIBE)?;
+        let rest = match rest.strip_prefix(':') {
+            Some(rest) => rest.trim(),
+            None if rest.is_empty() => "",
+            None => return None
+        };
+        if rest.is_empty() {
+            return Some(SubscribeRequest::default());
+        }
+        if rest.starts_with('{') {
+            return serde_json::from_str(rest).ok();
+        }
+        Some(SubscribeRequest::for_target(rest))
+    }
+
+    // a hwnd or an instrument title, empty for the selected pop-out
+    pub fn for_target(target: &str) -> SubscribeRequest {
+        return match target.parse::<isize>() {
+            Ok(hwnd) => SubscribeRequest { hwnd, ..Default::default() },
+            Err(_) => SubscribeRequest { instrument: target.to_string(), ..Default::default() }
+        };
+    }
+}
+
+struct Subscription {
+    id: u64,
+    hwnd: isize,
+    crop: Option<[[i32; 2]; 2]>,
+    refresh_ms: Option<u16>,
+    delta: bool,
+    format: Option<FrameFormat>,
+    quality: Option<u8>,
+    next_frame: Instant,
+    sink: FrameSink,
+    // the frame this subscriber has now, None until it got a whole one
+    last_frame: Option<Arc<RawCapture>>,
+    text: bool,
+    // the page text subscribers have
+    last_text: Option<String>,
+    last_keyframe: Instant,
+    force_keyframe: bool,
+    seq: u32,
+    ack: bool,
+    // current frame interval, between the refresh rate and 1000 / min_fps
+    interval_ms: f64,
+    // seq and send time of the frames that weren't acked yet
+    in_flight: VecDeque<(u32, Instant)>,
+    rtt_ms: Option<f64>,
+    sent_times: VecDeque<Instant>,
+    dropped: u64,
+}
+
+#[derive(PartialEq)]
+enum Outcome {
+    Sent,
+    // the subscriber already has these pixels
+    Unchanged,
+    Failed,
+}
+
+struct SendResult {
+    id: u64,
+    seq: u32,
+    // None for pages from output vars
+    frame: Option<Arc<RawCapture>>,
+    keyframe: bool,
+    outcome: Outcome,
+    text: Option<String>,
+}
+
+// a subscriber that's due this round, taken out of the list while the frames are made
+struct FrameJob {
+    id: u64,
+    delta: bool,
+    settings: EncoderSettings,
+    seq: u32,
+    last_frame: Option<Arc<RawCapture>>,
+    text: bool,
+    last_text: Option<String>,
+    sink: FrameSink,
+}
+
+// how long the pixels of a captured window stayed the same
+struct WindowActivity {
+    hash: u64,
+    unchanged: u32,
+    // captured at the idle rate now
+    idle: bool,
+    next_capture: Instant,
+    burst_until: Instant,
+}
+
+// the last capture of a window+crop, so unchanged frames are shared by pointer
+struct View {
+    frame: Arc<RawCapture>,
+    last_used: Instant,
+}
+
+pub struct ImageStreamer {
+    subscriptions: Mutex<Vec<Subscription>>,
+    activity: Mutex<HashMap<isize, WindowActivity>>,
+    // the last whole capture of every window the thread watches
+    latest: Mutex<HashMap<isize, (Arc<RawCapture>, Instant)>>,
+    // the FMS font of the current aircraft for text subscribers
+    fms_font: Mutex<Option<Arc<FmsFont>>>,
+    output_vars: Arc<OutputVars>,
+    next_id: AtomicU64,
+    thread_started: Mutex<bool>,
+    selected_hwnd: Arc<Mutex<isize>>,
+    display_crop: Arc<Mutex<[[i32; 2]; 2]>>,
+    config: Arc<Mutex<ConfigHandler>>,
+    log_str: Option<Arc<Mutex<String>>>,
+}
+
+impl ImageStreamer {
+    pub fn new(selected_hwnd: Arc<Mutex<isize>>, display_crop: Arc<Mutex<[[i32; 2]; 2]>>,
+               config: Arc<Mutex<ConfigHandler>>, output_vars: Arc<OutputVars>,
+               log_str: Option<Arc<Mutex<String>>>) -> Self {
+        ImageStreamer {
+            subscriptions: Mutex::new(vec![]),
+            activity: Mutex::new(HashMap::new()),
+            latest: Mutex::new(HashMap::new()),
+            fms_font: Mutex::new(None),
+            output_vars,
+            next_id: AtomicU64::new(1),
+            thread_started: Mutex::new(false),
+            selected_hwnd,
+            display_crop,
+            config,
+            log_str,
+        }
+    }
+
+    // returns the id of the new subscription, Err if the instrument or the pop-out can't be found
+    pub fn subscribe(self: &Arc<Self>, request: SubscribeRequest,
+                     sink: FrameSink) -> Result<u64, String> {
+        let mut hwnd = request.hwnd;
+        if hwnd != 0 && !ImageProcess::is_popup_window(hwnd) {
+            return Err(format!("Can't find pop-out: {}", hwnd));
+        }
+        if hwnd == 0 && !request.instrument.is_empty() {
+            hwnd = match ImageProcess::find_popup_windows().unwrap_or_default().iter()
+                .find(|popout| popout.title.eq_ignore_ascii_case(&request.instrument)) {
+                None => return Err(format!("Can't find instrument: {}", &request.instrument)),
+                Some(popout) => popout.hwnd
+            };
+        }
+        if request.text && self.fms_font().is_none() && !self.output_vars.active() {
+            return Err("No FMS font for this aircraft".to_string());
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        debug_logger::log(&*format!("New image subscription {}: hwnd: {}, crop: {:?}, refresh: {:?}, delta: {}, format: {:?}, text: {}",
+                                    id, hwnd, &request.crop, &request.refresh_ms, request.delta, &request.format, request.text),
+                          &self.log_str);
+        self.subscriptions.lock().unwrap().push(Subscription {
+            id,
+            hwnd,
+            crop: request.crop,
+            refresh_ms: request.refresh_ms,
+            delta: request.delta,
+            format: request.format,
+            quality: request.quality,
+            next_frame: Instant::now(),
+            sink,
+            last_frame: None,
+            text: request.text,
+            last_text: None,
+            last_keyframe: Instant::now(),
+            force_keyframe: true,
+            seq: 0,
+            ack: request.ack,
+            interval_ms: 0.0,
+            in_flight: VecDeque::new(),
+            rtt_ms: None,
+            sent_times: VecDeque::new(),
+            dropped: 0,
+        });
+        self.ensure_thread();
+        Ok(id)
+    }
+
+    pub fn unsubscribe(&self, id: u64) {
+        self.subscriptions.lock().unwrap().retain(|sub| sub.id != id);
+    }
+
+    // acks the frame with this seq and everything sent before it, the oldest frame without a seq
+    pub fn ack(&self, id: u64, seq: Option<u32>) {
+        let mut subs = self.subscriptions.lock().unwrap();
+        let sub = match subs.iter_mut().find(|sub| sub.id == id) {
+            None => return,
+            Some(sub) => sub
+        };
+        let position = match seq {
+            None => if sub.in_flight.is_empty() { None } else { Some(0) },
+            Some(seq) => sub.in_flight.iter().position(|(in_flight, _)| *in_flight == seq)
+        };
+        let position = match position {
+            None => return,
+            Some(position) => position
+        };
+        let (_, sent_at) = sub.in_flight[position];
+        sub.in_flight.drain(..=position);
+        let sample = sent_at.elapsed().as_secs_f64() * 1000.0;
+        sub.rtt_ms = Some(match sub.rtt_ms {
+            None => sample,
+            Some(rtt) => rtt * 0.8 + sample * 0.2
+        });
+        // keeping up, allowed to go faster (clamped to the refresh rate in the loop)
+        if sub.in_flight.is_empty() {
+            sub.interval_ms *= SPEED_UP;
+        }
+    }
+
+    // the pop-out and the part of it the frames of the subscription show, touches on them land
+    // there. The selected pop-out with the shared crop without a subscription
+    pub fn touch_target(&self, id: Option<u64>) -> Option<(isize, TouchArea)> {
+        let selected = *self.selected_hwnd.lock().unwrap();
+        let (hwnd, crop) = id.and_then(|id| self.subscriptions.lock().unwrap().iter()
+            .find(|sub| sub.id == id).map(|sub| (sub.hwnd, sub.crop)))
+            .unwrap_or((0, None));
+        let hwnd = if hwnd == 0 { selected } else { hwnd };
+        if hwnd == 0 {
+            return None;
+        }
+        let crop = match crop {
+            Some(crop) => crop,
+            None if hwnd == selected => *self.display_crop.lock().unwrap(),
+            None => [[0, 0], [0, 0]]
+        };
+        let geometry = backend().client_geometry(hwnd).ok()?;
+        // the crop is of the last capture, the client area if there's none yet
+        let capture = match self.latest_frame(hwnd) {
+            Some(frame) => [frame.width as i32, frame.height as i32],
+            None => [geometry.width, geometry.height]
+        };
+        Some((hwnd, TouchArea { crop, capture, client: [geometry.width, geometry.height], scale: geometry.scale }))
+    }
+
+    // full capture rate for the window for a while, 0 is ignored
+    pub fn wake(&self, hwnd: isize) {
+        if hwnd == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut activity = self.activity.lock().unwrap();
+        let window = activity.entry(hwnd).or_insert(WindowActivity {
+            hash: 0,
+            unchanged: 0,
+            idle: false,
+            next_capture: now,
+            burst_until: now,
+        });
+        window.unchanged = 0;
+        window.idle = false;
+        window.next_capture = now;
+        window.burst_until = now + BURST;
+    }
+
+    pub fn client_status(&self) -> Vec<ClientStatus> {
+        let selected = *self.selected_hwnd.lock().unwrap();
+        let activity = self.activity.lock().unwrap();
+        let subs = self.subscriptions.lock().unwrap();
+        subs.iter().map(|sub| {
+            let hwnd = if sub.hwnd == 0 { selected } else { sub.hwnd };
+            let recent = sub.sent_times.iter().filter(|sent| sent.elapsed() < FPS_WINDOW).count();
+            ClientStatus {
+                id: sub.id,
+                hwnd: sub.hwnd,
+                delta: sub.delta,
+                format: sub.format,
+                ack: sub.ack,
+                text: sub.text,
+                target_fps: if sub.interval_ms > 0.0 { 1000.0 / sub.interval_ms } else { 0.0 },
+                fps: recent as f64 / FPS_WINDOW.as_secs_f64(),
+                rtt_ms: sub.rtt_ms,
+                in_flight: sub.in_flight.len(),
+                dropped: sub.dropped,
+                idle: activity.get(&hwnd).map(|window| window.idle).unwrap_or(false),
+            }
+        }).collect()
+    }
+
+    pub fn request_keyframe(&self, id: u64) {
+        if let Some(sub) = self.subscriptions.lock().unwrap().iter_mut().find(|sub| sub.id == id) {
+            sub.force_keyframe = true;
+        }
+    }
+
+    pub fn set_fms_font(&self, font: Option<Arc<FmsFont>>) {
+        *self.fms_font.lock().unwrap() = font;
+    }
+
+    pub fn fms_font(&self) -> Option<Arc<FmsFont>> {
+        self.fms_font.lock().unwrap().clone()
+    }
+
+    // the last capture of the window if it's recent, 0 is the selected pop-out
+    pub fn latest_frame(&self, hwnd: isize) -> Option<Arc<RawCapture>> {
+        let hwnd = if hwnd == 0 { *self.selected_hwnd.lock().unwrap() } else { hwnd };
+        return match self.latest.lock().unwrap().get(&hwnd) {
+            Some((frame, captured_at)) if captured_at.elapsed() < LATEST_MAX_AGE => Some(Arc::clone(frame)),
+            _ => None
+        };
+    }
+
+    fn ensure_thread(self: &Arc<Self>) {
+        let mut started = self.thread_started.lock().unwrap();
+        if *started {
+            return;
+        }
+        debug_logger::log("Adding subscription thread...", &self.log_str);
+        *started = true;
+        let streamer = Arc::clone(self);
+        thread::spawn(move || streamer.run());
+    }
+
+    fn run(&self) {
+        let mut views: HashMap<(isize, [[i32; 2]; 2]), View> = HashMap::new();
+        loop {
+            let conf = self.config.lock().unwrap();
+            let default_refresh = conf.refresh_rate;
+            let min_fps = conf.min_fps.max(1);
+            let idle_capture = conf.idle_capture;
+            let default_settings = EncoderSettings { format: conf.image_format, quality: conf.image_quality };
+            drop(conf);
+            let selected = *self.selected_hwnd.lock().unwrap();
+            let shared_crop = *self.display_crop.lock().unwrap();
+            let text_from_vars = self.output_vars.active();
+
+            // hwnd -> crop -> the subscribers that want a frame of it now
+            let mut due: HashMap<isize, HashMap<[[i32; 2]; 2], Vec<FrameJob>>> = HashMap::new();
+            let mut var_jobs: Vec<FrameJob> = vec![];
+            let now = Instant::now();
+            let mut subs = self.subscriptions.lock().unwrap();
+            subs.retain(|sub| {
+                if !sub.sink.connected() {
+                    debug_logger::log("Removing a subscriber from img thread", &self.log_str);
+                }
+                sub.sink.connected()
+            });
+            for sub in subs.iter_mut() {
+                if sub.next_frame > now {
+                    continue;
+                }
+                let fastest = sub.refresh_ms.unwrap_or(default_refresh).max(MIN_REFRESH_MS) as f64;
+                let slowest = (1000.0 / min_fps as f64).max(fastest);
+                if sub.ack {
+                    while sub.in_flight.front().map(|(_, sent_at)| sent_at.elapsed() > ACK_TIMEOUT).unwrap_or(false) {
+                        sub.in_flight.pop_front();
+                        sub.dropped += 1;
+                        sub.force_keyframe = true;
+                    }
+                    // the client didn't catch up yet, skip this frame
+                    if sub.in_flight.len() >= MAX_IN_FLIGHT {
+                        sub.interval_ms = (sub.interval_ms * SLOW_DOWN).clamp(fastest, slowest);
+                        sub.next_frame = now + Duration::from_secs_f64(sub.interval_ms / 1000.0);
+                        continue;
+                    }
+                }
+                sub.interval_ms = sub.interval_ms.clamp(fastest, slowest);
+                sub.next_frame = now + Duration::from_secs_f64(sub.interval_ms / 1000.0);
+                if sub.text && text_from_vars {
+                    var_jobs.push(FrameJob {
+                        id: sub.id,
+                        delta: false,
+                        settings: default_settings,
+                        seq: sub.seq,
+                        last_frame: None,
+                        text: true,
+                        last_text: if sub.force_keyframe { None } else { sub.last_text.clone() },
+                        sink: sub.sink.clone(),
+                    });
+                    continue;
+                }
+                let hwnd = if sub.hwnd == 0 { selected } else { sub.hwnd };
+                if hwnd == 0 {
+                    continue;
+                }
+                let crop = match sub.crop {
+                    Some(crop) => crop,
+                    None if hwnd == selected => shared_crop,
+                    None => [[0, 0], [0, 0]]
+                };
+                let keyframe = sub.force_keyframe || (sub.delta && sub.last_keyframe.elapsed() > KEYFRAME_INTERVAL)
+                    || (sub.sink.is_http() && sub.last_keyframe.elapsed() > STREAM_REPEAT);
+                due.entry(hwnd).or_default().entry(crop).or_default().push(FrameJob {
+                    id: sub.id,
+                    delta: sub.delta,
+                    settings: EncoderSettings {
+                        format: sub.format.unwrap_or(default_settings.format),
+                        quality: sub.quality.unwrap_or(default_settings.quality),
+                    },
+                    seq: sub.seq,
+                    last_frame: if keyframe { None } else { sub.last_frame.clone() },
+                    text: sub.text,
+                    last_text: if keyframe { None } else { sub.last_text.clone() },
+                    sink: sub.sink.clone(),
+                });
+            }
+            let next_frame = subs.iter().map(|sub| sub.next_frame).min();
+            drop(subs);
+
+            let mut sent: Vec<SendResult> = vec![];
+            self.send_var_text(var_jobs, &mut sent);
+            for (hwnd, crops) in due {
+                // new subscribers and keyframes can't wait for an idle window
+                let waiting = crops.values().flatten().any(|job| job.last_frame.is_none());
+                if idle_capture && !waiting && !self.capture_due(hwnd) {
+                    continue;
+                }
+                let raw = match ImageProcess::capture_raw(hwnd) {
+                    Ok(raw) => raw,
+                    Err(e) => {
+                        println!("Cant capture instrument!:{}", e);
+                        continue;
+                    }
+                };
+                self.track_activity(hwnd, &raw, idle_capture);
+                let raw = Arc::new(raw);
+                self.latest.lock().unwrap().insert(hwnd, (Arc::clone(&raw), Instant::now()));
+                for (crop, jobs) in crops {
+                    let cropped = match crop {
+                        [[0, 0], [0, 0]] => Ok(RawCapture { pixels: raw.pixels.clone(), width: raw.width, height: raw.height }),
+                        _ => ImageProcess::crop_capture(&raw, crop)
+                    };
+                    let cropped = match cropped {
+                        Ok(cropped) => cropped,
+                        Err(_) => {
+                            debug_logger::log(&*format!("Crop {:?} doesn't fit pop-out {}", &crop, hwnd),
+                                              &self.log_str);
+                            continue;
+                        }
+                    };
+                    let frame = match views.get(&(hwnd, crop)) {
+                        Some(view) if view.frame.width == cropped.width && view.frame.height == cropped.height
+                            && view.frame.pixels == cropped.pixels => Arc::clone(&view.frame),
+                        _ => Arc::new(cropped)
+                    };
+                    views.insert((hwnd, crop), View { frame: Arc::clone(&frame), last_used: Instant::now() });
+                    self.send_frames(&frame, jobs, &mut sent);
+                }
+            }
+            views.retain(|_, view| view.last_used.elapsed() < KEYFRAME_INTERVAL);
+            self.activity.lock().unwrap()
+                .retain(|_, window| window.next_capture.elapsed() < KEYFRAME_INTERVAL);
+            self.latest.lock().unwrap().retain(|_, (_, captured_at)| captured_at.elapsed() < LATEST_MAX_AGE);
+
+            if !sent.is_empty() {
+                let mut subs = self.subscriptions.lock().unwrap();
+                for result in sent {
+                    let sub = match subs.iter_mut().find(|sub| sub.id == result.id) {
+                        None => continue,
+                        Some(sub) => sub
+                    };
+                    match result.outcome {
+                        Outcome::Sent => {
+                            sub.seq = sub.seq.wrapping_add(1);
+                            if result.keyframe {
+                                sub.force_keyframe = false;
+                                sub.last_keyframe = Instant::now();
+                            }
+                            if sub.ack {
+                                sub.in_flight.push_back((result.seq, Instant::now()));
+                            } else {
+                                sub.interval_ms *= SPEED_UP;
+                            }
+                            sub.sent_times.push_back(Instant::now());
+                            while sub.sent_times.front().map(|sent| sent.elapsed() > FPS_WINDOW).unwrap_or(false) {
+                                sub.sent_times.pop_front();
+                            }
+                            sub.last_frame = result.frame;
+                            sub.last_text = result.text;
+                        }
+                        Outcome::Unchanged => sub.last_frame = result.frame,
+                        // the mailbox is full, slow down and send a whole frame next
+                        Outcome::Failed => {
+                            sub.dropped += 1;
+                            sub.interval_ms *= SLOW_DOWN;
+                            sub.force_keyframe = true;
+                            sub.last_frame = None;
+                            sub.last_text = None;
+                        }
+                    }
+                }
+            }
+
+            let sleep = match next_frame {
+                None => IDLE_SLEEP,
+                Some(next_frame) => next_frame.saturating_duration_since(Instant::now()).min(IDLE_SLEEP)
+            };
+            thread::sleep(sleep);
+        }
+    }
+
+    fn capture_due(&self, hwnd: isize) -> bool {
+        return match self.activity.lock().unwrap().get(&hwnd) {
+            None => true,
+            Some(window) => window.next_capture <= Instant::now()
+        };
+    }
+
+    // compares the capture with the last one of the window and schedules the next capture
+    fn track_activity(&self, hwnd: isize, raw: &RawCapture, idle_capture: bool) {
+        let now = Instant::now();
+        let hash = pixel_hash(&raw.pixels);
+        let mut activity = self.activity.lock().unwrap();
+        let window = activity.entry(hwnd).or_insert(WindowActivity {
+            hash: !hash,
+            unchanged: 0,
+            idle: false,
+            next_capture: now,
+            burst_until: now,
+        });
+        if window.hash == hash {
+            window.unchanged = window.unchanged.saturating_add(1);
+        } else {
+            window.hash = hash;
+            window.unchanged = 0;
+        }
+        window.next_capture = now;
+        window.idle = idle_capture && window.unchanged >= IDLE_FRAMES && window.burst_until <= now;
+        if window.idle {
+            // 100ms, 200ms, 400ms... after the window went idle
+            let steps = (window.unchanged - IDLE_FRAMES).min(4);
+            window.next_capture = now + (IDLE_SLEEP * 2u32.pow(steps)).min(IDLE_MAX_INTERVAL);
+        }
+    }
+
+    fn send_frames(&self, frame: &Arc<RawCapture>, jobs: Vec<FrameJob>, sent: &mut Vec<SendResult>) {
+        // encoded once per window+crop and format, the deltas once per base frame
+        let mut encoded: HashMap<EncoderSettings, Option<Vec<u8>>> = HashMap::new();
+        let mut deltas: HashMap<usize, Option<Vec<[u32; 4]>>> = HashMap::new();
+        let total_tiles = frame.width.div_ceil(frame_delta::TILE_SIZE) * frame.height.div_ceil(frame_delta::TILE_SIZE);
+        // read once for all text subscribers, None without a font
+        let mut page_text: Option<Option<String>> = None;
+
+        for job in jobs {
+            if let Some(last_frame) = &job.last_frame {
+                if Arc::ptr_eq(last_frame, frame) {
+                    continue;
+                }
+            }
+            if job.text {
+                let text = match page_text.get_or_insert_with(|| self.read_page_text(frame)) {
+                    None => continue,
+                    Some(text) => text.clone()
+                };
+                // a new capture, but the page didn't change
+                let outcome = self.send_text(&job, &text);
+                sent.push(SendResult { id: job.id, seq: job.seq, frame: Some(Arc::clone(frame)), keyframe: true, outcome,
+                                       text: Some(text) });
+                continue;
+            }
+            #[cfg(feature = "webrtc")]
+            if let FrameSink::Video(video) = &job.sink {
+                let outcome = match video.try_send(frame) {
+                    Ok(_) => Outcome::Sent,
+                    Err(_) => Outcome::Failed
+                };
+                sent.push(SendResult { id: job.id, seq: job.seq, frame: Some(Arc::clone(frame)), keyframe: true, outcome,
+                                       text: None });
+                continue;
+            }
+            let tiles = match (&job.last_frame, job.delta) {
+                (Some(last_frame), true) => deltas.entry(Arc::as_ptr(last_frame) as usize)
+                    .or_insert_with(|| frame_delta::changed_tiles(last_frame, frame)).clone(),
+                _ => None
+            };
+            let delta = match tiles {
+                // same pixels in a new capture
+                Some(tiles) if tiles.is_empty() => {
+                    sent.push(SendResult { id: job.id, seq: job.seq, frame: Some(Arc::clone(frame)), keyframe: false,
+                                           outcome: Outcome::Unchanged, text: None });
+                    continue;
+                }
+                // a delta that touches most of the frame is bigger than a keyframe
+                Some(tiles) if (tiles.len() as u32) * 2 <= total_tiles => {
+                    frame_delta::encode_delta(frame, &tiles, job.settings, job.seq)
+                }
+                _ => None
+            };
+            let (message, keyframe) = match delta {
+                Some(delta) => (delta, false),
+                None => {
+                    let whole = encoded.entry(job.settings).or_insert_with(|| {
+                        frame_encoder::encode(&frame.pixels, frame.width, frame.height, job.settings).ok()
+                    });
+                    let whole = match whole {
+                        Some(whole) => whole,
+                        None => {
+                            debug_logger::log(&*format!("Cant encode frame as {:?}", &job.settings), &self.log_str);
+                            continue;
+                        }
+                    };
+                    if job.delta {
+                        (frame_delta::encode_keyframe(frame, whole, job.settings, job.seq), true)
+                    } else {
+                        (whole.clone(), true)
+                    }
+                }
+            };
+            let outcome = match job.sink.try_send(message) {
+                Ok(_) => Outcome::Sent,
+                Err(e) => {
+                    debug_logger::log(format!("Cant send img from sub thread: {}", e).as_str(),
+                                      &self.log_str);
+                    Outcome::Failed
+                }
+            };
+            sent.push(SendResult { id: job.id, seq: job.seq, frame: Some(Arc::clone(frame)), keyframe, outcome, text: None });
+        }
+    }
+
+    // text subscribers while the aircraft publishes its screen in output vars
+    fn send_var_text(&self, jobs: Vec<FrameJob>, sent: &mut Vec<SendResult>) {
+        let text = match self.output_vars.page_json() {
+            // nothing from the bridge yet
+            None => return,
+            Some(text) => text
+        };
+        for job in jobs {
+            let outcome = self.send_text(&job, &text);
+            sent.push(SendResult { id: job.id, seq: job.seq, frame: None, keyframe: true, outcome,
+                                   text: Some(text.clone()) });
+        }
+    }
+
+    fn send_text(&self, job: &FrameJob, text: &String) -> Outcome {
+        if job.last_text.as_ref() == Some(text) {
+            return Outcome::Unchanged;
+        }
+        return match job.sink.try_send_text(format!("{}:{}", FMS_TEXT, text)) {
+            Ok(_) => Outcome::Sent,
+            Err(e) => {
+                debug_logger::log(&*format!("Cant send FMS text from sub thread: {}", e), &self.log_str);
+                Outcome::Failed
+            }
+        };
+    }
+
+    fn read_page_text(&self, frame: &RawCapture) -> Option<String> {
+        let font = self.fms_font()?;
+        let page = fms_ocr::read_page(&font, &frame.pixels, frame.width, frame.height);
+        serde_json::to_string(&page).ok()
+    }
+}
+
+// FNV-1a over 8 byte words, only used to see whether a window changed
+fn pixel_hash(pixels: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut words = pixels.chunks_exact(8);
+    for word in &mut words {
+        hash ^= u64::from_le_bytes(word.try_into().unwrap());
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    for byte in words.remainder() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,512 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+// Touches from the clients end up here as pointer events on the selected pop-out, x and y are
+// pixels of its client area as the pop-out sees them (real pixels on a scaled monitor for a
+// dpi aware pop-out). The backends:
+//  message    (windows, default) posts the mouse messages to the pop-out: the system cursor and
+//             the focus stay with the pilot, and it works on pop-outs hidden off screen too
+//  cursor     (windows) the old way, moves the real cursor there, clicks and puts it back. For
+//             pop-outs that ignore posted messages, needs the pop-out on screen
+//  x11        (x11 feature, Proton) XSendEvent to the X window of the pop-out, or XTest with
+//             REACHFMS_INPUT=xtest, which moves the real pointer. The hwnd isn't an X window: Wine
+//             gives the X window the title of the pop-out and makes it transient for the X window of
+//             MSFS, a pop-out without one is refused
+//  recording  keeps the events in memory, used by the tests and where nothing else is available
+//
+// REACHFMS_INPUT overrides the input_method setting (message, cursor, x11, xtest, recording).
+
+pub const INPUT_ENV: &str = "REACHFMS_INPUT";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InputMethod {
+    #[default]
+    Message,
+    Cursor,
+    X11,
+    Xtest,
+    Recording,
+}
+
+impl InputMethod {
+    pub fn from_name(name: &str) -> Option<InputMethod> {
+        return match name.to_lowercase().as_str() {
+            "message" => Some(InputMethod::Message),
+            "cursor" => Some(InputMethod::Cursor),
+            "x11" => Some(InputMethod::X11),
+            "xtest" => Some(InputMethod::Xtest),
+            "recording" => Some(InputMethod::Recording),
+            _ => None
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PointerEvent {
+    // a drag while the button is down
+    Move,
+    Down,
+    Up,
+    // scroll by delta, 120 a notch (WHEEL_DELTA), positive away from the user
+    Wheel(i32),
+}
+
+pub trait InputInjector: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn pointer(&self, hwnd: isize, event: PointerEvent, x: i32, y: i32) -> Result<(), String>;
+    // brings the pop-out to the front to click it
+    fn takes_focus(&self) -> bool {
+        false
+    }
+}
+
+// down, held for hold, up. Blocks for hold: run it on the blocking pool, not on the actix workers
+pub fn click(injector: &dyn InputInjector, hwnd: isize, x: i32, y: i32, hold: Duration) -> Result<(), String> {
+    injector.pointer(hwnd, PointerEvent::Move, x, y)?;
+    injector.pointer(hwnd, PointerEvent::Down, x, y)?;
+    thread::sleep(hold);
+    injector.pointer(hwnd, PointerEvent::Up, x, y)
+}
+
+// the backend for the method, or the reason it can't be used
+pub fn create_injector(method: InputMethod) -> Result<Box<dyn InputInjector>, String> {
+    let method = std::env::var(INPUT_ENV).ok()
+        .and_then(|name| InputMethod::from_name(&name)).unwrap_or(method);
+    return match method {
+        InputMethod::Recording => Ok(Box::new(RecordingInjector::new())),
+        #[cfg(windows)]
+        InputMethod::Message => Ok(Box::new(win32::MessageInjector::new())),
+        #[cfg(windows)]
+        InputMethod::Cursor => Ok(Box::new(win32::CursorInjector::new())),
+        #[cfg(feature = "x11")]
+        InputMethod::X11 => Ok(Box::new(x11::X11Injector::connect(false)?)),
+        #[cfg(feature = "x11")]
+        InputMethod::Xtest => Ok(Box::new(x11::X11Injector::connect(true)?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(format!("Input method {:?} isn't available in this build", method))
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RecordedEvent {
+    pub hwnd: isize,
+    pub event: PointerEvent,
+    pub x: i32,
+    pub y: i32,
+    pub at: Instant,
+}
+
+pub struct RecordingInjector {
+    events: Mutex<Vec<RecordedEvent>>,
+}
+
+impl RecordingInjector {
+    pub fn new() -> Self {
+        RecordingInjector { events: Mutex::new(vec![]) }
+    }
+
+    // read by the tests
+    #[allow(dead_code)]
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl InputInjector for RecordingInjector {
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn pointer(&self, hwnd: isize, event: PointerEvent, x: i32, y: i32) -> Result<(), String> {
+        self.events.lock().unwrap().push(RecordedEvent { hwnd, event, x, y, at: Instant::now() });
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+pub mod win32 {
+    use std::mem;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use windows::Win32::Foundation::{HWND, LPARAM, POINT, WPARAM};
+    use windows::Win32::Graphics::Gdi::ClientToScreen;
+    use windows::Win32::System::SystemServices::MK_LBUTTON;
+    use windows::Win32::UI::Input::KeyboardAndMouse::{mouse_event, MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP,
+                                                   MOUSEEVENTF_WHEEL, SetFocus};
+    use windows::Win32::UI::WindowsAndMessaging::{GetCursorPos, GetForegroundWindow, GetSystemMetrics,
+                                                  PostMessageW, SetCursorPos, SetForegroundWindow,
+                                                  SM_CXSCREEN, SM_CYSCREEN, WM_LBUTTONDOWN, WM_LBUTTONUP,
+                                                  WM_MOUSEMOVE, WM_MOUSEWHEEL};
+    use crate::capture_backend::win32::dpi_scale;
+    use super::{InputInjector, PointerEvent};
+
+    // the cursor has to be there before the button goes down
+    const CURSOR_SETTLE: Duration = Duration::from_millis(50);
+
+    pub struct MessageInjector {
+        pressed: Mutex<bool>,
+    }
+
+    impl MessageInjector {
+        pub fn new() -> Self {
+            MessageInjector { pressed: Mutex::new(false) }
+        }
+    }
+
+    impl InputInjector for MessageInjector {
+        fn name(&self) -> &'static str {
+            "message"
+        }
+
+        fn pointer(&self, hwnd: isize, event: PointerEvent, x: i32, y: i32) -> Result<(), String> {
+            let mut pressed = self.pressed.lock().unwrap();
+            let (message, buttons) = match event {
+                PointerEvent::Move => (WM_MOUSEMOVE, if *pressed { MK_LBUTTON.0 } else { 0 }),
+                PointerEvent::Down => (WM_LBUTTONDOWN, MK_LBUTTON.0),
+                PointerEvent::Up => (WM_LBUTTONUP, 0),
+                PointerEvent::Wheel(delta) => (WM_MOUSEWHEEL, (delta as i16 as u16 as u32) << 16 |
+                    if *pressed { MK_LBUTTON.0 } else { 0 }),
+            };
+            unsafe {
+                let hwnd_in: HWND = mem::transmute(hwnd);
+                // the wheel message has screen coordinates
+                let mut point = POINT { x, y };
+                if let PointerEvent::Wheel(_) = event {
+                    let _ = ClientToScreen(hwnd_in, &mut point);
+                }
+                let position = ((point.y as u16 as u32) << 16 | point.x as u16 as u32) as isize;
+                if PostMessageW(hwnd_in, message, WPARAM(buttons as usize), LPARAM(position)).is_err() {
+                    return Err("error: can't post to the pop-out".to_string());
+                }
+            }
+            match event {
+                PointerEvent::Down => *pressed = true,
+                PointerEvent::Up => *pressed = false,
+                _ => {}
+            }
+            Ok(())
+        }
+    }
+
+    pub struct CursorInjector {
+        // where the pilot's cursor was when the button went down
+        original_pos: Mutex<Option<POINT>>,
+    }
+
+    impl CursorInjector {
+        pub fn new() -> Self {
+            CursorInjector { original_pos: Mutex::new(None) }
+        }
+    }
+
+    impl InputInjector for CursorInjector {
+        fn name(&self) -> &'static str {
+            "cursor"
+        }
+
+        fn takes_focus(&self) -> bool {
+            true
+        }
+
+        fn pointer(&self, hwnd: isize, event: PointerEvent, x: i32, y: i32) -> Result<(), String> {
+            unsafe {
+                let hwnda_to_use: HWND = mem::transmute(hwnd);
+                // Convert client coordinates (x, y) to screen coordinates, the ones this process
+                // sees if Windows scales the pop-out for it
+                let scale = dpi_scale(hwnda_to_use);
+                let mut click_point = POINT { x: (x as f64 / scale) as i32, y: (y as f64 / scale) as i32 };
+                let _ = ClientToScreen(hwnda_to_use, &mut click_point);
+                let sx = GetSystemMetrics(SM_CXSCREEN);
+                let sy = GetSystemMetrics(SM_CYSCREEN);
+                if click_point.x > sx || click_point.y > sy {
+                    return Err("error: the pop-out is hidden off screen, use the message input method".to_string());
+                }
+                let absolute_x = click_point.x * 65536 / sx;
+                let absolute_y = click_point.y * 65536 / sy;
+
+                let mut original_pos = self.original_pos.lock().unwrap();
+                match event {
+                    PointerEvent::Move => {
+                        let _ = SetCursorPos(click_point.x, click_point.y);
+                    }
+                    PointerEvent::Down => {
+                        let mut current = POINT { x: 0, y: 0 };
+                        if GetCursorPos(&mut current as *mut POINT).is_ok() {
+                            *original_pos = Some(current);
+                        }
+                        if GetForegroundWindow() != hwnda_to_use {
+                            let _ = SetForegroundWindow(hwnda_to_use);
+                            SetFocus(hwnda_to_use);
+                        }
+                        let _ = SetCursorPos(click_point.x, click_point.y);
+                        thread::sleep(CURSOR_SETTLE);
+                        mouse_event(MOUSEEVENTF_LEFTDOWN, absolute_x, absolute_y, 0, 0);
+                    }
+                    PointerEvent::Up => {
+                        mouse_event(MOUSEEVENTF_LEFTUP, absolute_x, absolute_y, 0, 0);
+                        thread::sleep(CURSOR_SETTLE);
+                        if let Some(original) = original_pos.take() {
+                            let _ = SetCursorPos(original.x, original.y);
+                        }
+                    }
+                    PointerEvent::Wheel(delta) => {
+                        // outside of a drag the cursor goes back right away
+                        let mut current = POINT { x: 0, y: 0 };
+                        let restore = original_pos.is_none() && GetCursorPos(&mut current as *mut POINT).is_ok();
+                        let _ = SetCursorPos(click_point.x, click_point.y);
+                        thread::sleep(CURSOR_SETTLE);
+                        mouse_event(MOUSEEVENTF_WHEEL, 0, 0, delta, 0);
+                        if restore {
+                            thread::sleep(CURSOR_SETTLE);
+                            let _ = SetCursorPos(current.x, current.y);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "x11")]
+pub mod x11 {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{Atom, AtomEnum, ButtonPressEvent, ConnectionExt, EventMask, KeyButMask, Motion,
+                                  MotionNotifyEvent, Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT,
+                                  MOTION_NOTIFY_EVENT};
+    use x11rb::protocol::xtest::ConnectionExt as XTestConnectionExt;
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::{CURRENT_TIME, NONE};
+    use crate::capture_backend::backend;
+    use super::{InputInjector, PointerEvent};
+
+    const LEFT_BUTTON: u8 = 1;
+    const WHEEL_DELTA: i32 = 120;
+    // X has no wheel, a notch is a click of these
+    const WHEEL_UP_BUTTON: u8 = 4;
+    const WHEEL_DOWN_BUTTON: u8 = 5;
+
+    pub struct X11Injector {
+        conn: RustConnection,
+        root: Window,
+        // XTest moves the real pointer, XSendEvent only tells the window
+        xtest: bool,
+        pressed: Mutex<bool>,
+        net_wm_name: Atom,
+        // the X window of each pop-out touched so far
+        windows: Mutex<HashMap<isize, Window>>,
+    }
+
+    // the button and the number of clicks of a wheel delta, at least one
+    fn wheel_clicks(delta: i32) -> (u8, i32) {
+        let button = if delta > 0 { WHEEL_UP_BUTTON } else { WHEEL_DOWN_BUTTON };
+        (button, (delta.abs() / WHEEL_DELTA).max(1))
+    }
+
+    impl X11Injector {
+        // the display of DISPLAY
+        pub fn connect(xtest: bool) -> Result<Self, String> {
+            let (conn, screen_num) = x11rb::connect(None).map_err(|e| e.to_string())?;
+            let root = conn.setup().roots[screen_num].root;
+            let net_wm_name = conn.intern_atom(false, b"_NET_WM_NAME").map_err(|e| e.to_string())?
+                .reply().map_err(|e| e.to_string())?.atom;
+            Ok(X11Injector { conn, root, xtest, pressed: Mutex::new(false), net_wm_name,
+                windows: Mutex::new(HashMap::new()) })
+        }
+
+        fn property(&self, window: Window, property: Atom) -> Result<Vec<u8>, String> {
+            let reply = self.conn.get_property(false, window, property, AtomEnum::ANY, 0, 1024)
+                .map_err(|e| e.to_string())?
+                .reply().map_err(|e| e.to_string())?;
+            Ok(reply.value)
+        }
+
+        // _NET_WM_NAME, or WM_NAME for a window without it
+        fn window_name(&self, window: Window) -> Result<String, String> {
+            let mut name = self.property(window, self.net_wm_name)?;
+            if name.is_empty() {
+                name = self.property(window, AtomEnum::WM_NAME.into())?;
+            }
+            Ok(String::from_utf8_lossy(&name).to_string())
+        }
+
+        fn transient_for(&self, window: Window) -> Result<Window, String> {
+            let reply = self.conn.get_property(false, window, AtomEnum::WM_TRANSIENT_FOR, AtomEnum::WINDOW, 0, 1)
+                .map_err(|e| e.to_string())?
+                .reply().map_err(|e| e.to_string())?;
+            Ok(reply.value32().and_then(|mut value| value.next()).unwrap_or(NONE))
+        }
+
+        // every window of the display with its name, window managers put the named ones in frames
+        fn named_windows(&self) -> Result<Vec<(Window, String)>, String> {
+            let mut named = vec![];
+            let mut next = vec![self.root];
+            while let Some(window) = next.pop() {
+                let tree = match self.conn.query_tree(window).map_err(|e| e.to_string())?.reply() {
+                    Ok(tree) => tree,
+                    // closed meanwhile
+                    Err(_) => continue
+                };
+                for child in tree.children {
+                    if let Ok(name) = self.window_name(child) {
+                        if !name.is_empty() {
+                            named.push((child, name));
+                        }
+                    }
+                    next.push(child);
+                }
+            }
+            Ok(named)
+        }
+
+        // the window with the title of the pop-out that's transient for the one with the title of MSFS
+        fn find_window(&self, hwnd: isize) -> Result<Window, String> {
+            let backend = backend();
+            let list = backend.window_list().map_err(|_| "Can't list the windows".to_string())?;
+            let title_of = |hwnd: isize| list.iter().find(|info| info.hwnd == hwnd).map(|info| info.title.clone());
+            let title = title_of(hwnd).ok_or(format!("No window {}", hwnd))?;
+            let sim_title = title_of(backend.window_owner(hwnd)).ok_or(format!("{} isn't a pop-out", title))?;
+
+            let named = self.named_windows()?;
+            let sim_windows: Vec<Window> = named.iter().filter(|(_, name)| name == &sim_title)
+                .map(|(window, _)| *window).collect();
+            for (window, name) in &named {
+                if name == &title && self.transient_for(*window).map(|owner| sim_windows.contains(&owner))
+                    .unwrap_or(false) {
+                    return Ok(*window);
+                }
+            }
+            Err(format!("{} has no X window under {}, the x11 input method needs MSFS on this display",
+                        title, sim_title))
+        }
+
+        fn x_window(&self, hwnd: isize) -> Result<Window, String> {
+            if let Some(window) = self.windows.lock().unwrap().get(&hwnd) {
+                return Ok(*window);
+            }
+            let window = self.find_window(hwnd)?;
+            self.windows.lock().unwrap().insert(hwnd, window);
+            Ok(window)
+        }
+
+        fn send_motion(&self, window: Window, x: i16, y: i16, root_x: i16, root_y: i16) -> Result<(), String> {
+            let state = if *self.pressed.lock().unwrap() { KeyButMask::BUTTON1 } else { KeyButMask::from(0u16) };
+            self.conn.send_event(false, window, EventMask::POINTER_MOTION, MotionNotifyEvent {
+                response_type: MOTION_NOTIFY_EVENT,
+                detail: Motion::NORMAL,
+                sequence: 0,
+                time: CURRENT_TIME,
+                root: self.root,
+                event: window,
+                child: NONE,
+                root_x,
+                root_y,
+                event_x: x,
+                event_y: y,
+                state,
+                same_screen: true,
+            }).map(drop).map_err(|e| e.to_string())
+        }
+
+        fn send_button(&self, window: Window, press: bool, button: u8, x: i16, y: i16,
+                       root_x: i16, root_y: i16) -> Result<(), String> {
+            let state = if *self.pressed.lock().unwrap() { KeyButMask::BUTTON1 } else { KeyButMask::from(0u16) };
+            let (response_type, mask) = match press {
+                true => (BUTTON_PRESS_EVENT, EventMask::BUTTON_PRESS),
+                false => (BUTTON_RELEASE_EVENT, EventMask::BUTTON_RELEASE)
+            };
+            self.conn.send_event(false, window, mask, ButtonPressEvent {
+                response_type,
+                detail: button,
+                sequence: 0,
+                time: CURRENT_TIME,
+                root: self.root,
+                event: window,
+                child: NONE,
+                root_x,
+                root_y,
+                event_x: x,
+                event_y: y,
+                state,
+                same_screen: true,
+            }).map(drop).map_err(|e| e.to_string())
+        }
+
+        fn send_event(&self, window: Window, event: PointerEvent, x: i16, y: i16,
+                      root_x: i16, root_y: i16) -> Result<(), String> {
+            match event {
+                PointerEvent::Move => self.send_motion(window, x, y, root_x, root_y)?,
+                PointerEvent::Down => self.send_button(window, true, LEFT_BUTTON, x, y, root_x, root_y)?,
+                PointerEvent::Up => self.send_button(window, false, LEFT_BUTTON, x, y, root_x, root_y)?,
+                PointerEvent::Wheel(delta) => {
+                    let (button, clicks) = wheel_clicks(delta);
+                    for _ in 0..clicks {
+                        self.send_button(window, true, button, x, y, root_x, root_y)?;
+                        self.send_button(window, false, button, x, y, root_x, root_y)?;
+                    }
+                }
+            }
+            match event {
+                PointerEvent::Down => *self.pressed.lock().unwrap() = true,
+                PointerEvent::Up => *self.pressed.lock().unwrap() = false,
+                _ => {}
+            }
+            Ok(())
+        }
+
+        fn fake_button(&self, press: bool, button: u8, root_x: i16, root_y: i16) -> Result<(), String> {
+            let kind = if press { BUTTON_PRESS_EVENT } else { BUTTON_RELEASE_EVENT };
+            self.conn.xtest_fake_input(kind, button, CURRENT_TIME, self.root, root_x, root_y, 0)
+                .map(drop).map_err(|e| e.to_string())
+        }
+
+        fn fake_input(&self, event: PointerEvent, root_x: i16, root_y: i16) -> Result<(), String> {
+            self.conn.xtest_fake_input(MOTION_NOTIFY_EVENT, 0, CURRENT_TIME, self.root, root_x, root_y, 0)
+                .map(drop).map_err(|e| e.to_string())?;
+            return match event {
+                PointerEvent::Move => Ok(()),
+                PointerEvent::Down => self.fake_button(true, LEFT_BUTTON, root_x, root_y),
+                PointerEvent::Up => self.fake_button(false, LEFT_BUTTON, root_x, root_y),
+                PointerEvent::Wheel(delta) => {
+                    let (button, clicks) = wheel_clicks(delta);
+                    for _ in 0..clicks {
+                        self.fake_button(true, button, root_x, root_y)?;
+                        self.fake_button(false, button, root_x, root_y)?;
+                    }
+                    Ok(())
+                }
+            };
+        }
+    }
+
+    impl InputInjector for X11Injector {
+        fn name(&self) -> &'static str {
+            if self.xtest { "xtest" } else { "x11" }
+        }
+
+        fn pointer(&self, hwnd: isize, event: PointerEvent, x: i32, y: i32) -> Result<(), String> {
+            let window = self.x_window(hwnd)?;
+            let translated = match self.conn.translate_coordinates(window, self.root, x as i16, y as i16)
+                .map_err(|e| e.to_string())?.reply() {
+                Ok(translated) => translated,
+                Err(e) => {
+                    // gone, looked up again on the next touch
+                    self.windows.lock().unwrap().remove(&hwnd);
+                    return Err(e.to_string());
+                }
+            };
+            if self.xtest {
+                self.fake_input(event, translated.dst_x, translated.dst_y)?;
+            } else {
+                self.send_event(window, event, x as i16, y as i16, translated.dst_x, translated.dst_y)?;
+            }
+            self.conn.flush().map_err(|e| e.to_string())
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use crate::config_handler::get_instrument_settings_file;
+use crate::debug_logger;
+use crate::image_process::{InstrumentRgb, MCDU_TITLE, UNKNOWN_TITLE};
+
+// What the user set for a pop-out, kept in data/instrument_settings.json by aircraft and pop-out
+// title (the hwnd changes whenever a pop-out is opened) and applied when the same pop-out of the
+// same aircraft shows up again. Untitled pop-outs (UNKNOWN, FMS once selected) share one entry.
+//
+// {
+//   "instruments": {"FNX320": {"FMS": {"auto_hide": false, "crop": [[0, 10], [700, 680]]}}},
+//   "last_selected": {"FNX320": "FMS"}
+// }
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct InstrumentSettings {
+    // None keeps what a fresh scan gives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_hide: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub excluded: Option<bool>,
+    // [[crop_x, crop_y], [crop_w, crop_h]] instead of the crop from the aircraft's fms_aspect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<[[i32; 2]; 2]>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SettingsFile {
+    // aircraft -> pop-out title -> settings
+    #[serde(default)]
+    instruments: HashMap<String, HashMap<String, InstrumentSettings>>,
+    // aircraft -> title of the pop-out selected last
+    #[serde(default)]
+    last_selected: HashMap<String, String>,
+}
+
+pub struct InstrumentSettingsStore {
+    file: Mutex<SettingsFile>,
+    log_str: Option<Arc<Mutex<String>>>,
+}
+
+impl InstrumentSettingsStore {
+    pub fn load(log_str: Option<Arc<Mutex<String>>>) -> Self {
+        let file = match fs::read_to_string(get_instrument_settings_file()) {
+            // nothing saved yet
+            Err(_) => SettingsFile::default(),
+            Ok(string_data) => match serde_json::from_str(&string_data) {
+                Ok(file) => file,
+                Err(e) => {
+                    debug_logger::log(&*format!("Invalid instrument settings, starting over: {}", e), &log_str);
+                    SettingsFile::default()
+                }
+            }
+        };
+        InstrumentSettingsStore { file: Mutex::new(file), log_str }
+    }
+
+    pub fn get(&self, aircraft: &str, title: &str) -> InstrumentSettings {
+        self.file.lock().unwrap().instruments.get(aircraft)
+            .and_then(|instruments| instruments.get(title_key(title)))
+            .cloned().unwrap_or_default()
+    }
+
+    // changes the settings of the pop-out and saves them
+    pub fn update<F: FnOnce(&mut InstrumentSettings)>(&self, aircraft: &str, title: &str, change: F) {
+        if aircraft.is_empty() {
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
+        change(file.instruments.entry(aircraft.to_string()).or_default()
+            .entry(title_key(title).to_string()).or_default());
+        self.save(&file);
+    }
+
+    pub fn set_last_selected(&self, aircraft: &str, title: &str) {
+        if aircraft.is_empty() {
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
+        if file.last_selected.get(aircraft).map(|last| last == title_key(title)).unwrap_or(false) {
+            return;
+        }
+        file.last_selected.insert(aircraft.to_string(), title_key(title).to_string());
+        self.save(&file);
+    }
+
+    // the pop-out selected last time with this aircraft, if it's open and not excluded
+    pub fn last_selected_in<'a>(&self, aircraft: &str, instruments: &'a [InstrumentRgb]) -> Option<&'a InstrumentRgb> {
+        let last = self.file.lock().unwrap().last_selected.get(aircraft)?.clone();
+        instruments.iter().find(|instrument| !instrument.excluded && title_key(&instrument.instrument) == last)
+    }
+
+    // the stored flags on the pop-outs of a fresh scan
+    pub fn apply(&self, aircraft: &str, instruments: &mut [InstrumentRgb]) {
+        for instrument in instruments.iter_mut() {
+            let settings = self.get(aircraft, &instrument.instrument);
+            if let Some(auto_hide) = settings.auto_hide {
+                instrument.auto_hide = auto_hide;
+            }
+            if let Some(excluded) = settings.excluded {
+                instrument.excluded = excluded;
+            }
+        }
+    }
+
+    fn save(&self, file: &SettingsFile) {
+        let json_string = serde_json::to_string_pretty(file).unwrap();
+        if fs::write(get_instrument_settings_file(), json_string).is_err() {
+            debug_logger::log("Unable to write instrument settings", &self.log_str);
+        }
+    }
+}
+
+// a selected untitled pop-out is called FMS, it's the same window
+fn title_key(title: &str) -> &str {
+    if title == MCDU_TITLE { UNKNOWN_TITLE } else { title }
+}
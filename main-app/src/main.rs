@@ -8,17 +8,43 @@ mod comm_sender;
 mod addon_config;
 mod mobiflight_installer;
 mod debug_logger;
+mod capture_backend;
+mod bridge_protocol;
+mod bridge_supervisor;
+mod image_streamer;
+mod input_injector;
+mod touch_gestures;
+mod pairing;
+mod tls;
+mod instrument_settings;
+mod window_layouts;
+mod window_journal;
+mod frame_delta;
+mod frame_encoder;
+mod mjpeg_stream;
+// learning fonts is for fms_font_tool
+#[allow(dead_code)]
+mod fms_ocr;
+mod output_vars;
+#[cfg(feature = "webrtc")]
+mod video_encoder;
+#[cfg(feature = "webrtc")]
+mod webrtc_transport;
 
 use std::{thread, time};
+#[cfg(windows)]
 use std::os::windows::process::CommandExt;
 use std::process::{Command};
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use fltk::{enums::{Color, Font, FrameType, Cursor}, prelude::*, *};
 use fltk::app::{screen_size};
 use fltk::enums::{Event};
-use crate::config_handler::ConfigHandler;
+use crate::config_handler::{ConfigHandler, get_http_port};
 use crate::debug_logger::show_warning_dialog;
 use crate::image_process::ImageProcess;
+use crate::pairing::DeviceInfo;
+use crate::window_journal::window_journal;
 
 // TODO:
 //aircrafts: MD82, bae146, ATR42
@@ -32,6 +58,8 @@ enum Message {
     CloseAllUrl,
     AutomaticInstall,
     ManualInstall,
+    Devices,
+    RefreshQr,
 }
 
 struct McduApp {
@@ -44,6 +72,11 @@ struct McduApp {
     receiver: app::Receiver<Message>,
     bridge_started: bool,
     qr_frame: frame::Frame,
+    // SHA-256 of the https certificate, the pair page shows it too
+    fingerprint_text: frame::Frame,
+    // when the shown QR code was written, a new one is written after every pairing
+    qr_modified: Option<SystemTime>,
+    devices_button: button::Button,
     url_text: button::Button,
     get_started: frame::Frame,
     url_not_working: button::Button,
@@ -61,7 +94,10 @@ struct McduApp {
 const CLOSE_WARNING: &str = "Can't find MSFS window: can't restore popout windows. \
                     It is recommended to close ReachFMS first while all pop-outs still open to \
                     let it restore all the pop-out windows!";
+// puts back the pop-outs a killed ReachFMS left below the screen, without starting the app
+const RESTORE_WINDOWS_ARG: &str = "--restore-windows";
 const WINDOW_BG_COLOR: Color = Color::from_rgb(21, 26, 32);
+const QR_REFRESH_SECS: f64 = 1.0;
 impl McduApp {
     pub fn new() -> Self {
         let app = app::App::default();
@@ -74,6 +110,7 @@ impl McduApp {
         let mut start_button = button::Button::new(0, 350, 160, 40, "Start server").center_x(&main_win);
 
         let qr_frame = frame::Frame::new(0, 150, 200, 200, "").center_x(&main_win);
+        let mut fingerprint_text = frame::Frame::new(405, 150, 190, 200, "");
 
         let mut status_text = frame::Frame::new(0, 60, 140, 20, "")
             .center_x(&main_win)
@@ -87,6 +124,9 @@ impl McduApp {
             .center_x(&main_win)
             .with_label("Displayed ip adress is not correct?");
 
+        let mut devices_button = button::Button::new(440, 372, 150, 20, "")
+            .with_label("Paired devices");
+
         let version_string: String = format!("v{}", env!("CARGO_PKG_VERSION"));
         let mut version_label = frame::Frame::new(5, 5, 30, 20, "")
             .with_label(&*version_string);
@@ -208,6 +248,40 @@ impl McduApp {
         url_not_working.set_frame(FrameType::NoBox);
         url_not_working.hide();
 
+        devices_button.set_label_size(15);
+        devices_button.set_label_color(Color::from_rgb(169, 169, 169));
+        devices_button.set_label_font(Font::Helvetica);
+        devices_button.handle(move |b, event| match event {
+            Event::Enter => {
+                b.set_label_color(Color::from_rgb(0, 255, 255));
+                b.redraw();
+                true
+            }
+            Event::Leave => {
+                b.set_label_color(Color::from_rgb(169, 169, 169));
+                b.redraw();
+                true
+            }
+            Event::Push => {
+                b.emit(s, Message::Devices);
+                true
+            }
+            _ => false,
+        });
+        devices_button.set_frame(FrameType::NoBox);
+        devices_button.hide();
+
+        fingerprint_text.set_label_size(12);
+        fingerprint_text.set_label_color(Color::from_rgb(169, 169, 169));
+        fingerprint_text.set_label_font(Font::Courier);
+        fingerprint_text.hide();
+
+        // the QR code is replaced once a device used it
+        app::add_timeout3(QR_REFRESH_SECS, move |handle| {
+            s.send(Message::RefreshQr);
+            app::repeat_timeout3(QR_REFRESH_SECS, handle);
+        });
+
         //welcome_text.set_align(Align::Right);
 
         //all_ip_pack.set_color(Color::from_rgb(128, 128, 128));
@@ -383,7 +457,7 @@ impl McduApp {
                 if !ImageProcess::restore_all() && *once_starated_inside.lock().unwrap() {
                     show_warning_dialog(CLOSE_WARNING);
                 }
-                match reqwest::blocking::get("http://localhost:5273/stop_server") {
+                match reqwest::blocking::get(format!("http://localhost:{}/stop_server", get_http_port())) {
                     Ok(..) => {
                         debug_logger::log("Server closed, closing the app.", &exit_log);
                         app::quit();
@@ -409,6 +483,9 @@ impl McduApp {
             receiver,
             bridge_started: false,
             qr_frame,
+            fingerprint_text,
+            qr_modified: None,
+            devices_button,
             url_text,
             get_started,
             url_not_working,
@@ -440,7 +517,7 @@ impl McduApp {
                         self.main_win.set_cursor(Cursor::Wait);
                         if self.bridge_started {
                             ImageProcess::restore_all();
-                            let resp = reqwest::blocking::get("http://localhost:5273/stop_server");
+                            let resp = reqwest::blocking::get(format!("http://localhost:{}/stop_server", get_http_port()));
                             match &resp {
                                 Ok(..) => {
                                     resp.unwrap().text().unwrap();
@@ -452,9 +529,11 @@ impl McduApp {
                                     self.bridge_started = false;
 
                                     self.qr_frame.hide();
+                                    self.fingerprint_text.hide();
                                     self.url_text.hide();
                                     self.all_ip_pack.hide();
                                     self.url_not_working.hide();
+                                    self.devices_button.hide();
 
                                     self.get_started.show();
                                 }
@@ -463,7 +542,7 @@ impl McduApp {
                                 }
                             }
                         } else {
-                            let resp = reqwest::blocking::get("http://localhost:5273/start_server");
+                            let resp = reqwest::blocking::get(format!("http://localhost:{}/start_server", get_http_port()));
                             match &resp {
                                 Ok(..) => {
                                     resp.unwrap().text().unwrap();
@@ -477,13 +556,11 @@ impl McduApp {
                                     self.bridge_started = true;
                                     //self.hpack.show();
                                     self.qr_frame.show();
-                                    let mut qr_image = image::PngImage::load(config_handler::get_qr_file()).unwrap();
-                                    qr_image.scale(200, 200, true, true);
-                                    self.qr_frame.set_image(Some(qr_image));
-                                    self.qr_frame.redraw();
+                                    self.load_qr();
 
                                     self.url_text.show();
                                     self.url_not_working.show();
+                                    self.devices_button.show();
                                     {
                                         let mut started = self.once_started.lock().unwrap();
                                         if !*started {
@@ -499,10 +576,7 @@ impl McduApp {
                         self.main_win.set_cursor(Cursor::Default);
                     }
                     Message::Url => {
-                        if let Ok(_child) = Command::new("cmd.exe").creation_flags(0x00000008u32)
-                            .arg("/C").arg("start").arg("").arg(&self.url_text.label()).spawn() {
-                            thread::sleep(time::Duration::new(3, 0)); // On windows need to allow time for browser to start
-                        }
+                        open_in_browser(&self.url_text.label());
                     }
                     Message::Continue => {
                         if !self.mobi_text.visible() && self.instructions.visible() {
@@ -536,11 +610,15 @@ impl McduApp {
                         self.adress_list.set_label(&addr_str);
 
                         self.qr_frame.hide();
+                        self.fingerprint_text.hide();
                         self.all_ip_pack.show();
                     }
                     Message::CloseAllUrl => {
                         self.all_ip_pack.hide();
                         self.qr_frame.show();
+                        if !self.fingerprint_text.label().is_empty() {
+                            self.fingerprint_text.show();
+                        }
                     }
                     Message::AutomaticInstall => {
                         self.install_automatically.hide();
@@ -565,19 +643,140 @@ impl McduApp {
                           \n Once you finished installing, click the Check button to continue.");
                         self.continue_button.show();
 
-                        if let Ok(_child) = Command::new("cmd.exe").creation_flags(0x00000008u32)
-                            .arg("/C").arg("start").arg("").arg("https://github.com/MobiFlight/MobiFlight-WASM-Module/releases/latest/").spawn() {
-                            thread::sleep(time::Duration::new(3, 0)); // On windows need to allow time for browser to start
+                        open_in_browser("https://github.com/MobiFlight/MobiFlight-WASM-Module/releases/latest/");
+                    }
+                    Message::Devices => manage_paired_devices(&log_str),
+                    Message::RefreshQr => {
+                        let modified = std::fs::metadata(config_handler::get_qr_file())
+                            .and_then(|metadata| metadata.modified()).ok();
+                        if self.bridge_started && modified != self.qr_modified {
+                            self.load_qr();
                         }
                     }
                 }
             }
         }
     }
+
+    fn load_qr(&mut self) {
+        self.qr_modified = std::fs::metadata(config_handler::get_qr_file())
+            .and_then(|metadata| metadata.modified()).ok();
+        let mut qr_image = image::PngImage::load(config_handler::get_qr_file()).unwrap();
+        qr_image.scale(200, 200, true, true);
+        self.qr_frame.set_image(Some(qr_image));
+        self.qr_frame.redraw();
+
+        // of the certificate served now, the QR code has it too
+        let fingerprint = reqwest::blocking::get(format!("http://localhost:{}/reachfms.crt", get_http_port())).ok()
+            .filter(|resp| resp.status().is_success())
+            .and_then(|resp| resp.bytes().ok())
+            .map(|cert| tls::display_fingerprint(&tls::fingerprint(&cert)));
+        match fingerprint {
+            Some(fingerprint) => {
+                let pairs: Vec<&str> = fingerprint.split(':').collect();
+                let lines: Vec<String> = pairs.chunks(8).map(|line| line.join(":")).collect();
+                self.fingerprint_text.set_label(&format!("HTTPS certificate\nSHA-256:\n\n{}", lines.join("\n")));
+                if !self.all_ip_pack.visible() {
+                    self.fingerprint_text.show();
+                }
+            }
+            None => {
+                self.fingerprint_text.set_label("");
+                self.fingerprint_text.hide();
+            }
+        }
+        self.fingerprint_text.redraw();
+    }
+}
+
+#[cfg(windows)]
+fn open_in_browser(url: &str) {
+    if let Ok(_child) = Command::new("cmd.exe").creation_flags(0x00000008u32)
+        .arg("/C").arg("start").arg("").arg(url).spawn() {
+        thread::sleep(time::Duration::new(3, 0)); // On windows need to allow time for browser to start
+    }
+}
+
+#[cfg(not(windows))]
+fn open_in_browser(url: &str) {
+    if let Ok(_child) = Command::new("xdg-open").arg(url).spawn() {
+        thread::sleep(time::Duration::new(3, 0));
+    }
+}
+
+fn restore_windows_headless() -> i32 {
+    return match window_journal().restore() {
+        Ok(count) => {
+            println!("Restored {} pop-out windows, {} left", count, window_journal().pending());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    };
+}
+
+// entries left in the journal: the last run didn't get to restore the pop-outs
+fn offer_window_restore(log_str: &Option<Arc<Mutex<String>>>) {
+    let pending = window_journal().pending();
+    if pending == 0 {
+        return;
+    }
+    debug_logger::log(&*format!("Unclean shutdown, {} pop-outs in the window journal", pending), log_str);
+    let message = format!("ReachFMS wasn't closed properly last time, {} pop-out windows may still be \
+                           hidden below the screen. Restore them now?", pending);
+    if dialog::choice2_default(&message, "Restore", "Not now", "") != Some(0) {
+        return;
+    }
+    match window_journal().restore() {
+        Ok(count) => debug_logger::log(&*format!("Restored {} pop-outs from the journal", count), log_str),
+        Err(e) => show_warning_dialog(&e)
+    }
+}
+
+// the devices that scanned the QR code, they lose access once revoked
+fn manage_paired_devices(log_str: &Option<Arc<Mutex<String>>>) {
+    let devices = match reqwest::blocking::get(format!("http://localhost:{}/pairings", get_http_port()))
+        .and_then(|resp| resp.json::<Vec<DeviceInfo>>()) {
+        Ok(devices) => devices,
+        Err(..) => {
+            debug_logger::log("Cant access /pairings in main", log_str);
+            return;
+        }
+    };
+    if devices.is_empty() {
+        dialog::message_default("No paired devices. Scan the QR code with a device to pair it.");
+        return;
+    }
+    let list: Vec<String> = devices.iter().enumerate()
+        .map(|(i, device)| format!("{}. {} (paired {})", i + 1, device.name, device.paired_at))
+        .collect();
+    let message = format!("Paired devices:\n\n{}", list.join("\n"));
+    let id = match dialog::choice2_default(&message, "Close", "Revoke one", "Revoke all") {
+        Some(1) => {
+            let number = dialog::input_default("Number of the device to revoke:", "")
+                .and_then(|number| number.trim().parse::<usize>().ok());
+            match number.and_then(|number| devices.get(number.wrapping_sub(1))) {
+                Some(device) => device.id.clone(),
+                None => return
+            }
+        }
+        Some(2) => "all".to_string(),
+        _ => return
+    };
+    match reqwest::blocking::get(format!("http://localhost:{}/pairings/revoke?id={}", get_http_port(), id)) {
+        Ok(..) => debug_logger::log(&*format!("Revoked pairing: {}", id), log_str),
+        Err(..) => debug_logger::log("Cant access /pairings/revoke in main", log_str)
+    }
 }
 
 fn main() {
+    if std::env::args().any(|arg| arg == RESTORE_WINDOWS_ARG) {
+        std::process::exit(restore_windows_headless());
+    }
     let app = McduApp::new();
     let log_str = debug_logger::clone_log(&app.log_str);
+    offer_window_restore(&log_str);
     app.run(log_str);
 }
\ No newline at end of file
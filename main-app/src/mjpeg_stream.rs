@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::web::Bytes;
+
+// multipart/x-mixed-replace body of the /stream/{instrument}.mjpg responses. The capture thread
+// pushes jpeg frames into the queue, the response sends them as parts as fast as the client reads
+// them. A client that doesn't keep up finds the queue full and gets frames less often.
+// Every part ends with the next boundary, browsers only draw a part once they see one.
+
+pub const BOUNDARY: &str = "reachfmsframe";
+const QUEUE_LEN: usize = 2;
+
+struct QueueState {
+    parts: VecDeque<Bytes>,
+    waker: Option<Waker>,
+    closed: bool,
+    started: bool,
+}
+
+pub struct FrameQueue {
+    state: Mutex<QueueState>,
+}
+
+impl FrameQueue {
+    pub fn new() -> Arc<Self> {
+        Arc::new(FrameQueue {
+            state: Mutex::new(QueueState { parts: VecDeque::new(), waker: None, closed: false, started: false }),
+        })
+    }
+
+    // false once the client went away
+    pub fn is_open(&self) -> bool {
+        !self.state.lock().unwrap().closed
+    }
+
+    pub fn push(&self, jpeg: &[u8]) -> Result<(), &'static str> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err("stream closed");
+        }
+        if state.parts.len() >= QUEUE_LEN {
+            return Err("stream queue full");
+        }
+        let mut part = Vec::with_capacity(jpeg.len() + 128);
+        if !state.started {
+            part.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            state.started = true;
+        }
+        part.extend_from_slice(format!("Content-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n", jpeg.len()).as_bytes());
+        part.extend_from_slice(jpeg);
+        part.extend_from_slice(format!("\r\n--{}\r\n", BOUNDARY).as_bytes());
+        state.parts.push_back(Bytes::from(part));
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+pub struct MjpegBody {
+    queue: Arc<FrameQueue>,
+}
+
+impl MjpegBody {
+    pub fn new(queue: Arc<FrameQueue>) -> Self {
+        MjpegBody { queue }
+    }
+}
+
+impl MessageBody for MjpegBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let mut state = self.queue.state.lock().unwrap();
+        if let Some(part) = state.parts.pop_front() {
+            return Poll::Ready(Some(Ok(part)));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+// the response is dropped when the client disconnects, the subscription goes with the queue
+impl Drop for MjpegBody {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        state.closed = true;
+        state.parts.clear();
+    }
+}
@@ -10,10 +10,12 @@ use crate::debug_logger::show_fatal_error;
 pub fn get_community_folder() -> Result<String, bool> {
     #[cfg(windows)]
     let mut file_path = std::env::var("APPDATA").expect("No APP_DATA1 directory") + "\\Microsoft Flight Simulator\\UserCfg.opt";
+    #[cfg(not(windows))]
+    let mut file_path = String::new();
 
 
     if !std::path::Path::new(&file_path).exists() {
-        file_path = std::env::var("LOCALAPPDATA").expect("No APP_DATA1 directory")
+        file_path = std::env::var("LOCALAPPDATA").unwrap_or_default()
             + "\\Packages\\Microsoft.FlightSimulator_8wekyb3d8bbwe\\LocalCache\\UserCfg.opt";
         if !std::path::Path::new(&file_path).exists() {
             show_fatal_error("Can't find your community folder!");
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::bridge_protocol::{BridgeCommand, VAR_SUBSCRIPTION_VERSION};
+use crate::comm_sender::BridgeDispatcher;
+use crate::debug_logger;
+use crate::fms_ocr::{FmsCell, FmsPage, COLUMNS, ROWS, UNKNOWN_CHAR};
+
+// The output vars of the current aircraft (output_vars in the addon config). The bridge pushes
+// their values when they change (SubscribeVars, bridge protocol 3). Aircraft that publish their
+// CDU screen in them get it made into the same page fms_ocr reads from captures, text subscribers
+// and /fms_text use that page then, so the pop-out isn't captured and doesn't need hiding. Older
+// bridges and aircraft without it fall back to reading the capture. fms_vars in the addon config
+// tells how the vars make the page:
+//
+//  "rows"      a var per CDU row, top to bottom, the text with the colour and size tags of the
+//              FBW MCDU: "{small}{cyan}CO RTE{end}{end}      {amber}LFPG/EDDF{end}"
+//  "pmdg_cdu"  the first var is the CDU screen of the PMDG SDK, a client data area the bridge
+//              sends hex encoded ("CD:PMDG_NG3_CDU_0", needs EnableCDUBroadcast in the aircraft's
+//              options): 24 columns of 14 cells of symbol, colour and flags, then the power flag
+
+const SEND_TIMEOUT: Duration = Duration::from_millis(200);
+const TAG_COLORS: [&str; 7] = ["white", "green", "cyan", "amber", "magenta", "yellow", "red"];
+// the colour numbers of the PMDG SDK
+const PMDG_COLORS: [&str; 6] = ["white", "cyan", "green", "magenta", "amber", "red"];
+const PMDG_FLAG_SMALL: u8 = 0x01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PageFormat {
+    Rows,
+    PmdgCdu,
+}
+
+impl PageFormat {
+    // None for aircraft that don't publish their screen
+    pub fn parse(text: &str) -> Option<PageFormat> {
+        return match text {
+            "rows" => Some(PageFormat::Rows),
+            "pmdg_cdu" => Some(PageFormat::PmdgCdu),
+            _ => None
+        };
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VarSource {
+    pub vars: Vec<String>,
+    pub format: PageFormat,
+}
+
+pub struct OutputVars {
+    source: Mutex<Option<VarSource>>,
+    values: Mutex<HashMap<String, String>>,
+    // the page json made from the values, None until they arrived
+    page: Mutex<Option<String>>,
+    bridge: Arc<BridgeDispatcher>,
+    log_str: Option<Arc<Mutex<String>>>,
+}
+
+impl OutputVars {
+    pub fn new(bridge: Arc<BridgeDispatcher>, log_str: Option<Arc<Mutex<String>>>) -> Self {
+        OutputVars {
+            source: Mutex::new(None),
+            values: Mutex::new(HashMap::new()),
+            page: Mutex::new(None),
+            bridge,
+            log_str,
+        }
+    }
+
+    // subscribes to the vars of the aircraft, the previous ones are dropped
+    pub fn select(&self, source: Option<VarSource>) {
+        let mut current = self.source.lock().unwrap();
+        if *current == source {
+            return;
+        }
+        debug_logger::log(&*format!("Output vars: {:?}", &source), &self.log_str);
+        *current = source;
+        drop(current);
+        self.values.lock().unwrap().clear();
+        *self.page.lock().unwrap() = None;
+        if self.bridge.bridge_version() >= VAR_SUBSCRIPTION_VERSION {
+            let command = self.subscription().unwrap_or(BridgeCommand::SubscribeVars { names: vec![] });
+            self.bridge.send_with_timeout(command, SEND_TIMEOUT);
+        }
+    }
+
+    // what a newly connected bridge gets, None without vars
+    pub fn subscription(&self) -> Option<BridgeCommand> {
+        self.source.lock().unwrap().as_ref()
+            .map(|source| BridgeCommand::SubscribeVars { names: source.vars.clone() })
+    }
+
+    // the screen comes from the vars, the capture isn't needed. Not before the first values, an
+    // aircraft can leave them empty (PMDG without EnableCDUBroadcast)
+    pub fn active(&self) -> bool {
+        self.page.lock().unwrap().is_some()
+    }
+
+    // the aircraft publishes its screen and the bridge pushes it, the pop-out doesn't need hiding
+    // even before the first values
+    pub fn available(&self) -> bool {
+        self.source.lock().unwrap().is_some() && self.bridge.bridge_version() >= VAR_SUBSCRIPTION_VERSION
+    }
+
+    pub fn update(&self, changed: HashMap<String, String>) {
+        let source = match self.source.lock().unwrap().clone() {
+            None => return,
+            Some(source) => source
+        };
+        let mut values = self.values.lock().unwrap();
+        values.extend(changed);
+        let page = match source.format {
+            PageFormat::Rows => {
+                let rows: Vec<&str> = source.vars.iter()
+                    .map(|name| values.get(name).map(|value| value.as_str()).unwrap_or("")).collect();
+                Some(rows_page(&rows))
+            }
+            PageFormat::PmdgCdu => source.vars.first()
+                .and_then(|name| values.get(name))
+                .and_then(|value| pmdg_cdu_page(value))
+        };
+        drop(values);
+        if let Some(page) = page {
+            *self.page.lock().unwrap() = serde_json::to_string(&page).ok();
+        }
+    }
+
+    // the bridge went away, its values are stale
+    pub fn bridge_lost(&self) {
+        self.values.lock().unwrap().clear();
+        *self.page.lock().unwrap() = None;
+    }
+
+    pub fn page_json(&self) -> Option<String> {
+        self.page.lock().unwrap().clone()
+    }
+}
+
+fn rows_page(rows: &[&str]) -> FmsPage {
+    let cells = (0..ROWS as usize).map(|row| markup_row(rows.get(row).unwrap_or(&""))).collect();
+    page_from_cells(cells)
+}
+
+// every colour or size tag lasts until its {end}, {sp} is a space
+pub fn markup_row(text: &str) -> Vec<FmsCell> {
+    let mut cells: Vec<FmsCell> = vec![];
+    let mut tags: Vec<&str> = vec![];
+    let mut rest = text;
+    while cells.len() < COLUMNS as usize {
+        let tag = rest.strip_prefix('{').and_then(|tag| tag.find('}').map(|end| &tag[..end]));
+        if let Some(tag) = tag {
+            rest = &rest[tag.len() + 2..];
+            match tag {
+                "end" => { tags.pop(); }
+                "sp" => cells.push(blank_cell()),
+                _ => tags.push(tag)
+            }
+            continue;
+        }
+        let char = match rest.chars().next() {
+            None => break,
+            Some(char) => char
+        };
+        rest = &rest[char.len_utf8()..];
+        if char == ' ' {
+            cells.push(blank_cell());
+            continue;
+        }
+        let color = tags.iter().rev().find(|tag| TAG_COLORS.contains(tag)).unwrap_or(&"white");
+        let small = tags.iter().rev().find(|tag| **tag == "small" || **tag == "big") == Some(&"small");
+        cells.push(FmsCell { char, color: color.to_string(), small });
+    }
+    cells.resize(COLUMNS as usize, blank_cell());
+    cells
+}
+
+// None if the data isn't a CDU screen, a CDU without power is blank
+pub fn pmdg_cdu_page(hex: &str) -> Option<FmsPage> {
+    let bytes: Vec<u8> = (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    let size = (COLUMNS * ROWS * 3) as usize;
+    if bytes.len() < size + 1 {
+        return None;
+    }
+    let powered = bytes[size] != 0;
+    let cells = (0..ROWS as usize).map(|row| (0..COLUMNS as usize).map(|column| {
+        let cell = &bytes[(column * ROWS as usize + row) * 3..][..3];
+        let char = match cell[0] {
+            _ if !powered => ' ',
+            0 | b' ' => ' ',
+            0x21..=0x7e => cell[0] as char,
+            0xa1 => '←',
+            0xa2 => '→',
+            0xb0 => '°',
+            0xea => '□',
+            _ => UNKNOWN_CHAR
+        };
+        if char == ' ' {
+            return blank_cell();
+        }
+        FmsCell {
+            char,
+            color: PMDG_COLORS.get(cell[1] as usize).unwrap_or(&"white").to_string(),
+            small: cell[2] & PMDG_FLAG_SMALL != 0,
+        }
+    }).collect()).collect();
+    Some(page_from_cells(cells))
+}
+
+fn blank_cell() -> FmsCell {
+    FmsCell { char: ' ', color: "".to_string(), small: false }
+}
+
+fn page_from_cells(cells: Vec<Vec<FmsCell>>) -> FmsPage {
+    let lines = cells.iter().map(|row| row.iter().map(|cell| cell.char).collect()).collect();
+    let unknown = cells.iter().flatten().filter(|cell| cell.char == UNKNOWN_CHAR).count() as u32;
+    FmsPage { lines, cells, unknown }
+}
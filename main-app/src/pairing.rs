@@ -0,0 +1,189 @@
+use std::fs;
+use std::sync::Mutex;
+use std::time::Duration;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Devices on the network have to be paired before they can use the server. The QR code shown in
+// the app opens /pair with a one-time token: the device that opens it gets a session cookie and the
+// token is replaced by a new one (the QR code is written again). The token is only kept in memory,
+// a restart makes the old QR code useless. The sessions are kept in data/pairings.json as hashes,
+// the file doesn't give access to anyone reading it:
+//
+// {"devices": [{"id": "3f9c1a2b", "name": "Mozilla/5.0 (iPad...", "session_hash": "9b1e...", "paired_at": "2026-10-18 09:12:44 UTC"}]}
+//
+// The app itself (the GUI and the bridge) talks to the server on localhost, those don't need a
+// session unless the request comes from a web page of another site (a browser on the same pc).
+
+pub const SESSION_COOKIE: &str = "reachfms_session";
+pub const PAIR_PATH: &str = "/pair";
+pub const NOT_PAIRED: &str = "Not paired: scan the QR code shown in ReachFMS";
+// how soon the open connections of a revoked device are closed
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// the names the app reaches the server by, see is_local_host
+const LOCAL_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+// the name a device gives itself is cut, it's only shown in the list
+const MAX_NAME_LEN: usize = 80;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PairedDevice {
+    pub id: String,
+    pub name: String,
+    session_hash: String,
+    pub paired_at: String,
+}
+
+// what the api and the GUI get, the session hash stays in the store
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub paired_at: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PairingFile {
+    #[serde(default)]
+    devices: Vec<PairedDevice>,
+}
+
+pub struct PairingStore {
+    file_path: String,
+    file: Mutex<PairingFile>,
+    token: Mutex<String>,
+}
+
+impl PairingStore {
+    pub fn open(file_path: String) -> Self {
+        // a broken file is started over, the devices have to be paired again
+        let file = fs::read_to_string(&file_path).ok()
+            .and_then(|string_data| serde_json::from_str(&string_data).ok())
+            .unwrap_or_default();
+        PairingStore { file_path, file: Mutex::new(file), token: Mutex::new(random_hex(16)) }
+    }
+
+    // the token of the QR code shown now
+    pub fn token(&self) -> String {
+        self.token.lock().unwrap().clone()
+    }
+
+    // a new device with the token of the QR code, gives its session; the token can't be used again
+    pub fn pair(&self, token: &str, name: &str, paired_at: String) -> Result<String, String> {
+        let mut current = self.token.lock().unwrap();
+        if !constant_time_eq(token.as_bytes(), current.as_bytes()) {
+            return Err("The QR code is out of date, scan the one shown in ReachFMS".to_string());
+        }
+        *current = random_hex(16);
+        let session = random_hex(32);
+        let mut file = self.file.lock().unwrap();
+        file.devices.push(PairedDevice {
+            id: random_hex(4),
+            name: name.chars().take(MAX_NAME_LEN).collect(),
+            session_hash: hash(&session),
+            paired_at,
+        });
+        self.save(&file)?;
+        Ok(session)
+    }
+
+    // the id of the device the session belongs to
+    pub fn device(&self, session: &str) -> Option<String> {
+        let session_hash = hash(session);
+        self.file.lock().unwrap().devices.iter()
+            .find(|device| constant_time_eq(device.session_hash.as_bytes(), session_hash.as_bytes()))
+            .map(|device| device.id.clone())
+    }
+
+    pub fn has_device(&self, id: &str) -> bool {
+        self.file.lock().unwrap().devices.iter().any(|device| device.id == id)
+    }
+
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.file.lock().unwrap().devices.iter()
+            .map(|device| DeviceInfo { id: device.id.clone(), name: device.name.clone(), paired_at: device.paired_at.clone() })
+            .collect()
+    }
+
+    // false if there was no such device
+    pub fn revoke(&self, id: &str) -> Result<bool, String> {
+        let mut file = self.file.lock().unwrap();
+        let count = file.devices.len();
+        file.devices.retain(|device| device.id != id);
+        if file.devices.len() == count {
+            return Ok(false);
+        }
+        self.save(&file)?;
+        Ok(true)
+    }
+
+    pub fn revoke_all(&self) -> Result<(), String> {
+        let mut file = self.file.lock().unwrap();
+        file.devices.clear();
+        self.save(&file)
+    }
+
+    fn save(&self, file: &PairingFile) -> Result<(), String> {
+        let json_string = serde_json::to_string(file).map_err(|e| e.to_string())?;
+        return match fs::write(&self.file_path, json_string) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Unable to write {}: {}", self.file_path, e))
+        };
+    }
+}
+
+// the pages a device needs to get paired
+pub fn is_public_path(path: &str) -> bool {
+    return match path {
+        // the certificate of the https server is installed before pairing over it
+        "/" | "/icon.png" | "/reachfms.crt" | PAIR_PATH => true,
+        _ => path.starts_with("/static/")
+    };
+}
+
+// true if a web page of another site made the browser send the request
+pub fn is_cross_site(sec_fetch_site: Option<&str>, origin: Option<&str>, host: Option<&str>) -> bool {
+    if let Some(site) = sec_fetch_site {
+        return site != "same-origin" && site != "none";
+    }
+    return match (origin, host) {
+        // not a browser, or an older one without Sec-Fetch-Site on a same origin GET
+        (None, _) => false,
+        // http or https, the ports tell them apart
+        (Some(origin), Some(host)) => origin.split_once("://").map(|(_, origin_host)| origin_host) != Some(host),
+        (Some(_), None) => true
+    };
+}
+
+// true if the request was sent to a loopback name: a page of another site can get a browser on
+// this pc to the loopback address with a name of its own that resolves there (dns rebinding)
+pub fn is_local_host(host: Option<&str>) -> bool {
+    return match host {
+        None => true,
+        Some(host) => {
+            let name = match host.rsplit_once(':') {
+                Some((name, port)) if !port.contains(']') => name,
+                _ => host
+            };
+            LOCAL_HOSTS.contains(&name)
+        }
+    };
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    rand::thread_rng().fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash(session: &str) -> String {
+    Sha256::digest(session.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// the time a comparison takes doesn't tell how much of the token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
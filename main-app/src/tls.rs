@@ -0,0 +1,103 @@
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use chrono::{Datelike, Utc};
+use rcgen::{CertificateParams, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose};
+use rustls::ServerConfig;
+use sha2::{Digest, Sha256};
+
+// HTTPS next to the plain HTTP of 5273, for the browser features that need a secure context
+// (fullscreen, wake lock, installing the client as a PWA). Off by default, see tls_enabled in the
+// config; it's bound when the server starts. Without a configured certificate one is made on the
+// first run and kept in data/: self-signed, for localhost and the addresses the pc had then (delete
+// the two files to get a new one, e.g. after the pc got another address). It's a server certificate,
+// not a CA: a device that installs it from /reachfms.crt trusts this server and nothing else, and
+// the key in data/ can't sign certificates for other sites.
+// The pairing QR code carries the SHA-256 fingerprint of the certificate: /pair refuses a QR code
+// made for another one, and the pair page and the app show it to compare with what the browser shows.
+
+pub const TLS_PORT: u16 = 5274;
+// Apple doesn't accept server certificates valid for longer
+const VALID_DAYS: i64 = 825;
+// made again a bit before it expires, the devices have to trust the new one
+const RENEW_DAYS: u64 = 800;
+
+pub struct TlsIdentity {
+    pub server_config: ServerConfig,
+    // the server's own certificate, DER
+    pub cert: Vec<u8>,
+    pub fingerprint: String,
+}
+
+// makes the self-signed certificate if it's missing or about to expire, true if it made one
+pub fn ensure_self_signed(cert_file: &str, key_file: &str, hosts: &[String]) -> Result<bool, String> {
+    let age = fs::metadata(cert_file).and_then(|metadata| metadata.modified()).ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    let renew_after = Duration::from_secs(RENEW_DAYS * 24 * 60 * 60);
+    if std::path::Path::new(key_file).exists() && age.map(|age| age < renew_after).unwrap_or(false) {
+        return Ok(false);
+    }
+    let mut params = CertificateParams::new(hosts.to_vec())
+        .map_err(|e| format!("Invalid certificate host: {}", e))?;
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, "ReachFMS");
+    params.distinguished_name = name;
+    params.is_ca = IsCa::NoCa;
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let now = Utc::now();
+    let not_before = now - chrono::Duration::days(1);
+    let not_after = now + chrono::Duration::days(VALID_DAYS);
+    params.not_before = rcgen::date_time_ymd(not_before.year(), not_before.month() as u8, not_before.day() as u8);
+    params.not_after = rcgen::date_time_ymd(not_after.year(), not_after.month() as u8, not_after.day() as u8);
+    let key = KeyPair::generate().map_err(|e| format!("Can't make a key: {}", e))?;
+    let cert = params.self_signed(&key).map_err(|e| format!("Can't make a certificate: {}", e))?;
+    fs::write(key_file, key.serialize_pem()).map_err(|e| format!("Unable to write {}: {}", key_file, e))?;
+    fs::write(cert_file, cert.pem()).map_err(|e| format!("Unable to write {}: {}", cert_file, e))?;
+    Ok(true)
+}
+
+// the certificate chain and the key from PEM files
+pub fn load(cert_file: &str, key_file: &str) -> Result<TlsIdentity, String> {
+    let mut cert_reader = BufReader::new(File::open(cert_file).map_err(|e| format!("Unable to read {}: {}", cert_file, e))?);
+    let chain = rustls_pemfile::certs(&mut cert_reader).collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid certificate in {}: {}", cert_file, e))?;
+    let cert = match chain.first() {
+        Some(cert) => cert.to_vec(),
+        None => return Err(format!("No certificate in {}", cert_file))
+    };
+    let mut key_reader = BufReader::new(File::open(key_file).map_err(|e| format!("Unable to read {}: {}", key_file, e))?);
+    let key = match rustls_pemfile::private_key(&mut key_reader) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Err(format!("No private key in {}", key_file)),
+        Err(e) => return Err(format!("Invalid private key in {}: {}", key_file, e))
+    };
+    // not the process wide default, more than one provider can be compiled in
+    let server_config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(chain, key)
+        .map_err(|e| format!("The certificate doesn't fit the key: {}", e))?;
+    let fingerprint = fingerprint(&cert);
+    Ok(TlsIdentity { server_config, cert, fingerprint })
+}
+
+// SHA-256 of the DER certificate, lowercase hex
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// the way browsers show it, AB:CD:..
+pub fn display_fingerprint(fingerprint: &str) -> String {
+    let hex = fingerprint.to_uppercase();
+    hex.as_bytes().chunks(2).map(|pair| String::from_utf8_lossy(pair).to_string()).collect::<Vec<String>>().join(":")
+}
+
+// either way of writing it, the fp of the QR code is lowercase hex
+pub fn same_fingerprint(first: &str, second: &str) -> bool {
+    let normalize = |fingerprint: &str| fingerprint.replace(':', "").to_lowercase();
+    !first.is_empty() && normalize(first) == normalize(second)
+}
@@ -0,0 +1,269 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde::Deserialize;
+use crate::input_injector::{InputInjector, PointerEvent};
+
+// Gestures on the touch-enabled instruments (GTN750, GNS530...) come over /ws, one message a
+// pointer event:
+//
+//  TOUCH:{"type":"down","id":1,"x":0.2,"y":0.5}   a finger went down, id tells the fingers apart
+//  TOUCH:{"type":"move","id":1,"x":0.25,"y":0.5}  ...dragged
+//  TOUCH:{"type":"up","id":1,"x":0.25,"y":0.5}    ...lifted
+//  TOUCH:{"type":"cancel","id":1}                 ...the browser took the gesture, released where it was
+//  TOUCH:{"type":"wheel","x":0.2,"y":0.5,"delta":-120}   scroll, 120 a notch, negative towards the user
+//  TOUCH:{"type":"press","x":0.2,"y":0.5,"ms":800}       tap held for ms (long press), in one message
+//
+// x and y go from 0 to 1 across the frames the connection gets, however big the client shows
+// them: its subscription's crop, the shared crop of the selected pop-out without one (or without a
+// subscription). TouchArea takes them through the crop, the capture and the pop-out's dpi scaling
+// to the pixel of the pop-out that was on the picture. A pop-out has one mouse: the first finger
+// down drives it, the other fingers are ignored until it's lifted. The gesture stays on the
+// pop-out it started on. The events are injected in order on a thread of the connection, a long
+// press doesn't hold up the ws.
+
+pub const TOUCH: &str = "TOUCH";
+pub const DEFAULT_PRESS_MS: u64 = 100;
+// longer presses are cut, the connection's input thread is busy meanwhile
+pub const MAX_PRESS_MS: u64 = 5000;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum GestureKind {
+    Down,
+    Move,
+    Up,
+    Cancel,
+    Wheel,
+    Press,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct GestureEvent {
+    #[serde(rename = "type")]
+    pub kind: GestureKind,
+    #[serde(default)]
+    pub id: u32,
+    #[serde(default)]
+    pub x: f64,
+    #[serde(default)]
+    pub y: f64,
+    #[serde(default)]
+    pub delta: i32,
+    #[serde(default)]
+    pub ms: u64,
+}
+
+impl GestureEvent {
+    // None if the text isn't a touch message
+    pub fn parse(text: &str) -> Option<Result<GestureEvent, String>> {
+        let rest = text.strip_prefix(TOUCH)?.strip_prefix(':')?;
+        Some(serde_json::from_str(rest.trim()).map_err(|e| format!("Invalid touch event: {}", e)))
+    }
+}
+
+// where the picture a client touches comes from
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TouchArea {
+    // [[crop_x, crop_y], [crop_w, crop_h]] of the capture, 0 size to its right/bottom edge
+    pub crop: [[i32; 2]; 2],
+    // size of the capture the crop was cut from
+    pub capture: [i32; 2],
+    // the pop-out's client area in the app's pixels
+    pub client: [i32; 2],
+    // pop-out pixels per app pixel, the dpi scaling of the pop-out's monitor
+    pub scale: f64,
+}
+
+impl TouchArea {
+    // the part of the capture the client got, [x, y, w, h]
+    fn picture(&self) -> [i32; 4] {
+        let [[x, y], [w, h]] = self.crop;
+        let w = if w > 0 { w } else { self.capture[0] - x };
+        let h = if h > 0 { h } else { self.capture[1] - y };
+        [x, y, w.max(1), h.max(1)]
+    }
+
+    // 0..1 on the picture to the pixel of the pop-out, points off the picture go to its edge
+    pub fn to_popout(self, x: f64, y: f64) -> (i32, i32) {
+        let [crop_x, crop_y, w, h] = self.picture();
+        let pixel_x = crop_x + ((x.clamp(0.0, 1.0) * w as f64) as i32).min(w - 1);
+        let pixel_y = crop_y + ((y.clamp(0.0, 1.0) * h as f64) as i32).min(h - 1);
+        // the middle of the capture pixel, the client area can be bigger than the capture
+        let to_client = |pixel: i32, capture: i32, client: i32| -> f64 {
+            if capture > 0 && client > 0 {
+                (pixel as f64 + 0.5) * client as f64 / capture as f64
+            } else {
+                pixel as f64 + 0.5
+            }
+        };
+        ((to_client(pixel_x, self.capture[0], self.client[0]) * self.scale) as i32,
+         (to_client(pixel_y, self.capture[1], self.client[1]) * self.scale) as i32)
+    }
+
+    // pixels of the picture to 0..1, older clients send those
+    pub fn normalize(&self, x: i32, y: i32) -> (f64, f64) {
+        let [_, _, w, h] = self.picture();
+        ((x as f64 + 0.5) / w as f64, (y as f64 + 0.5) / h as f64)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputStep {
+    Pointer(PointerEvent, i32, i32),
+    Hold(Duration),
+}
+
+// what a touch event does on the pop-out
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GestureSteps {
+    pub hwnd: isize,
+    pub steps: Vec<InputStep>,
+    // the mouse is released, e.g. the pop-out can be put back behind the sim
+    pub ended: bool,
+}
+
+impl GestureSteps {
+    fn new(hwnd: isize, steps: &[InputStep], ended: bool) -> Self {
+        GestureSteps { hwnd, steps: steps.to_vec(), ended }
+    }
+}
+
+// the finger that has the mouse and where it is
+struct Drag {
+    id: u32,
+    hwnd: isize,
+    area: TouchArea,
+    x: i32,
+    y: i32,
+}
+
+#[derive(Default)]
+pub struct GestureTracker {
+    drag: Option<Drag>,
+}
+
+impl GestureTracker {
+    pub fn new() -> Self {
+        GestureTracker { drag: None }
+    }
+
+    // what to inject for the event on the pop-out, None if it's ignored
+    pub fn steps(&mut self, event: &GestureEvent, hwnd: isize, area: TouchArea) -> Option<GestureSteps> {
+        return match event.kind {
+            GestureKind::Down => {
+                if self.drag.is_some() {
+                    return None;
+                }
+                let (x, y) = area.to_popout(event.x, event.y);
+                self.drag = Some(Drag { id: event.id, hwnd, area, x, y });
+                Some(GestureSteps::new(hwnd, &[InputStep::Pointer(PointerEvent::Move, x, y),
+                    InputStep::Pointer(PointerEvent::Down, x, y)], false))
+            }
+            GestureKind::Move => {
+                let drag = self.drag.as_mut().filter(|drag| drag.id == event.id)?;
+                (drag.x, drag.y) = drag.area.to_popout(event.x, event.y);
+                Some(GestureSteps::new(drag.hwnd, &[InputStep::Pointer(PointerEvent::Move, drag.x, drag.y)], false))
+            }
+            GestureKind::Up => {
+                let drag = self.release(event.id)?;
+                let (x, y) = drag.area.to_popout(event.x, event.y);
+                Some(GestureSteps::new(drag.hwnd, &[InputStep::Pointer(PointerEvent::Up, x, y)], true))
+            }
+            GestureKind::Cancel => {
+                let drag = self.release(event.id)?;
+                Some(GestureSteps::new(drag.hwnd, &[InputStep::Pointer(PointerEvent::Up, drag.x, drag.y)], true))
+            }
+            GestureKind::Wheel => {
+                if event.delta == 0 {
+                    return None;
+                }
+                // scrolling mid drag stays on the dragged pop-out
+                let (hwnd, area) = self.drag.as_ref().map(|drag| (drag.hwnd, drag.area)).unwrap_or((hwnd, area));
+                let (x, y) = area.to_popout(event.x, event.y);
+                Some(GestureSteps::new(hwnd, &[InputStep::Pointer(PointerEvent::Wheel(event.delta), x, y)],
+                                       self.drag.is_none()))
+            }
+            GestureKind::Press => {
+                if self.drag.is_some() {
+                    return None;
+                }
+                let (x, y) = area.to_popout(event.x, event.y);
+                let ms = if event.ms == 0 { DEFAULT_PRESS_MS } else { event.ms.min(MAX_PRESS_MS) };
+                Some(GestureSteps::new(hwnd, &[InputStep::Pointer(PointerEvent::Move, x, y),
+                    InputStep::Pointer(PointerEvent::Down, x, y),
+                    InputStep::Hold(Duration::from_millis(ms)),
+                    InputStep::Pointer(PointerEvent::Up, x, y)], true))
+            }
+        };
+    }
+
+    // the finger has the mouse no more
+    fn release(&mut self, id: u32) -> Option<Drag> {
+        return match &self.drag {
+            Some(drag) if drag.id == id => self.drag.take(),
+            _ => None
+        };
+    }
+
+    // the button goes up where it is if the connection is lost mid drag
+    pub fn abort(&mut self) -> Option<GestureSteps> {
+        let drag = self.drag.take()?;
+        Some(GestureSteps::new(drag.hwnd, &[InputStep::Pointer(PointerEvent::Up, drag.x, drag.y)], true))
+    }
+}
+
+// the touches of a ws connection, injected on its own thread with the injector of the moment
+pub struct GestureInput {
+    tracker: GestureTracker,
+    jobs: mpsc::Sender<GestureSteps>,
+}
+
+impl GestureInput {
+    // ended is called on the input thread after a gesture let go of the mouse, failed with the
+    // errors of the injector
+    pub fn start(injector: Arc<Mutex<Arc<dyn InputInjector>>>,
+                 ended: impl Fn(&dyn InputInjector, isize) + Send + 'static,
+                 failed: impl Fn(String) + Send + 'static) -> Self {
+        let (jobs, rx) = mpsc::channel::<GestureSteps>();
+        thread::spawn(move || {
+            // the thread ends with the connection
+            while let Ok(job) = rx.recv() {
+                let current = Arc::clone(&injector.lock().unwrap());
+                for step in job.steps.iter().copied() {
+                    let result = match step {
+                        InputStep::Pointer(event, x, y) => current.pointer(job.hwnd, event, x, y),
+                        InputStep::Hold(duration) => {
+                            thread::sleep(duration);
+                            Ok(())
+                        }
+                    };
+                    if let Err(e) = result {
+                        failed(e);
+                        break;
+                    }
+                }
+                if job.ended {
+                    ended(current.as_ref(), job.hwnd);
+                }
+            }
+        });
+        GestureInput { tracker: GestureTracker::new(), jobs }
+    }
+
+    // the pop-out the touch is on if it's injected
+    pub fn handle(&mut self, event: &GestureEvent, hwnd: isize, area: TouchArea) -> Option<isize> {
+        let steps = self.tracker.steps(event, hwnd, area)?;
+        let hwnd = steps.hwnd;
+        let _ = self.jobs.send(steps);
+        Some(hwnd)
+    }
+}
+
+impl Drop for GestureInput {
+    fn drop(&mut self) {
+        if let Some(steps) = self.tracker.abort() {
+            let _ = self.jobs.send(steps);
+        }
+    }
+}
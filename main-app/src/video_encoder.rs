@@ -0,0 +1,65 @@
+use openh264::encoder::{Encoder, EncoderConfig};
+use openh264::formats::YUVBuffer;
+use crate::capture_backend::RawCapture;
+
+// Software H.264 encoder (openh264) for the WebRTC transport. The encoder is made for one frame
+// size, it's recreated when the pop-out is resized and when a keyframe is needed, the first
+// frame of a new encoder is always an IDR frame.
+
+pub const DEFAULT_BITRATE: u32 = 2_000_000;
+const MAX_FRAME_RATE: f32 = 30.0;
+
+pub struct VideoEncoder {
+    encoder: Option<Encoder>,
+    width: u32,
+    height: u32,
+    bitrate: u32,
+}
+
+impl VideoEncoder {
+    pub fn new(bitrate: u32) -> Self {
+        VideoEncoder { encoder: None, width: 0, height: 0, bitrate }
+    }
+
+    pub fn force_keyframe(&mut self) {
+        self.encoder = None;
+    }
+
+    // annex b bitstream of the frame
+    pub fn encode(&mut self, frame: &RawCapture) -> Result<Vec<u8>, u8> {
+        // yuv 4:2:0 needs even sizes, the last row/column is dropped
+        let width = frame.width & !1;
+        let height = frame.height & !1;
+        if width == 0 || height == 0 {
+            return Err(1);
+        }
+        if self.encoder.is_none() || self.width != width || self.height != height {
+            let config = EncoderConfig::new(width, height)
+                .set_bitrate_bps(self.bitrate)
+                .max_frame_rate(MAX_FRAME_RATE);
+            self.encoder = match Encoder::with_config(config) {
+                Ok(encoder) => Some(encoder),
+                Err(_) => return Err(2)
+            };
+            self.width = width;
+            self.height = height;
+        }
+
+        let stride = frame.width as usize * 4;
+        let mut rgb = Vec::with_capacity((width * height * 3) as usize);
+        for row in 0..height as usize {
+            let start = row * stride;
+            for px in frame.pixels[start..start + width as usize * 4].chunks_exact(4) {
+                rgb.extend_from_slice(&px[..3]);
+            }
+        }
+        let yuv = YUVBuffer::with_rgb(width as usize, height as usize, &rgb);
+        return match self.encoder.as_mut().unwrap().encode(&yuv) {
+            Ok(bitstream) => Ok(bitstream.to_vec()),
+            Err(_) => {
+                self.encoder = None;
+                Err(3)
+            }
+        };
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use actix::{Message, Recipient};
+use actix_web::web::Bytes;
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender};
+use serde::Deserialize;
+use tokio::runtime::{Handle, Runtime};
+use webrtc::api::{API, APIBuilder};
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264};
+use webrtc::interceptor::registry::Registry;
+use webrtc::media::Sample;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_local::TrackLocal;
+use crate::capture_backend::RawCapture;
+use crate::debug_logger;
+use crate::image_streamer::SubscribeRequest;
+use crate::video_encoder::{DEFAULT_BITRATE, VideoEncoder};
+
+// WebRTC transport for big pop-outs (GTN750, glass PFDs) where whole images are too heavy at
+// high frame rates, only built with the "webrtc" cargo feature. The signalling runs over /ws,
+// the browser offers to receive one video track:
+//
+//  WEBRTCOFFER:{"offer":{"type":"offer","sdp":"..."},"subscription":{"hwnd":0,...}}
+//  WEBRTCANSWER:{"type":"answer","sdp":"..."}     the reply, an error is sent as plain text
+//  WEBRTCCLOSE
+//
+// "subscription" takes the IMAGESUBSCRIBE fields, the session replaces the image subscription of
+// the connection and IMAGESUBSCRIBE/IMAGEUNSUBSCRIBE end it. The candidates are in the answer
+// (no trickle ice), the clients are on the same network. Frames are encoded to H.264 in software
+// by video_encoder, a picture loss report of the browser or the connection coming up makes the next
+// frame a keyframe.
+
+pub const OFFER: &str = "WEBRTCOFFER";
+pub const ANSWER: &str = "WEBRTCANSWER";
+pub const CLOSE: &str = "WEBRTCCLOSE";
+// how often the encoder thread looks for picture loss while no frame comes
+const FRAME_WAIT: Duration = Duration::from_millis(100);
+const FIRST_FRAME_DURATION: Duration = Duration::from_millis(33);
+
+#[derive(Deserialize)]
+pub struct OfferRequest {
+    pub offer: RTCSessionDescription,
+    #[serde(default)]
+    pub subscription: SubscribeRequest,
+}
+
+impl OfferRequest {
+    // None if the text isn't an offer
+    pub fn parse(text: &str) -> Option<Result<OfferRequest, String>> {
+        let rest = text.strip_prefix(OFFER)?.strip_prefix(':')?;
+        Some(serde_json::from_str(rest).map_err(|e| format!("Invalid WebRTC offer: {}", e)))
+    }
+}
+
+// the answer for the ws connection that sent the offer
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Negotiated {
+    pub subscription: SubscribeRequest,
+    pub result: Result<(String, VideoSink), String>,
+}
+
+// the image streamer's end of a session, dropping the last one ends the session
+#[derive(Clone)]
+pub struct VideoSink {
+    frames: Sender<Arc<RawCapture>>,
+    open: Arc<AtomicBool>,
+}
+
+impl VideoSink {
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    // fails while the encoder is still busy with the previous frame
+    pub fn try_send(&self, frame: &Arc<RawCapture>) -> Result<(), String> {
+        if !self.is_open() {
+            return Err("session closed".to_string());
+        }
+        self.frames.try_send(Arc::clone(frame)).map_err(|e| e.to_string())
+    }
+}
+
+pub struct WebRtcTransport {
+    runtime: Runtime,
+    api: Arc<API>,
+    log_str: Option<Arc<Mutex<String>>>,
+}
+
+impl WebRtcTransport {
+    pub fn new(log_str: Option<Arc<Mutex<String>>>) -> Result<Self, String> {
+        let runtime = match tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => return Err(format!("Cant start WebRTC runtime: {}", e))
+        };
+        let mut media_engine = MediaEngine::default();
+        if let Err(e) = media_engine.register_default_codecs() {
+            return Err(format!("Cant register WebRTC codecs: {}", e));
+        }
+        let registry = match register_default_interceptors(Registry::new(), &mut media_engine) {
+            Ok(registry) => registry,
+            Err(e) => return Err(format!("Cant register WebRTC interceptors: {}", e))
+        };
+        let api = APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .build();
+        Ok(WebRtcTransport { runtime, api: Arc::new(api), log_str })
+    }
+
+    // answers the offer in the background, the result is sent to reply_to
+    pub fn negotiate(&self, request: OfferRequest, reply_to: Recipient<Negotiated>) {
+        let api = Arc::clone(&self.api);
+        let handle = self.runtime.handle().clone();
+        let log_inner = debug_logger::clone_log(&self.log_str);
+        self.runtime.spawn(async move {
+            let result = answer(&api, request.offer, handle, &log_inner).await;
+            if let Err(e) = &result {
+                debug_logger::log(e, &log_inner);
+            }
+            reply_to.do_send(Negotiated { subscription: request.subscription, result });
+        });
+    }
+}
+
+async fn answer(api: &API, offer: RTCSessionDescription, handle: Handle,
+                log_str: &Option<Arc<Mutex<String>>>) -> Result<(String, VideoSink), String> {
+    let failed = |e: webrtc::Error| format!("WebRTC negotiation failed: {}", e);
+    let connection = Arc::new(api.new_peer_connection(RTCConfiguration::default()).await.map_err(failed)?);
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability { mime_type: MIME_TYPE_H264.to_owned(), ..Default::default() },
+        "video".to_owned(),
+        "reachfms".to_owned(),
+    ));
+    let sender = connection.add_track(Arc::clone(&track) as Arc<dyn TrackLocal + Send + Sync>).await
+        .map_err(failed)?;
+
+    let open = Arc::new(AtomicBool::new(true));
+    let keyframe = Arc::new(AtomicBool::new(false));
+    let keyframe_inner = Arc::clone(&keyframe);
+    // rtcp has to be read for the interceptors to work, picture losses are what we need from it
+    tokio::spawn(async move {
+        while let Ok((packets, _)) = sender.read_rtcp().await {
+            if packets.iter().any(|packet| packet.as_any().downcast_ref::<PictureLossIndication>().is_some()) {
+                keyframe_inner.store(true, Ordering::SeqCst);
+            }
+        }
+    });
+    let open_inner = Arc::clone(&open);
+    let connected_keyframe = Arc::clone(&keyframe);
+    connection.on_peer_connection_state_change(Box::new(move |state| {
+        if state == RTCPeerConnectionState::Failed || state == RTCPeerConnectionState::Closed {
+            open_inner.store(false, Ordering::SeqCst);
+        }
+        // the frames sent before are dropped, a pop-out that doesn't change wouldn't send another
+        if state == RTCPeerConnectionState::Connected {
+            connected_keyframe.store(true, Ordering::SeqCst);
+        }
+        Box::pin(async {})
+    }));
+
+    connection.set_remote_description(offer).await.map_err(failed)?;
+    let local = connection.create_answer(None).await.map_err(failed)?;
+    let mut gathered = connection.gathering_complete_promise().await;
+    connection.set_local_description(local).await.map_err(failed)?;
+    let _ = gathered.recv().await;
+    let local = match connection.local_description().await {
+        Some(local) => local,
+        None => return Err("WebRTC negotiation failed: no local description".to_string())
+    };
+    let answer = serde_json::to_string(&local).map_err(|e| e.to_string())?;
+
+    let (frames, receiver) = bounded(1);
+    let log_inner = debug_logger::clone_log(log_str);
+    let open_inner = Arc::clone(&open);
+    thread::spawn(move || encode_frames(receiver, track, connection, keyframe, open_inner, handle, log_inner));
+    debug_logger::log("WebRTC session negotiated", log_str);
+    Ok((answer, VideoSink { frames, open }))
+}
+
+fn encode_frames(frames: Receiver<Arc<RawCapture>>, track: Arc<TrackLocalStaticSample>,
+                 connection: Arc<RTCPeerConnection>, keyframe: Arc<AtomicBool>, open: Arc<AtomicBool>,
+                 handle: Handle, log_str: Option<Arc<Mutex<String>>>) {
+    let mut encoder = VideoEncoder::new(DEFAULT_BITRATE);
+    let mut last_frame: Option<Arc<RawCapture>> = None;
+    let mut last_sample: Option<Instant> = None;
+    while open.load(Ordering::SeqCst) {
+        let frame = match frames.recv_timeout(FRAME_WAIT) {
+            Ok(frame) => frame,
+            // lost while nothing changed, the last frame is sent again as a keyframe
+            Err(RecvTimeoutError::Timeout) if keyframe.load(Ordering::SeqCst) && last_frame.is_some() => {
+                Arc::clone(last_frame.as_ref().unwrap())
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            // the subscription is gone
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+        if keyframe.swap(false, Ordering::SeqCst) {
+            encoder.force_keyframe();
+        }
+        let data = match encoder.encode(&frame) {
+            Ok(data) => data,
+            Err(e) => {
+                debug_logger::log(&*format!("Cant encode video frame: {}", e), &log_str);
+                continue;
+            }
+        };
+        let duration = last_sample.map(|sent| sent.elapsed()).unwrap_or(FIRST_FRAME_DURATION);
+        last_sample = Some(Instant::now());
+        let sample = Sample { data: Bytes::from(data), duration, ..Default::default() };
+        if let Err(e) = handle.block_on(track.write_sample(&sample)) {
+            debug_logger::log(&*format!("Cant send video frame: {}", e), &log_str);
+            break;
+        }
+        last_frame = Some(frame);
+    }
+    open.store(false, Ordering::SeqCst);
+    let _ = handle.block_on(connection.close());
+    debug_logger::log("WebRTC session closed", &log_str);
+}
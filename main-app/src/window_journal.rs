@@ -0,0 +1,124 @@
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use serde::{Deserialize, Serialize};
+use crate::capture_backend::{backend, WindowRect};
+use crate::config_handler::get_window_journal_file;
+use crate::image_process::ImageProcess;
+
+// Every pop-out the app hides or moves is written to data/window_journal.json with the rect it
+// had before, and the journal is emptied when the pop-outs are restored. Entries left at start
+// mean ReachFMS was killed with pop-outs hidden (MSFS keeps them below the screen once it saves
+// their position): the GUI offers to put them back, so does --restore-windows without it.
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    pub hwnd: isize,
+    pub title: String,
+    // "hide" or "move", the first one done to the window
+    pub action: String,
+    pub original: WindowRect,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct JournalFile {
+    #[serde(default)]
+    entries: Vec<JournalEntry>,
+}
+
+pub struct WindowJournal {
+    file: Mutex<JournalFile>,
+}
+
+static JOURNAL: OnceLock<WindowJournal> = OnceLock::new();
+
+pub fn window_journal() -> &'static WindowJournal {
+    JOURNAL.get_or_init(WindowJournal::load)
+}
+
+impl WindowJournal {
+    fn load() -> Self {
+        let file = fs::read_to_string(get_window_journal_file()).ok()
+            .and_then(|string_data| serde_json::from_str(&string_data).ok())
+            .unwrap_or_default();
+        WindowJournal { file: Mutex::new(file) }
+    }
+
+    // before the app hides or moves the window, only the first rect is kept. A window that's
+    // already hidden has nothing worth going back to
+    pub fn record(&self, hwnd: isize, action: &str) {
+        let mut file = self.file.lock().unwrap();
+        if file.entries.iter().any(|entry| entry.hwnd == hwnd) {
+            return;
+        }
+        if backend().is_hidden(hwnd) {
+            return;
+        }
+        let original = match backend().window_rect(hwnd) {
+            Ok(rect) => rect,
+            Err(_) => return
+        };
+        let title = ImageProcess::find_popup_windows().unwrap_or_default().into_iter()
+            .find(|popout| popout.hwnd == hwnd)
+            .map(|popout| popout.title).unwrap_or_default();
+        file.entries.push(JournalEntry { hwnd, title, action: action.to_string(), original });
+        save(&file);
+    }
+
+    // pop-outs the last run didn't restore
+    pub fn pending(&self) -> usize {
+        self.file.lock().unwrap().entries.len()
+    }
+
+    // the pop-outs are back where they were, the entries of the others are kept (a pop-out MSFS
+    // closed while it was hidden is restored by title when it's open again)
+    pub fn forget(&self, hwnds: &[isize]) {
+        let mut file = self.file.lock().unwrap();
+        let count = file.entries.len();
+        file.entries.retain(|entry| !hwnds.contains(&entry.hwnd));
+        if file.entries.len() != count {
+            save(&file);
+        }
+    }
+
+    // puts the windows of the journal back, by hwnd if the sim kept running, by title after a
+    // restart. Entries without an open pop-out are kept for the next try
+    pub fn restore(&self) -> Result<usize, String> {
+        let mut file = self.file.lock().unwrap();
+        if file.entries.is_empty() {
+            return Ok(0);
+        }
+        let mut popouts = match ImageProcess::find_popup_windows() {
+            Ok(popouts) => popouts,
+            Err(_) => return Err("Can't find MSFS window: start the sim and open the pop-outs first".to_string())
+        };
+        let mut restored = 0;
+        file.entries.retain(|entry| {
+            let found = popouts.iter().position(|popout| popout.hwnd == entry.hwnd)
+                .or_else(|| popouts.iter().position(|popout| popout.title == entry.title));
+            let popout = match found {
+                None => return true,
+                Some(i) => popouts.remove(i)
+            };
+            let rect = entry.original;
+            let _ = backend().show_window(popout.hwnd);
+            if backend().move_window(popout.hwnd, rect.left, rect.top,
+                                     rect.right - rect.left, rect.bottom - rect.top).is_err() {
+                return true;
+            }
+            restored += 1;
+            false
+        });
+        save(&file);
+        Ok(restored)
+    }
+}
+
+fn save(file: &JournalFile) {
+    if file.entries.is_empty() {
+        let _ = fs::remove_file(get_window_journal_file());
+        return;
+    }
+    if let Ok(json_string) = serde_json::to_string_pretty(file) {
+        let _ = fs::write(get_window_journal_file(), json_string);
+    }
+}
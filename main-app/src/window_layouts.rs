@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use serde::{Deserialize, Serialize};
+use crate::capture_backend::{backend, WindowRect};
+use crate::config_handler::get_window_layouts_file;
+use crate::image_process::{ImageProcess, PopOutWindow, POPOUT_HEIGHT, POPOUT_WIDTH};
+
+// Where the pop-outs were before the app hid or moved them, and the layouts the user saved, kept
+// in data/window_layouts.json by aircraft and pop-out title. A pop-out's rect is taken the first
+// time a scan sees its hwnd (every opened pop-out gets a new one), unless it's already hidden (the
+// app was closed without restoring): the last rect stored for the title is used for those.
+// Pop-outs with the same title (the untitled ones) are told apart by number, "UNKNOWN#2".
+//
+// {
+//   "original": {"FNX320": {"UNKNOWN": {"left": 1930, "top": 40, "right": 2630, "bottom": 740}}},
+//   "layouts": {"FNX320": {"second screen": {"UNKNOWN": {...}, "UNKNOWN#2": {...}}}}
+// }
+
+#[derive(Serialize, Deserialize, Default)]
+struct LayoutFile {
+    // aircraft -> pop-out -> rect before the app touched it
+    #[serde(default)]
+    original: HashMap<String, HashMap<String, WindowRect>>,
+    // aircraft -> layout name -> pop-out -> rect
+    #[serde(default)]
+    layouts: HashMap<String, HashMap<String, HashMap<String, WindowRect>>>,
+}
+
+pub struct WindowLayouts {
+    file: Mutex<LayoutFile>,
+    // rects of the pop-outs seen in this session by hwnd
+    first_seen: Mutex<HashMap<isize, WindowRect>>,
+    aircraft: Mutex<String>,
+}
+
+static LAYOUTS: OnceLock<WindowLayouts> = OnceLock::new();
+
+// the GUI restores the pop-outs on close as well, so there's one for the whole app
+pub fn window_layouts() -> &'static WindowLayouts {
+    LAYOUTS.get_or_init(WindowLayouts::load)
+}
+
+impl WindowLayouts {
+    fn load() -> Self {
+        // a broken file is started over, it only has window positions
+        let file = fs::read_to_string(get_window_layouts_file()).ok()
+            .and_then(|string_data| serde_json::from_str(&string_data).ok())
+            .unwrap_or_default();
+        WindowLayouts {
+            file: Mutex::new(file),
+            first_seen: Mutex::new(HashMap::new()),
+            aircraft: Mutex::new("".to_string()),
+        }
+    }
+
+    // the aircraft the pop-outs belong to, the layouts are kept per aircraft
+    pub fn set_aircraft(&self, aircraft: &str) {
+        *self.aircraft.lock().unwrap() = aircraft.to_string();
+    }
+
+    // takes the rects of the pop-outs not seen before, before the scan hides any
+    pub fn snapshot(&self, popouts: &[PopOutWindow]) {
+        let aircraft = self.aircraft.lock().unwrap().clone();
+        let mut first_seen = self.first_seen.lock().unwrap();
+        let mut file = self.file.lock().unwrap();
+        let mut changed = false;
+        for (popout, key) in popouts.iter().zip(popout_keys(popouts)) {
+            if first_seen.contains_key(&popout.hwnd) {
+                continue;
+            }
+            if backend().is_hidden(popout.hwnd) {
+                continue;
+            }
+            let rect = match backend().window_rect(popout.hwnd) {
+                Err(_) => continue,
+                Ok(rect) => rect
+            };
+            first_seen.insert(popout.hwnd, rect);
+            if !aircraft.is_empty() {
+                file.original.entry(aircraft.clone()).or_default().insert(key, rect);
+                changed = true;
+            }
+        }
+        if changed {
+            save(&file);
+        }
+    }
+
+    // puts the pop-outs back where they were, the ones never seen get the pop-out size at 0,0
+    pub fn restore(&self, popouts: &[PopOutWindow]) {
+        let aircraft = self.aircraft.lock().unwrap().clone();
+        let first_seen = self.first_seen.lock().unwrap();
+        let file = self.file.lock().unwrap();
+        let original = file.original.get(&aircraft);
+        for (popout, key) in popouts.iter().zip(popout_keys(popouts)) {
+            let rect = first_seen.get(&popout.hwnd).or_else(|| original.and_then(|rects| rects.get(&key)));
+            match rect {
+                Some(rect) => move_to(popout.hwnd, rect),
+                None => ImageProcess::move_window(popout.hwnd, 0, 0, POPOUT_WIDTH, POPOUT_HEIGHT)
+            }
+        }
+    }
+
+    // the current rects of the pop-outs under the name, hidden ones with the rect they're restored to
+    pub fn save_layout(&self, name: &str, popouts: &[PopOutWindow]) -> Result<usize, String> {
+        let aircraft = self.aircraft.lock().unwrap().clone();
+        if aircraft.is_empty() {
+            return Err("No aircraft".to_string());
+        }
+        let first_seen = self.first_seen.lock().unwrap();
+        let mut file = self.file.lock().unwrap();
+        let mut layout: HashMap<String, WindowRect> = HashMap::new();
+        for (popout, key) in popouts.iter().zip(popout_keys(popouts)) {
+            let rect = match backend().window_rect(popout.hwnd) {
+                Ok(rect) if !backend().is_hidden(popout.hwnd) => Some(rect),
+                _ => first_seen.get(&popout.hwnd).copied()
+            };
+            if let Some(rect) = rect {
+                layout.insert(key, rect);
+            }
+        }
+        if layout.is_empty() {
+            return Err("No pop-outs to save".to_string());
+        }
+        let count = layout.len();
+        file.layouts.entry(aircraft).or_default().insert(name.to_string(), layout);
+        save(&file);
+        Ok(count)
+    }
+
+    // moves the pop-outs that are in the layout, hidden ones stay hidden and go there on restore
+    pub fn apply_layout(&self, name: &str, popouts: &[PopOutWindow]) -> Result<usize, String> {
+        let aircraft = self.aircraft.lock().unwrap().clone();
+        let mut first_seen = self.first_seen.lock().unwrap();
+        let file = self.file.lock().unwrap();
+        let layout = match file.layouts.get(&aircraft).and_then(|layouts| layouts.get(name)) {
+            None => return Err(format!("No layout named {}", name)),
+            Some(layout) => layout
+        };
+        let mut count = 0;
+        for (popout, key) in popouts.iter().zip(popout_keys(popouts)) {
+            let rect = match layout.get(&key) {
+                None => continue,
+                Some(rect) => rect
+            };
+            first_seen.insert(popout.hwnd, *rect);
+            if !backend().is_hidden(popout.hwnd) {
+                move_to(popout.hwnd, rect);
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn delete_layout(&self, name: &str) -> bool {
+        let aircraft = self.aircraft.lock().unwrap().clone();
+        let mut file = self.file.lock().unwrap();
+        let removed = file.layouts.get_mut(&aircraft)
+            .map(|layouts| layouts.remove(name).is_some()).unwrap_or(false);
+        if removed {
+            save(&file);
+        }
+        removed
+    }
+
+    pub fn layout_names(&self) -> Vec<String> {
+        let aircraft = self.aircraft.lock().unwrap().clone();
+        let mut names: Vec<String> = self.file.lock().unwrap().layouts.get(&aircraft)
+            .map(|layouts| layouts.keys().cloned().collect()).unwrap_or_default();
+        names.sort();
+        names
+    }
+}
+
+// the title, numbered from the second pop-out with the same title on. The window list comes in
+// z-order, so they're counted in hwnd order (the order they were opened in)
+fn popout_keys(popouts: &[PopOutWindow]) -> Vec<String> {
+    let mut by_hwnd: Vec<usize> = (0..popouts.len()).collect();
+    by_hwnd.sort_by_key(|i| popouts[*i].hwnd);
+    let mut keys = vec!["".to_string(); popouts.len()];
+    let mut counts: HashMap<&str, u32> = HashMap::new();
+    for i in by_hwnd {
+        let title = popouts[i].title.as_str();
+        let count = counts.entry(title).or_insert(0);
+        *count += 1;
+        keys[i] = if *count == 1 { title.to_string() } else { format!("{}#{}", title, count) };
+    }
+    keys
+}
+
+fn move_to(hwnd: isize, rect: &WindowRect) {
+    ImageProcess::move_window(hwnd, rect.top, rect.left, rect.right - rect.left, rect.bottom - rect.top);
+}
+
+fn save(file: &LayoutFile) {
+    if let Ok(json_string) = serde_json::to_string_pretty(file) {
+        let _ = fs::write(get_window_layouts_file(), json_string);
+    }
+}
@@ -0,0 +1,163 @@
+// Many queries in flight on one bridge: every caller gets the reply to its own query, however the
+// bridge orders its answers, and no button press gets lost in between, not even when the
+// connection of the bridge closes with commands it took.
+
+#[path = "../src/bridge_protocol.rs"]
+#[allow(dead_code)]
+mod bridge_protocol;
+#[path = "../src/comm_sender.rs"]
+#[allow(dead_code)]
+mod comm_sender;
+
+// comm_sender logs through the app's logger, which writes next to the exe
+mod debug_logger {
+    use std::sync::{Arc, Mutex};
+
+    pub fn log(new_log: &str, _log_str: &Option<Arc<Mutex<String>>>) {
+        eprintln!("{}", new_log);
+    }
+}
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel::bounded;
+use bridge_protocol::{BridgeCommand, BridgeReply, BridgeRequest, BridgeResponse};
+use comm_sender::BridgeDispatcher;
+
+const QUERIES: usize = 40;
+const PRESSES: usize = 40;
+
+// answers the queries in batches, the last one first, and keeps the button presses
+fn fake_bridge(dispatcher: Arc<BridgeDispatcher>, rx: crossbeam_channel::Receiver<BridgeRequest>,
+               pressed: Arc<Mutex<Vec<String>>>) {
+    thread::spawn(move || {
+        let mut waiting: Vec<BridgeRequest> = vec![];
+        loop {
+            match rx.recv_timeout(Duration::from_millis(20)) {
+                Ok(BridgeRequest { command: BridgeCommand::ButtonPress { lvar }, .. }) => pressed.lock().unwrap().push(lvar),
+                Ok(request) => waiting.push(request),
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break
+            }
+            if waiting.len() >= 7 || (!waiting.is_empty() && rx.is_empty()) {
+                for request in waiting.drain(..).rev() {
+                    let reply = match request.command {
+                        BridgeCommand::GetVar { name } => BridgeReply::Var { value: format!("value of {}", name) },
+                        other => panic!("unexpected command {:?}", other)
+                    };
+                    dispatcher.dispatch(BridgeResponse { id: request.id, reply });
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn concurrent_queries_get_their_own_replies() {
+    let (tx, rx) = bounded::<BridgeRequest>(0);
+    let dispatcher = Arc::new(BridgeDispatcher::new(tx, None));
+    dispatcher.set_bridge_version(bridge_protocol::PROTOCOL_VERSION);
+    let pressed = Arc::new(Mutex::new(vec![]));
+    fake_bridge(Arc::clone(&dispatcher), rx, Arc::clone(&pressed));
+
+    let started = Instant::now();
+    let mut queries = vec![];
+    for i in 0..QUERIES {
+        let dispatcher = Arc::clone(&dispatcher);
+        // what /get_simvar does
+        queries.push(thread::spawn(move || (i, comm_sender::get_var(&format!("L:VAR_{}", i), &dispatcher))));
+    }
+    let mut presses = vec![];
+    for i in 0..PRESSES {
+        let dispatcher = Arc::clone(&dispatcher);
+        // what /mcdu_btn_press does
+        presses.push(thread::spawn(move || dispatcher.send(BridgeCommand::for_button(&format!("L:BTN_{}", i)))));
+    }
+    for query in queries {
+        let (i, value) = query.join().unwrap();
+        assert_eq!(value, format!("value of L:VAR_{}", i));
+    }
+    for press in presses {
+        assert!(press.join().unwrap());
+    }
+    // nothing waited for a timeout
+    assert!(started.elapsed() < Duration::from_secs(4), "took {:?}", started.elapsed());
+    let mut pressed = pressed.lock().unwrap().clone();
+    pressed.sort();
+    let mut expected: Vec<String> = (0..PRESSES).map(|i| format!("L:BTN_{}", i)).collect();
+    expected.sort();
+    assert_eq!(pressed, expected);
+}
+
+#[test]
+fn replies_nobody_waits_for_are_dropped() {
+    let (tx, rx) = bounded::<BridgeRequest>(0);
+    let dispatcher = Arc::new(BridgeDispatcher::new(tx, None));
+    // a late reply to a query that timed out doesn't go to the next one
+    dispatcher.dispatch(BridgeResponse { id: Some(999), reply: BridgeReply::Var { value: "stale".to_string() } });
+    let pressed = Arc::new(Mutex::new(vec![]));
+    fake_bridge(Arc::clone(&dispatcher), rx, pressed);
+    assert_eq!(comm_sender::get_var("L:X", &dispatcher), "value of L:X");
+}
+
+#[test]
+fn commands_of_a_gone_bridge_go_to_the_next_one() {
+    let (tx, rx) = bounded::<BridgeRequest>(0);
+    let dispatcher = Arc::new(BridgeDispatcher::new(tx, None));
+    let query = {
+        let dispatcher = Arc::clone(&dispatcher);
+        thread::spawn(move || comm_sender::get_var("L:X", &dispatcher))
+    };
+    let press = {
+        let dispatcher = Arc::clone(&dispatcher);
+        thread::spawn(move || dispatcher.send(BridgeCommand::for_button("L:BTN")))
+    };
+    // taken by the connection of a bridge that closed before sending them
+    let taken = vec![rx.recv().unwrap(), rx.recv().unwrap()];
+    let pressed = Arc::new(Mutex::new(vec![]));
+    fake_bridge(Arc::clone(&dispatcher), rx, Arc::clone(&pressed));
+    for request in taken {
+        dispatcher.requeue(request);
+    }
+    assert_eq!(query.join().unwrap(), "value of L:X");
+    assert!(press.join().unwrap());
+    let started = Instant::now();
+    while pressed.lock().unwrap().is_empty() && started.elapsed() < Duration::from_secs(2) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*pressed.lock().unwrap(), vec!["L:BTN".to_string()]);
+}
+
+#[test]
+fn a_query_no_bridge_takes_again_fails_at_once() {
+    let (tx, rx) = bounded::<BridgeRequest>(0);
+    let dispatcher = Arc::new(BridgeDispatcher::new(tx, None));
+    let started = Instant::now();
+    let query = {
+        let dispatcher = Arc::clone(&dispatcher);
+        thread::spawn(move || comm_sender::get_var("L:X", &dispatcher))
+    };
+    dispatcher.requeue(rx.recv().unwrap());
+    assert_eq!(query.join().unwrap(), "ERROR");
+    // not the 5 s a query waits for its reply
+    assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+}
+
+#[test]
+fn a_query_waits_no_longer_than_its_timeout() {
+    let (tx, rx) = bounded::<BridgeRequest>(0);
+    let dispatcher = Arc::new(BridgeDispatcher::new(tx, None));
+    // takes the query late and never answers it
+    let bridge = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(400));
+        let request = rx.recv().unwrap();
+        thread::sleep(Duration::from_millis(1000));
+        request
+    });
+    let started = Instant::now();
+    assert!(dispatcher.request(BridgeCommand::Status, Duration::from_millis(500)).is_none());
+    // not the time to send it plus the timeout again for the reply
+    assert!(started.elapsed() < Duration::from_millis(800), "took {:?}", started.elapsed());
+    bridge.join().unwrap();
+}
@@ -0,0 +1,110 @@
+// Pop-out discovery, cropping and capture on the synthetic backend: every png in
+// tests/fixtures/popouts is a pop-out of the fake MSFS window, titled by its file name.
+
+#[path = "../src/capture_backend.rs"]
+#[allow(dead_code)]
+mod capture_backend;
+#[path = "../src/config_handler.rs"]
+#[allow(dead_code)]
+mod config_handler;
+#[path = "../src/debug_logger.rs"]
+#[allow(dead_code)]
+mod debug_logger;
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+#[path = "../src/image_process.rs"]
+#[allow(dead_code)]
+mod image_process;
+#[path = "../src/input_injector.rs"]
+#[allow(dead_code)]
+mod input_injector;
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+#[path = "../src/window_journal.rs"]
+#[allow(dead_code)]
+mod window_journal;
+#[path = "../src/window_layouts.rs"]
+#[allow(dead_code)]
+mod window_layouts;
+
+use std::sync::Once;
+use image_process::{ImageProcess, PopOutWindow};
+
+// WASMINSTRUMENT.png: 800x600, red | 600 green | blue
+// PFD.png: 300x400, yellow / 300 blue / magenta
+const GREEN: [u8; 3] = [0, 255, 0];
+const BLUE: [u8; 3] = [0, 0, 255];
+
+fn popouts() -> Vec<PopOutWindow> {
+    static FIXTURES: Once = Once::new();
+    // the backend is picked once, on first use
+    FIXTURES.call_once(|| std::env::set_var(capture_backend::FIXTURES_ENV,
+                                            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/popouts")));
+    ImageProcess::find_popup_windows().unwrap()
+}
+
+fn hwnd_of(title: &str) -> isize {
+    popouts().iter().find(|popout| popout.title == title).map(|popout| popout.hwnd).unwrap()
+}
+
+fn decode(capture: &image_process::WindowCapture) -> image::RgbImage {
+    image::load_from_memory(&capture.buf).unwrap().to_rgb8()
+}
+
+#[test]
+fn pop_outs_of_the_sim_are_found() {
+    let mut titles: Vec<String> = popouts().into_iter().map(|popout| popout.title).collect();
+    titles.sort();
+    // the untitled pop-out goes by UNKNOWN, the sim window itself isn't one
+    assert_eq!(titles, vec!["PFD".to_string(), "UNKNOWN".to_string()]);
+}
+
+#[test]
+fn crop_cuts_the_square_from_the_middle() {
+    assert_eq!(ImageProcess::find_crop_for_instruments(800, 600), [[100, 0], [600, 600]]);
+    assert_eq!(ImageProcess::find_crop_for_instruments(300, 400), [[0, 50], [300, 300]]);
+    assert_eq!(ImageProcess::find_crop_for_instruments(700, 700), [[0, 0], [700, 700]]);
+}
+
+#[test]
+fn capture_gives_the_cropped_instrument() {
+    let unknown = hwnd_of("UNKNOWN");
+    let capture = ImageProcess::capture_instrument(unknown, ImageProcess::find_crop_for_instruments(800, 600)).unwrap();
+    assert_eq!((capture.width, capture.height), (600, 600));
+    let picture = decode(&capture);
+    for (x, y) in [(0, 0), (599, 0), (0, 599), (599, 599), (300, 300)] {
+        assert_eq!(picture.get_pixel(x, y).0, GREEN, "{} {}", x, y);
+    }
+
+    let pfd = hwnd_of("PFD");
+    let capture = ImageProcess::capture_instrument(pfd, ImageProcess::find_crop_for_instruments(300, 400)).unwrap();
+    let picture = decode(&capture);
+    assert_eq!((picture.width(), picture.height()), (300, 300));
+    assert_eq!(picture.get_pixel(0, 0).0, BLUE);
+    assert_eq!(picture.get_pixel(299, 299).0, BLUE);
+}
+
+#[test]
+fn capture_without_crop_is_the_whole_window() {
+    let capture = ImageProcess::capture_instrument(hwnd_of("UNKNOWN"), [[0, 0], [0, 0]]).unwrap();
+    assert_eq!((capture.width, capture.height), (800, 600));
+    assert_eq!(decode(&capture).get_pixel(799, 0).0, BLUE);
+    // a crop off the window and a window that isn't there
+    assert!(ImageProcess::capture_instrument(hwnd_of("PFD"), [[0, 0], [301, 300]]).is_err());
+    assert!(ImageProcess::capture_instrument(424242, [[0, 0], [0, 0]]).is_err());
+}
+
+#[test]
+fn hide_strategies_of_the_settings() {
+    use capture_backend::HideStrategy;
+    assert_eq!(HideStrategy::from_name("transparent"), Some(HideStrategy::Transparent));
+    assert_eq!(HideStrategy::from_name("cloaked"), None);
+    // a config from when cloaked was offered
+    assert_eq!(serde_json::from_str::<HideStrategy>("\"cloaked\"").unwrap(), HideStrategy::OffScreen);
+    assert_eq!(serde_json::to_string(&HideStrategy::OffScreen).unwrap(), "\"off_screen\"");
+}
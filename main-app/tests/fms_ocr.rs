@@ -0,0 +1,114 @@
+// FMS page OCR against stored CDU screenshots (tests/fixtures/fms). cdu_font.json was made from
+// training.png with: fms_font_tool learn cdu_font.json training.png training.txt --small 2,3
+// The INIT page screenshots are at other scales than the training one, init.txt and
+// init_colors.txt (first letter of the colour) are what they show.
+
+#[path = "../src/fms_ocr.rs"]
+#[allow(dead_code)]
+mod fms_ocr;
+
+use std::fs;
+use fms_ocr::{FmsFont, FmsPage, UNKNOWN_CHAR};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fms/");
+const SMALL_ROWS: [usize; 6] = [1, 3, 5, 7, 9, 11];
+
+fn screenshot(name: &str) -> (Vec<u8>, u32, u32) {
+    let rgba = image::open(format!("{}{}", FIXTURES, name)).unwrap().to_rgba8();
+    let (width, height) = rgba.dimensions();
+    (rgba.into_raw(), width, height)
+}
+
+fn fixture_lines(name: &str) -> Vec<String> {
+    let mut lines: Vec<String> = fs::read_to_string(format!("{}{}", FIXTURES, name)).unwrap()
+        .lines().map(|line| format!("{:<24}", line)).collect();
+    lines.resize(14, " ".repeat(24));
+    lines
+}
+
+fn read(font: &FmsFont, name: &str) -> FmsPage {
+    let (pixels, width, height) = screenshot(name);
+    fms_ocr::read_page(font, &pixels, width, height)
+}
+
+fn stored_font() -> FmsFont {
+    FmsFont::load(&format!("{}cdu_font.json", FIXTURES)).unwrap()
+}
+
+fn assert_init_page(page: &FmsPage) {
+    assert_eq!(page.lines, fixture_lines("init.txt"));
+    assert_eq!(page.unknown, 0);
+    for (row, colors) in fixture_lines("init_colors.txt").iter().enumerate() {
+        for (column, color) in colors.chars().enumerate() {
+            let cell = &page.cells[row][column];
+            match color {
+                ' ' => assert_eq!(cell.color, "", "cell {} {}", row, column),
+                _ => assert!(cell.color.starts_with(color), "cell {} {} is {}", row, column, cell.color)
+            }
+            if cell.char != ' ' {
+                assert_eq!(cell.small, SMALL_ROWS.contains(&row), "cell {} {}", row, column);
+            }
+        }
+    }
+}
+
+#[test]
+fn reads_init_page() {
+    assert_init_page(&read(&stored_font(), "init.png"));
+}
+
+#[test]
+fn reads_init_page_at_larger_scale() {
+    assert_init_page(&read(&stored_font(), "init_large.png"));
+}
+
+#[test]
+fn learned_font_matches_stored_font() {
+    let (pixels, width, height) = screenshot("training.png");
+    let mut font = FmsFont::new();
+    let learned = font.learn(&pixels, width, height, &fixture_lines("training.txt"), &[2, 3]);
+    assert_eq!(learned, 96);
+    let stored = stored_font();
+    assert_eq!(font.glyphs.len(), stored.glyphs.len());
+    for (glyph, stored) in font.glyphs.iter().zip(&stored.glyphs) {
+        assert_eq!((glyph.char, glyph.small, &glyph.rows), (stored.char, stored.small, &stored.rows));
+    }
+    assert_init_page(&read(&FmsFont::from_json(&font.to_json()).unwrap(), "init.png"));
+}
+
+#[test]
+fn reads_training_page_back() {
+    let page = read(&stored_font(), "training.png");
+    assert_eq!(page.lines, fixture_lines("training.txt"));
+    assert!(page.cells[2].iter().chain(&page.cells[3]).all(|cell| cell.small));
+    assert!(page.cells[0].iter().chain(&page.cells[1]).all(|cell| !cell.small && cell.color == "white"));
+}
+
+#[test]
+fn unknown_glyphs_are_marked() {
+    let (pixels, width, height) = screenshot("init.png");
+    // only the large letters, the small ones and the boxes are missing
+    let mut font = FmsFont::new();
+    let (training, training_width, training_height) = screenshot("training.png");
+    font.learn(&training, training_width, training_height, &fixture_lines("training.txt")[..1], &[]);
+    let page = fms_ocr::read_page(&font, &pixels, width, height);
+    assert!(page.unknown > 0);
+    assert_eq!(page.cells[2][0].char, UNKNOWN_CHAR);
+    assert_eq!(page.cells[2][0].color, "amber");
+    assert_eq!(&page.lines[0], "          INIT          ");
+}
+
+#[test]
+fn empty_screen_reads_as_spaces() {
+    let pixels = vec![0u8; 480 * 476 * 4];
+    let page = fms_ocr::read_page(&stored_font(), &pixels, 480, 476);
+    assert_eq!(page.lines, vec![" ".repeat(24); 14]);
+    assert_eq!(page.unknown, 0);
+}
+
+#[test]
+fn rejects_invalid_fonts() {
+    assert!(FmsFont::from_json("{\"glyphs\": [], \"columns\": 0}").is_err());
+    assert!(FmsFont::from_json("not json").is_err());
+    assert!(FmsFont::load(&format!("{}missing.json", FIXTURES)).is_err());
+}
@@ -0,0 +1,109 @@
+// Touch input without the real cursor. The recording backend runs everywhere, the X11 one needs
+// the x11 feature and a display, e.g.: xvfb-run cargo test --features x11 --test input_injector
+
+// the X11 backend finds the X window of a pop-out by its title, from the synthetic backend here
+#[path = "../src/capture_backend.rs"]
+#[allow(dead_code)]
+mod capture_backend;
+#[path = "../src/config_handler.rs"]
+#[allow(dead_code)]
+mod config_handler;
+#[path = "../src/debug_logger.rs"]
+#[allow(dead_code)]
+mod debug_logger;
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+#[path = "../src/input_injector.rs"]
+#[allow(dead_code)]
+mod input_injector;
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+
+use std::time::Duration;
+use input_injector::{click, InputInjector, PointerEvent, RecordingInjector};
+
+#[test]
+fn click_is_move_down_up_at_the_point() {
+    let injector = RecordingInjector::new();
+    click(&injector, 42, 120, 300, Duration::from_millis(30)).unwrap();
+    let events = injector.events();
+    let kinds: Vec<PointerEvent> = events.iter().map(|event| event.event).collect();
+    assert_eq!(kinds, vec![PointerEvent::Move, PointerEvent::Down, PointerEvent::Up]);
+    assert!(events.iter().all(|event| event.hwnd == 42 && event.x == 120 && event.y == 300));
+    assert!(events[2].at - events[1].at >= Duration::from_millis(30));
+}
+
+#[test]
+fn drag_keeps_the_events_in_order() {
+    let injector = RecordingInjector::new();
+    injector.pointer(7, PointerEvent::Down, 10, 10).unwrap();
+    injector.pointer(7, PointerEvent::Move, 20, 15).unwrap();
+    injector.pointer(7, PointerEvent::Up, 30, 20).unwrap();
+    let points: Vec<(i32, i32)> = injector.events().iter().map(|event| (event.x, event.y)).collect();
+    assert_eq!(points, vec![(10, 10), (20, 15), (30, 20)]);
+}
+
+#[cfg(feature = "x11")]
+#[test]
+fn x11_click_reaches_the_window_of_the_pop_out() {
+    use std::time::Instant;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, CreateWindowAux, EventMask, PropMode, Window,
+                                  WindowClass};
+    use x11rb::protocol::Event;
+    use x11rb::wrapper::ConnectionExt as WrapperConnectionExt;
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+    use capture_backend::{backend, SYNTHETIC_SIM_TITLE};
+    use input_injector::x11::X11Injector;
+
+    if std::env::var("DISPLAY").is_err() {
+        eprintln!("no DISPLAY, skipped (run under xvfb-run)");
+        return;
+    }
+    std::env::set_var(capture_backend::FIXTURES_ENV, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/popouts"));
+    let pfd = backend().window_list().unwrap().into_iter().find(|info| info.title == "PFD").unwrap().hwnd;
+
+    // what Wine makes of MSFS and its pop-out
+    let (conn, screen_num) = x11rb::connect(None).unwrap();
+    let root = conn.setup().roots[screen_num].root;
+    let create = |name: &str| -> Window {
+        let window = conn.generate_id().unwrap();
+        conn.create_window(COPY_DEPTH_FROM_PARENT, window, root, 0, 0, 200, 200, 0, WindowClass::INPUT_OUTPUT,
+                           0, &CreateWindowAux::new().event_mask(EventMask::BUTTON_PRESS | EventMask::BUTTON_RELEASE))
+            .unwrap();
+        conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_NAME, AtomEnum::STRING, name.as_bytes())
+            .unwrap();
+        conn.map_window(window).unwrap();
+        window
+    };
+    let sim = create(SYNTHETIC_SIM_TITLE);
+    let window = create("PFD");
+    conn.sync().unwrap();
+
+    let injector = X11Injector::connect(false).unwrap();
+    // a window of the same name that isn't under MSFS, and an hwnd taken for an X window
+    assert!(injector.pointer(pfd, PointerEvent::Down, 40, 50).is_err());
+    assert!(injector.pointer(window as isize, PointerEvent::Down, 40, 50).is_err());
+
+    conn.change_property32(PropMode::REPLACE, window, AtomEnum::WM_TRANSIENT_FOR, AtomEnum::WINDOW, &[sim])
+        .unwrap();
+    conn.sync().unwrap();
+    click(&injector, pfd, 40, 50, Duration::from_millis(10)).unwrap();
+
+    let mut received: Vec<(&str, Window, i16, i16)> = vec![];
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while received.len() < 2 && Instant::now() < deadline {
+        match conn.poll_for_event().unwrap() {
+            Some(Event::ButtonPress(event)) => received.push(("down", event.event, event.event_x, event.event_y)),
+            Some(Event::ButtonRelease(event)) => received.push(("up", event.event, event.event_x, event.event_y)),
+            Some(_) => {}
+            None => std::thread::sleep(Duration::from_millis(10))
+        }
+    }
+    assert_eq!(received, vec![("down", window, 40, 50), ("up", window, 40, 50)]);
+}
@@ -0,0 +1,140 @@
+// CDU screens from output vars: the markup rows of the FBW MCDU, the PMDG SDK screen, and when the
+// pop-out can be left alone because the bridge pushes the screen.
+
+#[path = "../src/bridge_protocol.rs"]
+#[allow(dead_code)]
+mod bridge_protocol;
+#[path = "../src/comm_sender.rs"]
+#[allow(dead_code)]
+mod comm_sender;
+#[path = "../src/fms_ocr.rs"]
+#[allow(dead_code)]
+mod fms_ocr;
+#[path = "../src/output_vars.rs"]
+#[allow(dead_code)]
+mod output_vars;
+
+// comm_sender and output_vars log through the app's logger, which writes next to the exe
+mod debug_logger {
+    use std::sync::{Arc, Mutex};
+
+    pub fn log(new_log: &str, _log_str: &Option<Arc<Mutex<String>>>) {
+        eprintln!("{}", new_log);
+    }
+}
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use crossbeam_channel::bounded;
+use bridge_protocol::{BridgeRequest, VAR_SUBSCRIPTION_VERSION};
+use comm_sender::BridgeDispatcher;
+use fms_ocr::{FmsCell, FmsPage, COLUMNS, ROWS, UNKNOWN_CHAR};
+use output_vars::{OutputVars, PageFormat, VarSource};
+
+fn text(cells: &[FmsCell]) -> String {
+    cells.iter().map(|cell| cell.char).collect()
+}
+
+fn cell(char: char, color: &str, small: bool) -> FmsCell {
+    FmsCell { char, color: color.to_string(), small }
+}
+
+#[test]
+fn markup_tags_nest_until_their_end() {
+    let cells = output_vars::markup_row("{small}{cyan}CO{end}R{end} {amber}{big}L{end}F");
+    assert_eq!(cells.len(), COLUMNS as usize);
+    assert_eq!(&cells[..5], &[cell('C', "cyan", true), cell('O', "cyan", true), cell('R', "white", true),
+        cell(' ', "", false), cell('L', "amber", false)]);
+    // {big} ended, {amber} didn't
+    assert_eq!(cells[5], cell('F', "amber", false));
+
+    let cells = output_vars::markup_row("{green}A{magenta}B{end}C{end}D");
+    let colors: Vec<&str> = cells[..4].iter().map(|cell| cell.color.as_str()).collect();
+    assert_eq!(colors, vec!["green", "magenta", "green", "white"]);
+}
+
+#[test]
+fn markup_spaces_and_broken_tags() {
+    assert_eq!(text(&output_vars::markup_row("A{sp}{sp}B")), format!("{:<24}", "A  B"));
+    // an unterminated tag is text, an unknown one is ignored
+    assert_eq!(text(&output_vars::markup_row("AB{cyan")), format!("{:<24}", "AB{cyan"));
+    assert_eq!(text(&output_vars::markup_row("{blink}AB{end}")), format!("{:<24}", "AB"));
+    // longer rows are cut at the width of the CDU
+    assert_eq!(text(&output_vars::markup_row(&"X".repeat(30))), "X".repeat(24));
+    assert_eq!(text(&output_vars::markup_row("")), " ".repeat(24));
+}
+
+// the PMDG screen: cells column by column, symbol, colour and flags, then the power flag
+fn pmdg_screen(powered: bool, cells: &[(usize, usize, u8, u8, u8)]) -> String {
+    let mut bytes = vec![0u8; (COLUMNS * ROWS * 3) as usize + 1];
+    for (column, row, symbol, color, flags) in cells {
+        let at = (column * ROWS as usize + row) * 3;
+        bytes[at..at + 3].copy_from_slice(&[*symbol, *color, *flags]);
+    }
+    bytes[(COLUMNS * ROWS * 3) as usize] = powered as u8;
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[test]
+fn pmdg_screen_makes_a_page() {
+    let screen = pmdg_screen(true, &[(0, 0, b'A', 2, 0x01), (1, 0, b'B', 1, 0), (23, 13, 0xb0, 4, 0),
+        (5, 6, 0xff, 0, 0)]);
+    let page: FmsPage = output_vars::pmdg_cdu_page(&screen).unwrap();
+    assert_eq!(page.lines.len(), ROWS as usize);
+    assert_eq!(page.lines[0], format!("{:<24}", "AB"));
+    assert_eq!(page.cells[0][0], cell('A', "green", true));
+    assert_eq!(page.cells[0][1], cell('B', "cyan", false));
+    assert_eq!(page.cells[13][23], cell('°', "amber", false));
+    // a symbol the page has no char for
+    assert_eq!(page.cells[6][5].char, UNKNOWN_CHAR);
+    assert_eq!(page.unknown, 1);
+}
+
+#[test]
+fn unpowered_pmdg_screen_is_blank() {
+    let page = output_vars::pmdg_cdu_page(&pmdg_screen(false, &[(0, 0, b'A', 2, 0)])).unwrap();
+    assert!(page.lines.iter().all(|line| line == &" ".repeat(24)));
+    assert_eq!(page.unknown, 0);
+}
+
+#[test]
+fn broken_pmdg_data_is_no_page() {
+    let screen = pmdg_screen(true, &[]);
+    // the area before the aircraft wrote it
+    assert!(output_vars::pmdg_cdu_page("").is_none());
+    assert!(output_vars::pmdg_cdu_page(&screen[..screen.len() - 2]).is_none());
+    assert!(output_vars::pmdg_cdu_page(&format!("{}0", screen)).is_none());
+    assert!(output_vars::pmdg_cdu_page(&screen.replacen("00", "ZZ", 1)).is_none());
+}
+
+fn rows_source() -> VarSource {
+    VarSource { vars: vec!["L:ROW_0".to_string(), "L:ROW_1".to_string()], format: PageFormat::Rows }
+}
+
+#[test]
+fn screen_from_the_bridge_keeps_the_pop_out_in_place() {
+    let (tx, rx) = bounded::<BridgeRequest>(0);
+    // takes the subscriptions
+    thread::spawn(move || while rx.recv().is_ok() {});
+    let bridge = Arc::new(BridgeDispatcher::new(tx, None));
+    let vars = OutputVars::new(Arc::clone(&bridge), None);
+
+    // a bridge that can't push vars
+    vars.select(Some(rows_source()));
+    assert!(!vars.available());
+    bridge.set_bridge_version(VAR_SUBSCRIPTION_VERSION);
+    vars.select(None);
+    assert!(!vars.available());
+
+    // no hiding from the start, the values come later
+    vars.select(Some(rows_source()));
+    assert!(vars.available());
+    assert!(!vars.active());
+    vars.update(HashMap::from([("L:ROW_1".to_string(), "{amber}LFPG{end}".to_string())]));
+    assert!(vars.active());
+    let page: FmsPage = serde_json::from_str(&vars.page_json().unwrap()).unwrap();
+    assert_eq!(page.lines[0], " ".repeat(24));
+    assert_eq!(page.lines[1], format!("{:<24}", "LFPG"));
+    assert_eq!(page.cells[1][0].color, "amber");
+}
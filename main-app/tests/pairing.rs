@@ -0,0 +1,98 @@
+// Pairing devices with the token of the QR code, and which requests need a paired device.
+
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+
+use std::fs;
+use pairing::{is_cross_site, is_local_host, is_public_path, PairingStore};
+
+fn store(name: &str) -> (PairingStore, String) {
+    let file = std::env::temp_dir().join(format!("reachfms_pairings_{}_{}.json", name, std::process::id()));
+    let _ = fs::remove_file(&file);
+    let file = file.to_string_lossy().to_string();
+    (PairingStore::open(file.clone()), file)
+}
+
+#[test]
+fn token_pairs_one_device() {
+    let (store, file) = store("once");
+    let token = store.token();
+    assert!(store.pair("not the token", "phone", "now".to_string()).is_err());
+    let session = store.pair(&token, "phone", "now".to_string()).unwrap();
+    // the QR code was scanned, it doesn't pair anyone else
+    assert!(store.pair(&token, "tablet", "now".to_string()).is_err());
+    assert_ne!(store.token(), token);
+    let id = store.device(&session).unwrap();
+    assert!(store.device("made up session").is_none());
+    assert_eq!(store.devices().iter().map(|device| (device.id.clone(), device.name.clone())).collect::<Vec<_>>(),
+               vec![(id, "phone".to_string())]);
+    // the file only has the hash of the session
+    assert!(!fs::read_to_string(&file).unwrap().contains(&session));
+    let _ = fs::remove_file(file);
+}
+
+#[test]
+fn pairings_survive_a_restart_the_token_doesnt() {
+    let (store, file) = store("restart");
+    let session = store.pair(&store.token(), "phone", "now".to_string()).unwrap();
+    let token = store.token();
+    let reopened = PairingStore::open(file.clone());
+    assert_eq!(reopened.device(&session), store.device(&session));
+    assert_ne!(reopened.token(), token);
+    let _ = fs::remove_file(file);
+}
+
+#[test]
+fn revoked_devices_lose_their_session() {
+    let (store, file) = store("revoke");
+    let phone = store.pair(&store.token(), "phone", "now".to_string()).unwrap();
+    let tablet = store.pair(&store.token(), "tablet", "now".to_string()).unwrap();
+    let phone_id = store.device(&phone).unwrap();
+    assert_eq!(store.revoke(&phone_id), Ok(true));
+    assert_eq!(store.revoke(&phone_id), Ok(false));
+    assert!(!store.has_device(&phone_id));
+    assert!(store.device(&phone).is_none());
+    assert!(store.device(&tablet).is_some());
+    store.revoke_all().unwrap();
+    assert!(store.device(&tablet).is_none());
+    assert!(PairingStore::open(file.clone()).devices().is_empty());
+    let _ = fs::remove_file(file);
+}
+
+#[test]
+fn only_the_pairing_pages_are_public() {
+    for path in ["/", "/icon.png", "/reachfms.crt", "/pair", "/static/js/main.js"] {
+        assert!(is_public_path(path), "{}", path);
+    }
+    for path in ["/touch_event", "/stop_server", "/set_settings", "/ws", "/pairings", "/pairings/revoke", "/pair/x"] {
+        assert!(!is_public_path(path), "{}", path);
+    }
+}
+
+#[test]
+fn pages_of_other_sites_are_told_apart() {
+    // the app and the bridge
+    assert!(!is_cross_site(None, None, Some("localhost:5273")));
+    // the page of the server, on this pc or on a device
+    assert!(!is_cross_site(Some("same-origin"), Some("http://192.168.1.20:5273"), Some("192.168.1.20:5273")));
+    assert!(!is_cross_site(None, Some("http://localhost:5273"), Some("localhost:5273")));
+    assert!(!is_cross_site(None, Some("https://192.168.1.20:5274"), Some("192.168.1.20:5274")));
+    // typed into the address bar
+    assert!(!is_cross_site(Some("none"), None, Some("localhost:5273")));
+    assert!(is_cross_site(Some("cross-site"), Some("https://example.com"), Some("localhost:5273")));
+    // another server on this pc
+    assert!(is_cross_site(Some("same-site"), Some("http://localhost:3000"), Some("localhost:5273")));
+    assert!(is_cross_site(None, Some("https://example.com"), Some("localhost:5273")));
+    assert!(is_cross_site(None, Some("http://192.168.1.20:8080"), Some("192.168.1.20:5273")));
+}
+
+#[test]
+fn loopback_only_by_its_names() {
+    for host in [None, Some("localhost:5273"), Some("127.0.0.1:5273"), Some("[::1]:5273"), Some("localhost")] {
+        assert!(is_local_host(host), "{:?}", host);
+    }
+    // a name of another site resolving to this pc
+    assert!(!is_local_host(Some("rebind.example.com:5273")));
+    assert!(!is_local_host(Some("192.168.1.20:5273")));
+}
@@ -0,0 +1,257 @@
+// The http server with mock_bridge standing in for SimConnector.exe: the requests of the web ui
+// reach the bridge and get its answers. One server (on a free port, not the app's) and one bridge
+// for all the tests, pop-outs come from the png fixtures and clicks go nowhere.
+
+#[path = "../src/addon_config.rs"]
+#[allow(dead_code)]
+mod addon_config;
+#[path = "../src/api_communicator.rs"]
+#[allow(dead_code)]
+mod api_communicator;
+#[path = "../src/bridge_protocol.rs"]
+#[allow(dead_code)]
+mod bridge_protocol;
+#[path = "../src/bridge_supervisor.rs"]
+#[allow(dead_code)]
+mod bridge_supervisor;
+#[path = "../src/capture_backend.rs"]
+#[allow(dead_code)]
+mod capture_backend;
+#[path = "../src/comm_sender.rs"]
+#[allow(dead_code)]
+mod comm_sender;
+#[path = "../src/config_handler.rs"]
+#[allow(dead_code)]
+mod config_handler;
+#[path = "../src/debug_logger.rs"]
+#[allow(dead_code)]
+mod debug_logger;
+#[path = "../src/fms_ocr.rs"]
+#[allow(dead_code)]
+mod fms_ocr;
+#[path = "../src/frame_delta.rs"]
+#[allow(dead_code)]
+mod frame_delta;
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+#[path = "../src/http_streamer.rs"]
+#[allow(dead_code)]
+mod http_streamer;
+#[path = "../src/image_process.rs"]
+#[allow(dead_code)]
+mod image_process;
+#[path = "../src/image_streamer.rs"]
+#[allow(dead_code)]
+mod image_streamer;
+#[path = "../src/input_injector.rs"]
+#[allow(dead_code)]
+mod input_injector;
+#[path = "../src/instrument_settings.rs"]
+#[allow(dead_code)]
+mod instrument_settings;
+#[path = "../src/mjpeg_stream.rs"]
+#[allow(dead_code)]
+mod mjpeg_stream;
+#[path = "../src/output_vars.rs"]
+#[allow(dead_code)]
+mod output_vars;
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+#[path = "../src/touch_gestures.rs"]
+#[allow(dead_code)]
+mod touch_gestures;
+#[cfg(feature = "webrtc")]
+#[path = "../src/video_encoder.rs"]
+#[allow(dead_code)]
+mod video_encoder;
+#[cfg(feature = "webrtc")]
+#[path = "../src/webrtc_transport.rs"]
+#[allow(dead_code)]
+mod webrtc_transport;
+#[path = "../src/window_journal.rs"]
+#[allow(dead_code)]
+mod window_journal;
+#[path = "../src/window_layouts.rs"]
+#[allow(dead_code)]
+mod window_layouts;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+// http_streamer takes it from the crate root, like in main.rs
+use crate::image_process::ImageProcess;
+
+const WAIT: Duration = Duration::from_secs(15);
+
+// stored with a version the update server won't have, so it's the one used
+const ADDON_CONFIG: &str = r#"{"aircraft_addons": [{
+    "title": "a310_fixture", "display": "A310", "svg_image": "", "output_vars": [],
+    "button_actions": [{"button": "EXEC", "lvar": "A310_MCDU_1_EXEC"}, {"button": "INIT", "lvar": "A310_MCDU_1_INIT"}],
+    "fo_button_actions": [{"button": "EXEC", "lvar": "A310_MCDU_2_EXEC"}],
+    "fms_aspect": 1.0, "display_width": 100, "display_top": 0, "display_left": 0,
+    "custom_popout": [], "touch_enabled": true, "last_updated": ""
+}], "version": 4000000000, "app_version": 0, "updated": ""}"#;
+
+const SCENARIO: &str = r#"{
+    "connected": true,
+    "aircraft": "Microsoft_A310_Fixture",
+    "vars": {"L:S_FCU_EFIS1_BARO_STD": "1", "A:PLANE ALTITUDE,feet": "12000"}
+}"#;
+
+struct Bridge {
+    port: u16,
+    _process: Child,
+    record_file: String,
+}
+
+// the server and the bridge, started by the first test that needs them
+fn bridge() -> &'static Bridge {
+    static BRIDGE: OnceLock<Bridge> = OnceLock::new();
+    BRIDGE.get_or_init(|| {
+        std::env::set_var(capture_backend::FIXTURES_ENV, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/popouts"));
+        std::env::set_var(input_injector::INPUT_ENV, "recording");
+        // free now, the server takes it right after
+        let port = TcpListener::bind(("127.0.0.1", 0)).unwrap().local_addr().unwrap().port();
+        std::env::set_var(config_handler::PORT_ENV, port.to_string());
+        fs::create_dir_all(config_handler::get_static_folder()).unwrap();
+        fs::write(config_handler::get_addon_config(), ADDON_CONFIG).unwrap();
+        thread::spawn(|| http_streamer::main(None));
+        wait_for("the server", || TcpStream::connect(("127.0.0.1", port)).is_ok());
+
+        let dir = std::env::temp_dir();
+        let scenario_file = dir.join(format!("reachfms_scenario_{}.json", std::process::id()));
+        let record_file = dir.join(format!("reachfms_record_{}.jsonl", std::process::id()));
+        fs::write(&scenario_file, SCENARIO).unwrap();
+        let _ = fs::remove_file(&record_file);
+        // it exits when the test process is gone and the connection with it
+        let process = Command::new(env!("CARGO_BIN_EXE_mock_bridge"))
+            .arg(&scenario_file)
+            .arg("--record").arg(&record_file)
+            .arg("--url").arg(format!("ws://localhost:{}/ws", port))
+            .spawn().unwrap();
+        wait_for("the bridge", || {
+            let status: serde_json::Value = serde_json::from_str(&get_from(port, "/status")).unwrap();
+            status["bridge_status"]["comm"] == true
+        });
+        Bridge { port, _process: process, record_file: record_file.to_string_lossy().to_string() }
+    })
+}
+
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < WAIT, "{} didn't come up", what);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// a GET from this pc, like the app's own requests; the status code and the body
+fn request(path: &str) -> (u16, String) {
+    request_from(bridge().port, path)
+}
+
+fn request_from(port: u16, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    stream.set_read_timeout(Some(WAIT)).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost:{}\r\nConnection: close\r\n\r\n", path, port).unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let code = head.split(' ').nth(1).and_then(|code| code.parse::<u16>().ok()).unwrap();
+    (code, body.to_string())
+}
+
+// the body of a 200
+fn get(path: &str) -> String {
+    get_from(bridge().port, path)
+}
+
+fn get_from(port: u16, path: &str) -> String {
+    let (code, body) = request_from(port, path);
+    assert_eq!(code, 200, "{}: {}", path, body);
+    body
+}
+
+// the lvars of the button presses the bridge got
+fn pressed(bridge: &Bridge) -> Vec<String> {
+    fs::read_to_string(&bridge.record_file).unwrap_or_default().lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|command| command["type"] == "ButtonPress")
+        .filter_map(|command| command["lvar"].as_str().map(|lvar| lvar.to_string()))
+        .collect()
+}
+
+fn wait_for_press(bridge: &Bridge, lvar: &str) {
+    wait_for(lvar, || pressed(bridge).iter().any(|pressed| pressed == lvar));
+}
+
+#[test]
+fn status_comes_from_the_bridge() {
+    bridge();
+    let status: serde_json::Value = serde_json::from_str(&get("/status")).unwrap();
+    assert_eq!(status["bridge_status"]["comm"], true);
+    assert_eq!(status["bridge_status"]["connected"], true);
+    // the app didn't start it, the supervisor doesn't know about it
+    assert_eq!(status["bridge_status"]["started"], false);
+}
+
+#[test]
+fn simvars_are_read_from_the_bridge() {
+    bridge();
+    assert_eq!(get("/get_simvar?var=L:S_FCU_EFIS1_BARO_STD"), "1");
+    assert_eq!(get("/get_simvar?var=A:PLANE%20ALTITUDE,feet"), "12000");
+    assert_eq!(get("/get_simvar?var=L:NOT_IN_THE_SCENARIO"), "");
+}
+
+#[test]
+fn buttons_are_pressed_on_the_bridge() {
+    let bridge = bridge();
+    assert_eq!(get("/get_aircraft"), "MICROSOFT_A310_FIXTURE");
+    assert_eq!(get("/mcdu_btn_press?btn=BTN:INIT"), "ok");
+    wait_for_press(bridge, "A310_MCDU_1_INIT");
+    assert_eq!(get("/mcdu_btn_press?btn=BTN:EXEC&side=fo"), "ok");
+    wait_for_press(bridge, "A310_MCDU_2_EXEC");
+    // the aircraft has one map for the third MCDU as well: the captain's
+    assert_eq!(get("/mcdu_btn_press?btn=BTN:EXEC&side=3"), "ok");
+    wait_for_press(bridge, "A310_MCDU_1_EXEC");
+    assert_eq!(get("/mcdu_btn_press?btn=BTN:NO_SUCH_BUTTON"), "Cant find lvar");
+}
+
+// the server of the tests doesn't serve https
+#[test]
+fn qr_code_for_another_certificate_doesnt_pair() {
+    bridge();
+    let (code, body) = request("/pair?token=whatever&fp=0a1b");
+    assert_eq!(code, 409, "{}", body);
+    assert!(body.contains("0A:1B"), "{}", body);
+}
+
+#[test]
+fn only_pop_outs_of_the_sim_are_served() {
+    let port = bridge().port;
+    let windows = capture_backend::backend().window_list().unwrap();
+    let sim = ImageProcess::get_sim_hwnd(&windows);
+    let pfd = windows.iter().find(|window| window.title == "PFD").unwrap().hwnd;
+    assert_eq!(request(&format!("/image_state?hwnd={}", pfd)).0, 200);
+    for hwnd in [sim, 424242] {
+        assert_eq!(request(&format!("/image_state?hwnd={}", hwnd)).0, 404, "{}", hwnd);
+        assert_eq!(request(&format!("/stream/{}.mjpg", hwnd)).0, 404, "{}", hwnd);
+    }
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://localhost:{}/ws", port)).unwrap();
+    socket.send(tungstenite::Message::Text(format!("IMAGESUBSCRIBE:{}", sim))).unwrap();
+    match socket.read().unwrap() {
+        tungstenite::Message::Text(text) => assert_eq!(text, format!("Can't find pop-out: {}", sim)),
+        other => panic!("unexpected message {:?}", other)
+    }
+}
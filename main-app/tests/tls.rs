@@ -0,0 +1,101 @@
+// The certificate of the https server: made once as a server certificate (not a CA), loaded into a
+// server config, and its fingerprint.
+
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("reachfms_tls_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn files(dir: &Path) -> (String, String) {
+    (dir.join("tls_cert.pem").to_string_lossy().to_string(), dir.join("tls_key.pem").to_string_lossy().to_string())
+}
+
+#[test]
+fn self_signed_certificate_is_made_once() {
+    let dir = scratch_dir("once");
+    let (cert_file, key_file) = files(&dir);
+    let hosts = vec!["localhost".to_string(), "127.0.0.1".to_string(), "192.168.1.20".to_string()];
+    assert_eq!(tls::ensure_self_signed(&cert_file, &key_file, &hosts), Ok(true));
+    let first = tls::load(&cert_file, &key_file).unwrap();
+    // the devices trust this one, the next start keeps it
+    assert_eq!(tls::ensure_self_signed(&cert_file, &key_file, &hosts), Ok(false));
+    let second = tls::load(&cert_file, &key_file).unwrap();
+    assert_eq!(first.fingerprint, second.fingerprint);
+    assert_eq!(first.fingerprint, tls::fingerprint(&first.cert));
+    assert_eq!(first.fingerprint.len(), 64);
+    assert!(first.fingerprint.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn missing_key_makes_a_new_certificate() {
+    let dir = scratch_dir("key");
+    let (cert_file, key_file) = files(&dir);
+    let hosts = vec!["localhost".to_string()];
+    tls::ensure_self_signed(&cert_file, &key_file, &hosts).unwrap();
+    let first = tls::load(&cert_file, &key_file).unwrap().fingerprint;
+    fs::remove_file(&key_file).unwrap();
+    assert_eq!(tls::ensure_self_signed(&cert_file, &key_file, &hosts), Ok(true));
+    assert_ne!(tls::load(&cert_file, &key_file).unwrap().fingerprint, first);
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn broken_configured_files_are_refused() {
+    let dir = scratch_dir("broken");
+    let (cert_file, key_file) = files(&dir);
+    assert!(tls::load(&cert_file, &key_file).is_err());
+    fs::write(&cert_file, "not a certificate").unwrap();
+    fs::write(&key_file, "not a key").unwrap();
+    assert!(tls::load(&cert_file, &key_file).is_err());
+    // a key of another certificate
+    let other = scratch_dir("other");
+    let (other_cert, other_key) = files(&other);
+    tls::ensure_self_signed(&cert_file, &key_file, &["localhost".to_string()]).unwrap();
+    tls::ensure_self_signed(&other_cert, &other_key, &["localhost".to_string()]).unwrap();
+    assert!(tls::load(&cert_file, &other_key).is_err());
+    let _ = fs::remove_dir_all(dir);
+    let _ = fs::remove_dir_all(other);
+}
+
+fn contains(der: &[u8], part: &[u8]) -> bool {
+    der.windows(part.len()).any(|window| window == part)
+}
+
+#[test]
+fn self_signed_certificate_is_no_ca() {
+    let dir = scratch_dir("leaf");
+    let (cert_file, key_file) = files(&dir);
+    let hosts = vec!["localhost".to_string(), "192.168.1.20".to_string()];
+    tls::ensure_self_signed(&cert_file, &key_file, &hosts).unwrap();
+    let cert = tls::load(&cert_file, &key_file).unwrap().cert;
+    // no basic constraints (2.5.29.19): it can't sign certificates for other sites
+    assert!(!contains(&cert, &[0x06, 0x03, 0x55, 0x1d, 0x13]));
+    // server auth (1.3.6.1.5.5.7.3.1) for the hosts in its alt names
+    assert!(contains(&cert, &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01]));
+    assert!(contains(&cert, &[0x82, 0x09]) && contains(&cert, b"localhost"));
+    assert!(contains(&cert, &[0x87, 0x04, 192, 168, 1, 20]));
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn fingerprint_as_browsers_show_it() {
+    let fingerprint = "0a1b2c3d".repeat(8);
+    let shown = tls::display_fingerprint(&fingerprint);
+    assert!(shown.starts_with("0A:1B:2C:3D:0A"));
+    assert_eq!(shown.len(), 64 + 31);
+    // the fp of the QR code against either way of writing it
+    assert!(tls::same_fingerprint(&fingerprint, &shown));
+    assert!(tls::same_fingerprint(&shown, &fingerprint.to_uppercase()));
+    assert!(!tls::same_fingerprint(&fingerprint, &"0a1b2c3d".repeat(7)));
+    assert!(!tls::same_fingerprint("", ""));
+}
@@ -0,0 +1,169 @@
+// Touches on the streamed picture: parsing, the transform into the pop-out and what ends up on it.
+
+// input_injector looks pop-outs up through capture_backend
+#[path = "../src/capture_backend.rs"]
+#[allow(dead_code)]
+mod capture_backend;
+#[path = "../src/config_handler.rs"]
+#[allow(dead_code)]
+mod config_handler;
+#[path = "../src/debug_logger.rs"]
+#[allow(dead_code)]
+mod debug_logger;
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+#[path = "../src/input_injector.rs"]
+#[allow(dead_code)]
+mod input_injector;
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+#[path = "../src/touch_gestures.rs"]
+#[allow(dead_code)]
+mod touch_gestures;
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use input_injector::{InputInjector, PointerEvent, RecordingInjector};
+use touch_gestures::{GestureEvent, GestureInput, GestureKind, GestureTracker, InputStep, TouchArea};
+
+// what calculate_crop gives a 4:3 instrument in the 700x700 pop-out
+const FMS: TouchArea = TouchArea { crop: [[0, 87], [700, 525]], capture: [700, 700], client: [700, 700], scale: 1.0 };
+const WHOLE: TouchArea = TouchArea { crop: [[0, 0], [0, 0]], capture: [100, 100], client: [100, 100], scale: 1.0 };
+
+fn event(text: &str) -> GestureEvent {
+    GestureEvent::parse(text).unwrap().unwrap()
+}
+
+#[test]
+fn parses_touch_messages_only() {
+    let down = event(r#"TOUCH:{"type":"down","id":3,"x":0.1,"y":0.25}"#);
+    assert_eq!((down.kind, down.id, down.x, down.y), (GestureKind::Down, 3, 0.1, 0.25));
+    let wheel = event(r#"TOUCH: {"type":"wheel","x":1,"y":0,"delta":-240}"#);
+    assert_eq!((wheel.kind, wheel.delta), (GestureKind::Wheel, -240));
+    assert!(GestureEvent::parse("IMAGESUBSCRIBE").is_none());
+    assert!(GestureEvent::parse(r#"TOUCH:{"type":"pinch"}"#).unwrap().is_err());
+}
+
+#[test]
+fn transform_goes_through_the_crop() {
+    assert_eq!(FMS.to_popout(0.0, 0.0), (0, 87));
+    assert_eq!(FMS.to_popout(0.5, 0.5), (350, 349));
+    // off the picture: to its edge, not onto the part of the pop-out that was cropped off
+    assert_eq!(FMS.to_popout(1.0, 1.0), (699, 611));
+    assert_eq!(FMS.to_popout(-0.3, 2.0), (0, 611));
+    // find_crop_for_instruments cuts a square from the middle
+    let square = TouchArea { crop: [[100, 0], [600, 600]], capture: [800, 600], client: [800, 600], scale: 1.0 };
+    assert_eq!(square.to_popout(0.0, 0.0), (100, 0));
+    assert_eq!(square.to_popout(0.5, 0.5), (400, 300));
+    // a crop without size goes to the edge of the capture
+    let corner = TouchArea { crop: [[200, 100], [0, 0]], ..square };
+    assert_eq!(corner.to_popout(1.0, 1.0), (799, 599));
+}
+
+#[test]
+fn transform_scales_to_the_client_and_dpi() {
+    // the capture is smaller than the client area
+    let half = TouchArea { capture: [50, 50], ..WHOLE };
+    assert_eq!(half.to_popout(0.5, 0.5), (51, 51));
+    // 150% monitor, the pop-out takes real pixels
+    let scaled = TouchArea { scale: 1.5, ..FMS };
+    assert_eq!(scaled.to_popout(0.0, 0.0), (0, 131));
+    assert_eq!(scaled.to_popout(0.5, 0.5), (525, 524));
+    assert_eq!(scaled.to_popout(1.0, 1.0), (1049, 917));
+}
+
+#[test]
+fn pixels_of_older_clients_map_back() {
+    let (x, y) = FMS.normalize(350, 262);
+    assert_eq!(FMS.to_popout(x, y), (350, 349));
+    let (x, y) = FMS.normalize(0, 524);
+    assert_eq!(FMS.to_popout(x, y), (0, 611));
+}
+
+#[test]
+fn first_finger_drives_the_mouse() {
+    let mut tracker = GestureTracker::new();
+    let down = tracker.steps(&event(r#"TOUCH:{"type":"down","id":1,"x":0.1,"y":0.2}"#), 5, FMS).unwrap();
+    assert_eq!(down.steps, vec![InputStep::Pointer(PointerEvent::Move, 70, 192),
+                                InputStep::Pointer(PointerEvent::Down, 70, 192)]);
+    assert!(!down.ended);
+    assert!(tracker.steps(&event(r#"TOUCH:{"type":"down","id":2,"x":0.5,"y":0.5}"#), 5, FMS).is_none());
+    assert!(tracker.steps(&event(r#"TOUCH:{"type":"move","id":2,"x":0.6,"y":0.6}"#), 5, FMS).is_none());
+    // the selected pop-out changed mid drag, the gesture stays where it started
+    let drag = tracker.steps(&event(r#"TOUCH:{"type":"move","id":1,"x":0.2,"y":0.2}"#), 9, WHOLE).unwrap();
+    assert_eq!((drag.hwnd, drag.steps.clone()), (5, vec![InputStep::Pointer(PointerEvent::Move, 140, 192)]));
+    assert!(tracker.steps(&event(r#"TOUCH:{"type":"up","id":2,"x":0.6,"y":0.6}"#), 5, FMS).is_none());
+    let up = tracker.steps(&event(r#"TOUCH:{"type":"up","id":1,"x":0.3,"y":0.2}"#), 5, FMS).unwrap();
+    assert_eq!(up.steps, vec![InputStep::Pointer(PointerEvent::Up, 210, 192)]);
+    assert!(up.ended);
+    assert!(tracker.abort().is_none());
+}
+
+#[test]
+fn cancel_releases_where_the_finger_was() {
+    let mut tracker = GestureTracker::new();
+    tracker.steps(&event(r#"TOUCH:{"type":"down","id":1,"x":0.1,"y":0.1}"#), 5, WHOLE).unwrap();
+    tracker.steps(&event(r#"TOUCH:{"type":"move","id":1,"x":0.2,"y":0.3}"#), 5, WHOLE).unwrap();
+    let cancel = tracker.steps(&event(r#"TOUCH:{"type":"cancel","id":1}"#), 5, WHOLE).unwrap();
+    assert_eq!(cancel.steps, vec![InputStep::Pointer(PointerEvent::Up, 20, 30)]);
+}
+
+#[test]
+fn long_press_and_wheel() {
+    let mut tracker = GestureTracker::new();
+    let press = tracker.steps(&event(r#"TOUCH:{"type":"press","x":0.2,"y":0.3,"ms":800}"#), 5, WHOLE).unwrap();
+    assert_eq!(press.steps, vec![InputStep::Pointer(PointerEvent::Move, 20, 30),
+                                 InputStep::Pointer(PointerEvent::Down, 20, 30),
+                                 InputStep::Hold(Duration::from_millis(800)),
+                                 InputStep::Pointer(PointerEvent::Up, 20, 30)]);
+    let capped = tracker.steps(&event(r#"TOUCH:{"type":"press","x":0.2,"y":0.3,"ms":60000}"#), 5, WHOLE).unwrap();
+    assert_eq!(capped.steps[2], InputStep::Hold(Duration::from_millis(touch_gestures::MAX_PRESS_MS)));
+    let wheel = tracker.steps(&event(r#"TOUCH:{"type":"wheel","x":0.1,"y":0.2,"delta":120}"#), 5, FMS).unwrap();
+    assert_eq!(wheel.steps, vec![InputStep::Pointer(PointerEvent::Wheel(120), 70, 192)]);
+    assert!(wheel.ended);
+}
+
+#[test]
+fn gestures_are_injected_in_order() {
+    let recorder = Arc::new(RecordingInjector::new());
+    let shared: Arc<dyn InputInjector> = recorder.clone();
+    let (ended_tx, ended_rx) = mpsc::channel();
+    let mut input = GestureInput::start(Arc::new(Mutex::new(shared)),
+                                        move |_, hwnd| ended_tx.send(hwnd).unwrap(),
+                                        |e| panic!("{}", e));
+    for text in [r#"TOUCH:{"type":"down","id":1,"x":0.1,"y":0.2}"#,
+                 r#"TOUCH:{"type":"move","id":1,"x":0.2,"y":0.2}"#,
+                 r#"TOUCH:{"type":"up","id":1,"x":0.3,"y":0.2}"#,
+                 r#"TOUCH:{"type":"press","x":0.4,"y":0.4,"ms":20}"#] {
+        input.handle(&event(text), 7, FMS);
+    }
+    assert_eq!(ended_rx.recv_timeout(Duration::from_secs(2)), Ok(7));
+    assert_eq!(ended_rx.recv_timeout(Duration::from_secs(2)), Ok(7));
+    let events: Vec<(PointerEvent, i32, i32)> = recorder.events().iter()
+        .map(|recorded| (recorded.event, recorded.x, recorded.y)).collect();
+    assert_eq!(events, vec![(PointerEvent::Move, 70, 192), (PointerEvent::Down, 70, 192),
+                            (PointerEvent::Move, 140, 192), (PointerEvent::Up, 210, 192),
+                            (PointerEvent::Move, 280, 297), (PointerEvent::Down, 280, 297),
+                            (PointerEvent::Up, 280, 297)]);
+    let held = recorder.events();
+    assert!(held[6].at - held[5].at >= Duration::from_millis(20));
+}
+
+#[test]
+fn lost_connection_lets_go_of_the_mouse() {
+    let recorder = Arc::new(RecordingInjector::new());
+    let shared: Arc<dyn InputInjector> = recorder.clone();
+    let (ended_tx, ended_rx) = mpsc::channel();
+    let mut input = GestureInput::start(Arc::new(Mutex::new(shared)),
+                                        move |_, hwnd| ended_tx.send(hwnd).unwrap(),
+                                        |e| panic!("{}", e));
+    input.handle(&event(r#"TOUCH:{"type":"down","id":1,"x":0.1,"y":0.1}"#), 7, WHOLE);
+    drop(input);
+    assert_eq!(ended_rx.recv_timeout(Duration::from_secs(2)), Ok(7));
+    assert_eq!(recorder.events().last().map(|recorded| recorded.event), Some(PointerEvent::Up));
+}
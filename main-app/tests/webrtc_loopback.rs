@@ -0,0 +1,187 @@
+// The browser side of the WebRTC transport against the server: an offer over /ws, the answer
+// applied, and the H.264 frames of a pop-out arriving on the video track. Pop-outs come from the
+// png fixtures, the server runs on a free port.
+#![cfg(feature = "webrtc")]
+
+#[path = "../src/addon_config.rs"]
+#[allow(dead_code)]
+mod addon_config;
+#[path = "../src/api_communicator.rs"]
+#[allow(dead_code)]
+mod api_communicator;
+#[path = "../src/bridge_protocol.rs"]
+#[allow(dead_code)]
+mod bridge_protocol;
+#[path = "../src/bridge_supervisor.rs"]
+#[allow(dead_code)]
+mod bridge_supervisor;
+#[path = "../src/capture_backend.rs"]
+#[allow(dead_code)]
+mod capture_backend;
+#[path = "../src/comm_sender.rs"]
+#[allow(dead_code)]
+mod comm_sender;
+#[path = "../src/config_handler.rs"]
+#[allow(dead_code)]
+mod config_handler;
+#[path = "../src/debug_logger.rs"]
+#[allow(dead_code)]
+mod debug_logger;
+#[path = "../src/fms_ocr.rs"]
+#[allow(dead_code)]
+mod fms_ocr;
+#[path = "../src/frame_delta.rs"]
+#[allow(dead_code)]
+mod frame_delta;
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+#[path = "../src/http_streamer.rs"]
+#[allow(dead_code)]
+mod http_streamer;
+#[path = "../src/image_process.rs"]
+#[allow(dead_code)]
+mod image_process;
+#[path = "../src/image_streamer.rs"]
+#[allow(dead_code)]
+mod image_streamer;
+#[path = "../src/input_injector.rs"]
+#[allow(dead_code)]
+mod input_injector;
+#[path = "../src/instrument_settings.rs"]
+#[allow(dead_code)]
+mod instrument_settings;
+#[path = "../src/mjpeg_stream.rs"]
+#[allow(dead_code)]
+mod mjpeg_stream;
+#[path = "../src/output_vars.rs"]
+#[allow(dead_code)]
+mod output_vars;
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+#[path = "../src/touch_gestures.rs"]
+#[allow(dead_code)]
+mod touch_gestures;
+#[cfg(feature = "webrtc")]
+#[path = "../src/video_encoder.rs"]
+#[allow(dead_code)]
+mod video_encoder;
+#[cfg(feature = "webrtc")]
+#[path = "../src/webrtc_transport.rs"]
+#[allow(dead_code)]
+mod webrtc_transport;
+#[path = "../src/window_journal.rs"]
+#[allow(dead_code)]
+mod window_journal;
+#[path = "../src/window_layouts.rs"]
+#[allow(dead_code)]
+mod window_layouts;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::{connect, Message};
+use webrtc::api::APIBuilder;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+use crate::image_process::ImageProcess;
+
+const WAIT: Duration = Duration::from_secs(15);
+// without one the app closes when the update server can't be reached
+const ADDON_CONFIG: &str = r#"{"aircraft_addons": [], "version": 4000000000, "app_version": 0, "updated": ""}"#;
+
+// the port of the server
+fn start_server() -> u16 {
+    std::env::set_var(capture_backend::FIXTURES_ENV, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/popouts"));
+    std::env::set_var(input_injector::INPUT_ENV, "recording");
+    // free now, the server takes it right after
+    let port = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap().local_addr().unwrap().port();
+    std::env::set_var(config_handler::PORT_ENV, port.to_string());
+    std::fs::create_dir_all(config_handler::get_static_folder()).unwrap();
+    std::fs::write(config_handler::get_addon_config(), ADDON_CONFIG).unwrap();
+    thread::spawn(|| http_streamer::main(None));
+    let start = Instant::now();
+    while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(start.elapsed() < WAIT, "the server didn't come up");
+        thread::sleep(Duration::from_millis(100));
+    }
+    port
+}
+
+#[test]
+fn frames_arrive_over_webrtc() {
+    let port = start_server();
+    let hwnd = ImageProcess::find_popup_windows().unwrap().iter()
+        .find(|popout| popout.title == "PFD").unwrap().hwnd;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let frames = Arc::new(AtomicU64::new(0));
+
+    let mut media_engine = MediaEngine::default();
+    media_engine.register_default_codecs().unwrap();
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine).unwrap();
+    let api = APIBuilder::new().with_media_engine(media_engine).with_interceptor_registry(registry).build();
+    let connection = runtime.block_on(api.new_peer_connection(RTCConfiguration::default())).unwrap();
+    runtime.block_on(connection.add_transceiver_from_kind(RTPCodecType::Video, &[RTCRtpTransceiverInit {
+        direction: RTCRtpTransceiverDirection::Recvonly,
+        send_encodings: vec![],
+    }])).unwrap();
+
+    let frames_inner = Arc::clone(&frames);
+    connection.on_track(Box::new(move |track, _| {
+        let frames = Arc::clone(&frames_inner);
+        Box::pin(async move {
+            let track = match track {
+                None => return,
+                Some(track) => track
+            };
+            while let Ok((packet, _)) = track.read_rtp().await {
+                // the marker bit is set on the last packet of a frame
+                if packet.header.marker {
+                    frames.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })
+    }));
+
+    let offer = runtime.block_on(connection.create_offer(None)).unwrap();
+    let mut gathered = runtime.block_on(connection.gathering_complete_promise());
+    runtime.block_on(connection.set_local_description(offer)).unwrap();
+    let _ = runtime.block_on(gathered.recv());
+    let offer = runtime.block_on(connection.local_description()).unwrap();
+
+    let (mut socket, _) = connect(format!("ws://localhost:{}/ws", port)).unwrap();
+    let request = serde_json::json!({"offer": offer, "subscription": {"hwnd": hwnd}});
+    socket.send(Message::Text(format!("{}:{}", webrtc_transport::OFFER, request))).unwrap();
+    let answer = loop {
+        match socket.read().unwrap() {
+            Message::Text(text) => match text.strip_prefix(&format!("{}:", webrtc_transport::ANSWER)) {
+                Some(answer) => break answer.to_string(),
+                None => panic!("no answer: {}", text)
+            },
+            _ => continue
+        }
+    };
+    let answer: RTCSessionDescription = serde_json::from_str(&answer).unwrap();
+    runtime.block_on(connection.set_remote_description(answer)).unwrap();
+
+    // the session lives as long as the ws connection
+    thread::spawn(move || while socket.read().is_ok() {});
+    let start = Instant::now();
+    while frames.load(Ordering::Relaxed) == 0 {
+        assert!(start.elapsed() < WAIT, "no frame arrived, connection: {}", connection.connection_state());
+        thread::sleep(Duration::from_millis(100));
+    }
+    let _ = runtime.block_on(connection.close());
+}
@@ -0,0 +1,62 @@
+// The journal of hidden pop-outs on the synthetic backend: restoring the open pop-outs keeps the
+// entries of the ones that aren't open, they're put back when they are.
+
+#[path = "../src/capture_backend.rs"]
+#[allow(dead_code)]
+mod capture_backend;
+#[path = "../src/config_handler.rs"]
+#[allow(dead_code)]
+mod config_handler;
+#[path = "../src/debug_logger.rs"]
+#[allow(dead_code)]
+mod debug_logger;
+#[path = "../src/frame_encoder.rs"]
+#[allow(dead_code)]
+mod frame_encoder;
+#[path = "../src/image_process.rs"]
+#[allow(dead_code)]
+mod image_process;
+#[path = "../src/input_injector.rs"]
+#[allow(dead_code)]
+mod input_injector;
+#[path = "../src/pairing.rs"]
+#[allow(dead_code)]
+mod pairing;
+#[path = "../src/tls.rs"]
+#[allow(dead_code)]
+mod tls;
+#[path = "../src/window_journal.rs"]
+#[allow(dead_code)]
+mod window_journal;
+#[path = "../src/window_layouts.rs"]
+#[allow(dead_code)]
+mod window_layouts;
+
+use std::fs;
+use capture_backend::{backend, HideStrategy};
+use image_process::ImageProcess;
+use window_journal::window_journal;
+
+// left by a run that was killed, for a pop-out MSFS closed since
+const GONE: &str = r#"{"entries": [{"hwnd": 424242, "title": "GONE", "action": "hide",
+    "original": {"left": 10, "top": 20, "right": 310, "bottom": 420}}]}"#;
+
+#[test]
+fn restoring_keeps_the_entries_of_closed_pop_outs() {
+    std::env::set_var(capture_backend::FIXTURES_ENV, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/popouts"));
+    let journal_file = config_handler::get_window_journal_file();
+    fs::create_dir_all(std::path::Path::new(&journal_file).parent().unwrap()).unwrap();
+    fs::write(&journal_file, GONE).unwrap();
+
+    let pfd = ImageProcess::find_popup_windows().unwrap().into_iter()
+        .find(|popout| popout.title == "PFD").unwrap().hwnd;
+    ImageProcess::hide_window(pfd, HideStrategy::OffScreen);
+    assert!(backend().is_hidden(pfd));
+    assert_eq!(window_journal().pending(), 2);
+
+    assert!(ImageProcess::restore_all());
+    assert!(!backend().is_hidden(pfd));
+    assert_eq!(window_journal().pending(), 1);
+    assert!(fs::read_to_string(&journal_file).unwrap().contains("GONE"));
+    let _ = fs::remove_file(journal_file);
+}